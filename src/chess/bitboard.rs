@@ -0,0 +1,239 @@
+use crate::chess::{Board, Piece, PieceColor, PieceType};
+use crate::chess::zobrist;
+
+/**
+ * A set of squares, one bit per square.
+ * Bit index is row * 8 + col, using the same coordinates as Board.board (row 0 is the 8th rank).
+ */
+pub(crate) type Bitboard = u64;
+
+// ray directions as (row, col) offsets; the first four increase the square index, the last four decrease it
+const DIRECTIONS: [(i8, i8); 8] = [(1, 0), (0, 1), (1, 1), (1, -1), (-1, 0), (0, -1), (-1, -1), (-1, 1)];
+pub(crate) const SOUTH: usize = 0;
+pub(crate) const EAST: usize = 1;
+pub(crate) const SOUTH_EAST: usize = 2;
+pub(crate) const SOUTH_WEST: usize = 3;
+pub(crate) const NORTH: usize = 4;
+pub(crate) const WEST: usize = 5;
+pub(crate) const NORTH_WEST: usize = 6;
+pub(crate) const NORTH_EAST: usize = 7;
+
+const KNIGHT_JUMPS: [(i8, i8); 8] = [(1, 2), (2, 1), (-1, 2), (-2, 1), (1, -2), (2, -1), (-1, -2), (-2, -1)];
+
+const fn leaper_attacks(offsets: &[(i8, i8)]) -> [Bitboard; 64] {
+    let mut table = [0; 64];
+    let mut sq = 0;
+    while sq < 64 {
+        let (row, col) = ((sq / 8) as i8, (sq % 8) as i8);
+        let mut i = 0;
+        while i < offsets.len() {
+            let (r, c) = (row + offsets[i].0, col + offsets[i].1);
+            if r >= 0 && r < 8 && c >= 0 && c < 8 {
+                table[sq] |= 1 << (r * 8 + c);
+            }
+            i += 1;
+        }
+        sq += 1;
+    }
+    table
+}
+
+const fn ray_table() -> [[Bitboard; 64]; 8] {
+    let mut table = [[0; 64]; 8];
+    let mut dir = 0;
+    while dir < 8 {
+        let mut sq = 0;
+        while sq < 64 {
+            let (mut r, mut c) = ((sq / 8) as i8 + DIRECTIONS[dir].0, (sq % 8) as i8 + DIRECTIONS[dir].1);
+            while r >= 0 && r < 8 && c >= 0 && c < 8 {
+                table[dir][sq] |= 1 << (r * 8 + c);
+                r += DIRECTIONS[dir].0;
+                c += DIRECTIONS[dir].1;
+            }
+            sq += 1;
+        }
+        dir += 1;
+    }
+    table
+}
+
+const KING_OFFSETS: [(i8, i8); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+pub(crate) const KNIGHT_ATTACKS: [Bitboard; 64] = leaper_attacks(&KNIGHT_JUMPS);
+pub(crate) const KING_ATTACKS: [Bitboard; 64] = leaper_attacks(&KING_OFFSETS);
+// squares attacked by a pawn of the given color (indexed by PieceColor::index) standing on a square
+pub(crate) const PAWN_ATTACKS: [[Bitboard; 64]; 2] = [leaper_attacks(&[(-1, -1), (-1, 1)]), leaper_attacks(&[(1, -1), (1, 1)])];
+const RAYS: [[Bitboard; 64]; 8] = ray_table();
+
+pub(crate) fn square(row: u8, col: u8) -> usize {
+    row as usize * 8 + col as usize
+}
+
+pub(crate) fn coords(square: usize) -> (u8, u8) {
+    ((square / 8) as u8, (square % 8) as u8)
+}
+
+/**
+ * Iterate over the squares of a bitboard, from the lowest index to the highest.
+ */
+pub(crate) fn squares(mut bitboard: Bitboard) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        if bitboard == 0 {
+            return None;
+        }
+        let sq = bitboard.trailing_zeros() as usize;
+        bitboard &= bitboard - 1;
+        Some(sq)
+    })
+}
+
+/**
+ * First occupied square met when moving from square in the given direction, if any.
+ */
+pub(crate) fn first_blocker(dir: usize, square: usize, occupied: Bitboard) -> Option<usize> {
+    let blockers = RAYS[dir][square] & occupied;
+    if blockers == 0 {
+        None
+    } else if dir < 4 {
+        Some(blockers.trailing_zeros() as usize)
+    } else {
+        Some(63 - blockers.leading_zeros() as usize)
+    }
+}
+
+/**
+ * Squares reached by a slider moving from square in the given direction, up to and including the first blocker.
+ */
+fn ray_attacks(dir: usize, square: usize, occupied: Bitboard) -> Bitboard {
+    match first_blocker(dir, square, occupied) {
+        Some(blocker) => RAYS[dir][square] ^ RAYS[dir][blocker],
+        None => RAYS[dir][square],
+    }
+}
+
+pub(crate) fn rook_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    [SOUTH, EAST, NORTH, WEST].iter().fold(0, |acc, dir| acc | ray_attacks(*dir, square, occupied))
+}
+
+pub(crate) fn bishop_attacks(square: usize, occupied: Bitboard) -> Bitboard {
+    [SOUTH_EAST, SOUTH_WEST, NORTH_WEST, NORTH_EAST].iter().fold(0, |acc, dir| acc | ray_attacks(*dir, square, occupied))
+}
+
+impl Board {
+    pub(crate) fn occupied(&self) -> Bitboard {
+        self.occupancy[0] | self.occupancy[1]
+    }
+
+    pub(crate) fn pieces_of(&self, color: PieceColor, piece_type: PieceType) -> Bitboard {
+        self.bitboards[color.index()][piece_type.index()]
+    }
+
+    /**
+     * Pieces of the opponent of color attacking the square.
+     * Sliders see through the king of color, so that it cannot step back along the attacking line.
+     */
+    pub(crate) fn attackers(&self, square: usize, color: &PieceColor) -> Bitboard {
+        let enemy = color.opposite();
+        let occupied = self.occupied() & !self.pieces_of(*color, PieceType::King);
+        let queens = self.pieces_of(enemy, PieceType::Queen);
+        (KNIGHT_ATTACKS[square] & self.pieces_of(enemy, PieceType::Knight))
+            | (rook_attacks(square, occupied) & (self.pieces_of(enemy, PieceType::Rook) | queens))
+            | (bishop_attacks(square, occupied) & (self.pieces_of(enemy, PieceType::Bishop) | queens))
+            | (KING_ATTACKS[square] & self.pieces_of(enemy, PieceType::King))
+            | (PAWN_ATTACKS[color.index()][square] & self.pieces_of(enemy, PieceType::Pawn))
+    }
+
+    /**
+     * Put a piece (or nothing) on a square, keeping the bitboards and the hash in sync with Board.board.
+     * Whatever was on the square before is removed.
+     */
+    pub(crate) fn set_square(&mut self, row: u8, col: u8, piece: Option<Piece>) {
+        self.take_square(row, col);
+        if let Some(piece) = piece {
+            let bit = 1 << square(row, col);
+            self.bitboards[piece.color.index()][piece.piece_type.index()] |= bit;
+            self.occupancy[piece.color.index()] |= bit;
+            self.hash ^= zobrist::piece_key(piece.color, piece.piece_type, square(row, col));
+            self.board[row as usize][col as usize] = Some(piece);
+        }
+    }
+
+    pub(crate) fn take_square(&mut self, row: u8, col: u8) -> Option<Piece> {
+        let piece = self.board[row as usize][col as usize].take();
+        if let Some(piece) = &piece {
+            let bit: Bitboard = 1 << square(row, col);
+            self.bitboards[piece.color.index()][piece.piece_type.index()] &= !bit;
+            self.occupancy[piece.color.index()] &= !bit;
+            self.hash ^= zobrist::piece_key(piece.color, piece.piece_type, square(row, col));
+        }
+        piece
+    }
+
+    /**
+     * Rebuild every bitboard from Board.board.
+     */
+    pub(crate) fn init_bitboards(&mut self) {
+        self.bitboards = [[0; 6]; 2];
+        self.occupancy = [0; 2];
+        for row in 0..Board::ROWS {
+            for col in 0..Board::COLS {
+                if let Some(piece) = self.board[row][col] {
+                    self.set_square(row as u8, col as u8, Some(piece));
+                }
+            }
+        }
+    }
+
+    /**
+     * Pieces of color pinned to their king, one set per line of the pin: row, column, back diagonal (a8-h1 direction) and forward diagonal (a1-h8 direction).
+     * A piece is pinned when it is the first piece met from the king along a line and the next one is an enemy slider moving along that line.
+     */
+    pub(crate) fn pinned_lines(&self, color: PieceColor) -> [Bitboard; 4] {
+        let mut pins = [0; 4];
+        let king = match squares(self.pieces_of(color, PieceType::King)).next() {
+            Some(king) => king,
+            None => return pins,
+        };
+        let enemy = color.opposite();
+        let queens = self.pieces_of(enemy, PieceType::Queen);
+        let occupied = self.occupied();
+        for dir in 0..8 {
+            let (line, sliders) = match dir {
+                EAST | WEST => (0, self.pieces_of(enemy, PieceType::Rook) | queens),
+                SOUTH | NORTH => (1, self.pieces_of(enemy, PieceType::Rook) | queens),
+                SOUTH_EAST | NORTH_WEST => (2, self.pieces_of(enemy, PieceType::Bishop) | queens),
+                _ => (3, self.pieces_of(enemy, PieceType::Bishop) | queens),
+            };
+            if let Some(blocker) = first_blocker(dir, king, occupied) {
+                if self.occupancy[color.index()] & 1 << blocker == 0 {
+                    continue;
+                }
+                if let Some(pinner) = first_blocker(dir, blocker, occupied) {
+                    if sliders & 1 << pinner != 0 {
+                        pins[line] |= 1 << blocker;
+                    }
+                }
+            }
+        }
+        pins
+    }
+
+    /**
+     * Whether capturing en passant from from to to leaves the king of color safe.
+     * Two pawns leave the same row at once, so a rook or queen can be uncovered along it (e.g. "3k4/8/8/K1Pp3r/8/8/8/8 w - d6"),
+     * and the captured pawn may be the one giving check: the whole position after the capture is checked instead of relying on pins.
+     */
+    pub(crate) fn en_passant_is_legal(&self, from: (u8, u8), to: (u8, u8), color: PieceColor) -> bool {
+        let king = match squares(self.pieces_of(color, PieceType::King)).next() {
+            Some(king) => king,
+            None => return true,
+        };
+        let enemy = color.opposite();
+        let captured: Bitboard = 1 << square(from.0, to.1);
+        let occupied = (self.occupied() & !(1 << square(from.0, from.1)) & !captured) | 1 << square(to.0, to.1);
+        let queens = self.pieces_of(enemy, PieceType::Queen);
+        (KNIGHT_ATTACKS[king] & self.pieces_of(enemy, PieceType::Knight)) == 0
+            && (rook_attacks(king, occupied) & (self.pieces_of(enemy, PieceType::Rook) | queens)) == 0
+            && (bishop_attacks(king, occupied) & (self.pieces_of(enemy, PieceType::Bishop) | queens)) == 0
+            && (PAWN_ATTACKS[color.index()][king] & self.pieces_of(enemy, PieceType::Pawn) & !captured) == 0
+    }
+}
@@ -1,10 +1,9 @@
-use std::collections::HashMap;
-
 use gtk::glib::object::Cast;
 use gtk::{gdk_pixbuf, Button, Grid, Picture};
 use gtk::prelude::{GridExt, ButtonExt};
 
-use crate::chess::{HistoryData, Board, PieceColor, Piece, Move, PieceType, GameOutcome, DrawType};
+use crate::chess::{HistoryData, Board, PieceColor, Piece, Move, MoveKind, PieceType, GameOutcome, DrawType, FenError, FenField};
+use crate::chess::bitboard;
 
 impl HistoryData {
     fn new(board: &Board, from: (u8, u8), mv: &Move) -> Self {
@@ -20,6 +19,7 @@ impl HistoryData {
             halfmove_clock: board.halfmove_clock,
             fullmove_number: board.fullmove_number,
             is_check: board.is_check,
+            hash: board.hash,
         }
     }
 }
@@ -32,65 +32,51 @@ impl Board {
         Self::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").unwrap()
     }
 
-    pub fn from_fen(fen: &str) -> Result<Self, &str> {
-        let mut board: Vec<Vec<Option<Piece>>> = vec![vec![None; Board::COLS]; Board::ROWS];
-        let (mut row, mut col) : (usize, usize) = (0, 0);
-        let parts: Vec<&str> = fen.split(' ').collect();
-        // fill the board
-        for c in parts[0].chars() {
-            match c {
-                '1'..='8' => {
-                    let increment = match c.to_digit(10) {
-                        Some(d) => d,
-                        None => return Err("from_fen: error getting digit value.")
-                    } as usize;
-                    col += increment;
-                },
-                '/' => {
-                    col = 0;
-                    row += 1;
-                },
-                _ => {
-                    let piece = match Piece::from_fen(c, row as u8, col as u8) {
-                        Some(piece) => piece,
-                        None => return Err("from_fen: error getting piece.")
-                    };
-                    board[row][col] = Some(piece);
-                    col += 1;                    
-                }
-            }
+    pub fn from_fen(fen: &str) -> Result<Self, FenError> {
+        Board::parse_fen(fen, false)
+    }
+
+    /**
+     * Same as from_fen, but also accepts EPD-style records which only carry the first four fields.
+     * Missing clocks default to "0 1" and any trailing EPD operations are ignored.
+     */
+    pub fn from_fen_lenient(fen: &str) -> Result<Self, FenError> {
+        Board::parse_fen(fen, true)
+    }
+
+    fn parse_fen(fen: &str, lenient: bool) -> Result<Self, FenError> {
+        let parts: Vec<&str> = fen.split_whitespace().collect();
+        let has_clocks = parts.len() > 4 && parts[4].chars().all(|c| c.is_ascii_digit());
+        if parts.len() < 4 || (!lenient && parts.len() != 6) || (lenient && has_clocks && parts.len() < 6) {
+            return Err(FenError::FieldCount(parts.len()));
         }
+
+        let board = Board::parse_placement(parts[0])?;
+
         // pick turn
         let turn = match parts[1] {
             "w" => PieceColor::White,
             "b" => PieceColor::Black,
-            _  => return Err("from_fen: error getting turn")
+            _  => return Err(FenError::InvalidField(FenField::SideToMove, parts[1].to_string()))
         };
 
         // pick castling rights
-        let (wq_castle, wk_castle, bq_castle, bk_castle) = (
-            parts[2].contains('Q'),
-            parts[2].contains('K'),
-            parts[2].contains('q'),
-            parts[2].contains('k')
-        );
-        
-        let en_passant = match parts[3] {
-            "-" => None,
-            _ => match Board::coords_to_u8(parts[3]) {
-                Ok(pair) => Some(pair),
-                Err(_) => return Err("from_fen: error getting en passant move")
-            }
-        };
+        let (wq_castle, wk_castle, bq_castle, bk_castle) = Board::parse_castling(parts[2], &board)?;
 
-        let halfmove_clock: u8 = match parts[4].parse::<u8>() {
-            Ok(v) => v,
-            Err(_) => return Err("from_fen: error getting halfmove clock")
-        };
+        let en_passant = Board::parse_en_passant(parts[3], turn, &board)?;
 
-        let fullmove_number: u16 = match parts[5].parse::<u16>() {
-            Ok(v) => v,
-            Err(_) => return Err("from_fen: error getting fullmove number")
+        let (halfmove_clock, fullmove_number) = if !lenient || has_clocks {
+            let halfmove_clock: u8 = match parts[4].parse::<u8>() {
+                Ok(v) => v,
+                Err(_) => return Err(FenError::InvalidField(FenField::HalfmoveClock, parts[4].to_string()))
+            };
+            let fullmove_number: u16 = match parts[5].parse::<u16>() {
+                Ok(v) if v > 0 => v,
+                _ => return Err(FenError::InvalidField(FenField::FullmoveNumber, parts[5].to_string()))
+            };
+            (halfmove_clock, fullmove_number)
+        } else {
+            (0, 1)
         };
 
         let mut board = Self {
@@ -105,30 +91,152 @@ impl Board {
             fullmove_number,
             is_check: false,
             history: Vec::new(),
-            board_config_counts: HashMap::new(),
+            hash: 0,
             white_king_pos: (9, 9),
             black_king_pos: (9, 9),
             prefetched_moves: None,
+            bitboards: [[0; 6]; 2],
+            occupancy: [0; 2],
         };
-        board.is_check = !board.get_checking_pieces(&board.turn, true).is_empty();
+        board.init_bitboards();
+        board.hash = board.compute_hash();
         board.white_king_pos = board.king_coords(&PieceColor::White);
         board.black_king_pos = board.king_coords(&PieceColor::Black);
+        if !board.get_checking_pieces(&board.turn.opposite(), true).is_empty() {
+            return Err(FenError::OpponentInCheck);
+        }
+        board.is_check = !board.get_checking_pieces(&board.turn, true).is_empty();
         board.prefetched_moves = Some(board.get_all_possible_moves());
 
         Ok(board)
     }
 
-    fn get_all_possible_moves(&self) -> Vec<(u8, u8, Move)> {
-        let mut moves = Vec::new();
-        let checking_pieces = if self.is_check {self.get_checking_pieces(&self.turn, false)} else {Vec::new()};
-        for row in 0..Board::ROWS {
-            for col in 0..Board::COLS {
-                if let Some(piece) = &self.board[row][col] {
-                    if piece.color == self.turn {
-                        let piece_moves = piece.generate_moves(self, &checking_pieces);
-                        moves.extend(piece_moves.iter().map(|mv| (row as u8, col as u8, *mv)));
+    fn parse_placement(placement: &str) -> Result<Vec<Vec<Option<Piece>>>, FenError> {
+        let mut board: Vec<Vec<Option<Piece>>> = vec![vec![None; Board::COLS]; Board::ROWS];
+        let ranks: Vec<&str> = placement.split('/').collect();
+        if ranks.len() != Board::ROWS {
+            return Err(FenError::RankCount(ranks.len()));
+        }
+        let mut index = 0;
+        let (mut white_kings, mut black_kings) = (0, 0);
+        for (row, rank) in ranks.iter().enumerate() {
+            let rank_number = (Board::ROWS - row) as u8;
+            let mut col = 0;
+            let mut previous_digit = false;
+            for c in rank.chars() {
+                match c {
+                    '1'..='8' => {
+                        if previous_digit {
+                            return Err(FenError::ConsecutiveDigits { rank: rank_number, index });
+                        }
+                        previous_digit = true;
+                        col += c.to_digit(10).unwrap() as usize;
+                    },
+                    _ => {
+                        previous_digit = false;
+                        let piece = match Piece::from_fen(c, row as u8, col as u8) {
+                            Some(piece) => piece,
+                            None => return Err(FenError::InvalidPiece { index, symbol: c })
+                        };
+                        if col < Board::COLS {
+                            if piece.piece_type == PieceType::Pawn && (row == 0 || row == Board::ROWS-1) {
+                                return Err(FenError::PawnOnBackRank(Board::u8_coords_to_str((row as u8, col as u8))));
+                            }
+                            if piece.piece_type == PieceType::King {
+                                match piece.color {
+                                    PieceColor::White => white_kings += 1,
+                                    PieceColor::Black => black_kings += 1,
+                                };
+                            }
+                            board[row][col] = Some(piece);
+                        }
+                        col += 1;
                     }
                 }
+                if col > Board::COLS {
+                    return Err(FenError::RankLength { rank: rank_number, squares: col });
+                }
+                index += 1;
+            }
+            if col != Board::COLS {
+                return Err(FenError::RankLength { rank: rank_number, squares: col });
+            }
+            // account for the '/' separator
+            index += 1;
+        }
+        if white_kings != 1 {
+            return Err(FenError::KingCount(PieceColor::White, white_kings));
+        }
+        if black_kings != 1 {
+            return Err(FenError::KingCount(PieceColor::Black, black_kings));
+        }
+        Ok(board)
+    }
+
+    fn parse_castling(castling: &str, board: &[Vec<Option<Piece>>]) -> Result<(bool, bool, bool, bool), FenError> {
+        let (mut wq_castle, mut wk_castle, mut bq_castle, mut bk_castle) = (false, false, false, false);
+        if castling == "-" {
+            return Ok((wq_castle, wk_castle, bq_castle, bk_castle));
+        }
+        for c in castling.chars() {
+            let (right, king, rook) = match c {
+                'K' => (&mut wk_castle, (7, 4, 'K'), (7, 7, 'R')),
+                'Q' => (&mut wq_castle, (7, 4, 'K'), (7, 0, 'R')),
+                'k' => (&mut bk_castle, (0, 4, 'k'), (0, 7, 'r')),
+                'q' => (&mut bq_castle, (0, 4, 'k'), (0, 0, 'r')),
+                _ => return Err(FenError::InvalidField(FenField::Castling, castling.to_string()))
+            };
+            if *right {
+                return Err(FenError::InvalidField(FenField::Castling, castling.to_string()));
+            }
+            let in_place = |(r, c, symbol): (usize, usize, char)| board[r][c].map(|piece| piece.to_fen()) == Some(symbol);
+            if !in_place(king) || !in_place(rook) {
+                return Err(FenError::CastlingRights(c));
+            }
+            *right = true;
+        }
+        Ok((wq_castle, wk_castle, bq_castle, bk_castle))
+    }
+
+    fn parse_en_passant(en_passant: &str, turn: PieceColor, board: &[Vec<Option<Piece>>]) -> Result<Option<(u8, u8)>, FenError> {
+        if en_passant == "-" {
+            return Ok(None);
+        }
+        let (row, col) = match Board::coords_to_u8(en_passant) {
+            Ok(pair) => pair,
+            Err(_) => return Err(FenError::InvalidField(FenField::EnPassant, en_passant.to_string()))
+        };
+        // the target square lies behind a pawn of the side which just moved, and both it and the square it was crossed from are empty
+        let (target_row, pawn_row, origin_row, pawn) = match turn {
+            PieceColor::White => (2, 3, 1, 'p'),
+            PieceColor::Black => (5, 4, 6, 'P'),
+        };
+        let (row, col) = (row as usize, col as usize);
+        if row != target_row
+           || board[row][col].is_some()
+           || board[origin_row][col].is_some()
+           || board[pawn_row][col].map(|piece| piece.to_fen()) != Some(pawn) {
+            return Err(FenError::EnPassantSquare(en_passant.to_string()));
+        }
+        Ok(Some((row as u8, col as u8)))
+    }
+
+    pub(crate) fn get_all_possible_moves(&self) -> Vec<(u8, u8, Move)> {
+        self.get_moves(MoveKind::All)
+    }
+
+    /**
+     * Legal moves of the given kind for the side to move, so that the search can try the captures before generating the quiet moves.
+     */
+    pub(crate) fn get_moves(&self, kind: MoveKind) -> Vec<(u8, u8, Move)> {
+        let mut moves = Vec::new();
+        let checking_pieces = if self.is_check {self.get_checking_pieces(&self.turn, false)} else {Vec::new()};
+        let pins = self.pinned_lines(self.turn);
+        for sq in bitboard::squares(self.occupancy[self.turn.index()]) {
+            let (row, col) = bitboard::coords(sq);
+            if let Some(piece) = &self.board[row as usize][col as usize] {
+                let piece_moves = piece.generate_moves(self, &checking_pieces, &pins, kind);
+                moves.extend(piece_moves.iter().map(|mv| (row, col, *mv)));
             }
         }
         moves
@@ -159,215 +267,76 @@ impl Board {
         res
     }
 
-    pub(crate) fn get_attacking_pieces(&self, row: usize, col: usize, color: &PieceColor, early_stop: bool) -> Vec<(u8, u8)> {
-        let mut res = Vec::new();
-        let knight_moves = vec![(1, 2), (2, 1), (-1, 2), (-2, 1), (1, -2), (2, -1), (-1, -2), (-2, -1)];
-        
-        for (dr, dc) in knight_moves {
-            let r = (row as i8 + dr) as usize;
-            let c = (col as i8 + dc) as usize;
-            if r < 8 && c < 8 {
-                if let Some(piece) = &self.board[r][c] {
-                    if piece.piece_type == PieceType::Knight && piece.color != *color {
-                        res.push((r as u8, c as u8));
-                        if early_stop {
-                            return res;
-                        }
-                    }
-                }
-            }
-        }
-
-        let king_mask = PieceType::King.id() | color.id();
-        let directions = [(1, 0), (0, 1), (-1, 0), (0, -1)];
-        for (dr, dc) in directions.iter() {
-            let (mut r, mut c) = ((row as i8 + dr) as usize, (col as i8 + dc) as usize);
-            let mut row_col_mask = PieceType::Queen.id() | PieceType::Rook.id() | PieceType::King.id() | color.opposite().id();
-            while r < Board::ROWS && c < Board::COLS {
-                if let Some(piece) = &self.board[r][c] {
-                    if piece.matches(row_col_mask) {
-                        res.push((r as u8, c as u8));
-                        if early_stop {
-                            return res;
-                        } else {
-                            break;
-                        }
-                    } else if !piece.matches(king_mask) {
-                        break;
-                    }
-                }
-                r = (r as i8 + dr) as usize;
-                c = (c as i8 + dc) as usize;
-                row_col_mask &= !PieceType::King.id();
-            }
-        }
-
-        let directions = [(1, 1), (-1, -1), (1, -1), (-1, 1)];
-        for (dr, dc) in directions.iter() {
-            let (mut r, mut c) = ((row as i8 + dr) as usize, (col as i8 + dc) as usize);
-            let mut diag_mask = PieceType::Queen.id() | PieceType::Bishop.id() | PieceType::King.id() | color.opposite().id();
-            while r < Board::ROWS && c < Board::COLS {
-                if let Some(piece) = &self.board[r][c] {
-                    if piece.matches(diag_mask) {
-                        res.push((r as u8, c as u8));
-                        if early_stop {
-                            return res;
-                        } else {
-                            break;
-                        }
-                    } else if !piece.matches(king_mask) {
-                        break;
-                    }
-                }
-                r = (r as i8 + dr) as usize;
-                c = (c as i8 + dc) as usize;
-                diag_mask &= !PieceType::King.id();
-            }
-        }
-
-        let directions = match color {
-            PieceColor::White => [(-1, -1), (-1, 1)],
-            PieceColor::Black => [(1, -1), (1, 1)],
+    pub fn to_fen(&self) -> String {
+        let turn = match self.turn {
+            PieceColor::White => "w",
+            PieceColor::Black => "b",
         };
-        for (dr, dc) in directions.iter() {
-            let r = (row as i8 + dr) as usize;
-            let c = (col as i8 + dc) as usize;
-            if r < 8 && c < 8 {
-                if let Some(piece) = &self.board[r][c] {
-                    if piece.piece_type == PieceType::Pawn && piece.color != *color {
-                        res.push((r as u8, c as u8));
-                        if early_stop {
-                            return res;
-                        }
-                    }
-                }
-            }
+        let mut castling = String::new();
+        if self.wk_castle {
+            castling.push('K');
         }
-        res
-    }
-
-    pub(crate)fn row_pin(&self, row: usize, col: usize, mask1: u8, mask2: u8) -> bool {
-        let (mut found1, mut found2) = (false, false);
-        let mut c = col as i32 - 1;
-        while c >= 0 {
-            if let Some(piece) = &self.board[row][c as usize] {
-                found1 |= piece.matches(mask1);
-                found2 |= piece.matches(mask2);
-                break;
-            } else {
-                c -= 1;
-            }
+        if self.wq_castle {
+            castling.push('Q');
         }
-        let mut c = col+1;
-        while c < Board::COLS {
-            if let Some(piece) = &self.board[row][c] {
-                found1 |= piece.matches(mask1);
-                found2 |= piece.matches(mask2);
-                break;
-            } else {
-                c += 1;
-            }
+        if self.bk_castle {
+            castling.push('k');
         }
-        found1 && found2
-    }
-
-    pub(crate)fn col_pin(&self, row: usize, col: usize, mask1: u8, mask2: u8) -> bool {
-        let (mut found1, mut found2) = (false, false);
-        let mut r = row as i32 - 1;
-        while r >= 0 {
-            if let Some(piece) = &self.board[r as usize][col] {
-                found1 |= piece.matches(mask1);
-                found2 |= piece.matches(mask2);
-                break;
-            } else {
-                r -= 1;
-            }
+        if self.bq_castle {
+            castling.push('q');
         }
-        let mut r = row+1;
-        while r < Board::ROWS {
-            if let Some(piece) = &self.board[r][col] {
-                found1 |= piece.matches(mask1);
-                found2 |= piece.matches(mask2);
-                break;
-            } else {
-                r += 1;
-            }
+        if castling.is_empty() {
+            castling.push('-');
         }
-        found1 && found2
+        let en_passant = match self.en_passant {
+            Some(coords) => Board::u8_coords_to_str(coords),
+            None => "-".to_string(),
+        };
+        format!("{} {} {} {} {} {}", self.to_fen_board(), turn, castling, en_passant, self.halfmove_clock, self.fullmove_number)
     }
 
-    pub(crate)fn back_diag_pin(&self, row: usize, col: usize, mask1: u8, mask2: u8) -> bool {
-        let (mut found1, mut found2) = (false, false);
-        let mut r = row as i32 - 1;
-        let mut c = col as i32 - 1;
-        while r >= 0 && c >= 0 {
-            if let Some(piece) = &self.board[r as usize][c as usize] {
-                found1 |= piece.matches(mask1);
-                found2 |= piece.matches(mask2);
-                break;
-            } else {
-                r -= 1;
-                c -= 1;
-            }
-        }
-        let mut r = row+1;
-        let mut c = col+1;
-        while r < Board::ROWS && c < Board::COLS {
-            if let Some(piece) = &self.board[r][c] {
-                found1 |= piece.matches(mask1);
-                found2 |= piece.matches(mask2);
-                break;
-            } else {
-                r += 1;
-                c += 1;
+    /**
+     * Text picture of the board with rank and file labels, White at the bottom unless flipped.
+     * Pieces are chess symbols, or FEN letters when unicode is false.
+     */
+    pub fn diagram(&self, unicode: bool, flipped: bool) -> String {
+        let order = |i: usize| if flipped {Board::ROWS - 1 - i} else {i};
+        let files: String = (0..Board::COLS).map(|i| format!(" {}", (b'a' + order(i) as u8) as char)).collect();
+        let mut diagram = String::new();
+        for i in 0..Board::ROWS {
+            let row = order(i);
+            diagram.push_str(&format!("{} ", Board::ROWS - row));
+            for j in 0..Board::COLS {
+                let symbol = match &self.board[row][order(j)] {
+                    Some(piece) if unicode => piece.symbol(),
+                    Some(piece) => piece.to_fen(),
+                    None => if unicode {'·'} else {'.'},
+                };
+                diagram.push(symbol);
+                diagram.push(' ');
             }
+            diagram.push('\n');
         }
-        found1 && found2
+        diagram.push(' ');
+        diagram.push_str(&files);
+        diagram.push('\n');
+        diagram
     }
 
-    pub(crate) fn forward_diag_pin(&self, row: usize, col: usize, mask1: u8, mask2: u8) -> bool {
-        let (mut found1, mut found2) = (false, false);
-        let mut r = row as i32 - 1;
-        let mut c = col+1;
-        while r >= 0 && c < Board::COLS {
-            if let Some(piece) = &self.board[r as usize][c] {
-                found1 |= piece.matches(mask1);
-                found2 |= piece.matches(mask2);
-                break;
-            } else {
-                r -= 1;
-                c += 1;
-            }
-        }
-        let mut r = row+1;
-        let mut c = col as i32 - 1;
-        while r < Board::ROWS && c >= 0 {
-            if let Some(piece) = &self.board[r][c as usize] {
-                found1 |= piece.matches(mask1);
-                found2 |= piece.matches(mask2);
-                break;
-            } else {
-                r += 1;
-                c -= 1;
-            }
+    pub(crate) fn get_attacking_pieces(&self, row: usize, col: usize, color: &PieceColor, early_stop: bool) -> Vec<(u8, u8)> {
+        let attackers = bitboard::squares(self.attackers(bitboard::square(row as u8, col as u8), color)).map(bitboard::coords);
+        if early_stop {
+            attackers.take(1).collect()
+        } else {
+            attackers.collect()
         }
-        found1 && found2
     }
 
-    fn material_count(&self, potential: bool) -> (usize, usize) {
-        let mut white = 0;
-        let mut black = 0;
-        for row in 0..Board::ROWS {
-            for col in 0..Board::COLS {
-                if let Some(piece) = &self.board[row][col] {
-                    match piece.color {
-                        PieceColor::White => white += if potential {piece.get_potential_value()} else {piece.get_value()},
-                        PieceColor::Black => black += if potential {piece.get_potential_value()} else {piece.get_value()},
-                    }
-                }
-            }
-        }
-        (white, black)
+    /**
+     * Whether the square is attacked by the opponent of color, without building the list of attackers.
+     */
+    pub(crate) fn is_attacked(&self, row: usize, col: usize, color: &PieceColor) -> bool {
+        self.attackers(bitboard::square(row as u8, col as u8), color) != 0
     }
 
     // fn same_row(&self, row: usize, mask1: u8, mask2: u8) -> bool {
@@ -454,7 +423,7 @@ impl Board {
         }
     }
 
-    fn coords_to_u8(coords: &str) -> Result<(u8, u8), &str> {
+    pub(crate) fn coords_to_u8(coords: &str) -> Result<(u8, u8), &str> {
         if coords.len() != 2 {
             return Err("coords_to_u8: invalid length");
         }
@@ -463,23 +432,17 @@ impl Board {
             _ => return Err("coords_to_u8: invalid column")
         };
         let row = match coords.chars().nth(1).unwrap() {
-            '1'..='8' => coords.chars().nth(1).unwrap() as u8 - b'1',
+            '1'..='8' => b'8' - coords.chars().nth(1).unwrap() as u8,
             _ => return Err("coords_to_u8: invalid row")
         };
         Ok((row, col))
     }
 
     pub(crate) fn king_coords(&self, color: &PieceColor) -> (u8, u8) {
-        for row in 0..Board::ROWS {
-            for col in 0..Board::COLS {
-                if let Some(piece) = &self.board[row][col] {
-                    if piece.piece_type == PieceType::King && piece.color == *color {
-                        return (row as u8, col as u8);
-                    }
-                }
-            }
+        match bitboard::squares(self.pieces_of(*color, PieceType::King)).next() {
+            Some(sq) => bitboard::coords(sq),
+            None => (9, 9),
         }
-        (9, 9)
     }
 
     pub(crate) fn get_checking_pieces(&self, color: &PieceColor, early_stop: bool) -> Vec<(u8, u8)> {
@@ -490,76 +453,106 @@ impl Board {
         self.get_attacking_pieces(king_position.0 as usize, king_position.1 as usize, color, early_stop)
     }
 
-    pub fn play_random_move(&mut self) -> Option<GameOutcome> {
-        let mut moves = match self.prefetched_moves.take() {
-            Some(moves) => moves,
+    /**
+     * Legal moves of the side to move, as (starting row, starting column, move).
+     */
+    pub fn legal_moves(&self) -> Vec<(u8, u8, Move)> {
+        match &self.prefetched_moves {
+            Some(moves) => moves.clone(),
             None => self.get_all_possible_moves(),
-        };
-        println!("{} moves available", moves.len());
-        let threefold_repetition;
-        if self.is_check {
-            loop {
-                if moves.len() == 0 {
-                    return Some(GameOutcome::DebugError("Checkmate, but should not find it here!".to_string()));
-                }
-                let random_idx = rand::random::<usize>() % moves.len();
-                let (row, col, mv) = moves.swap_remove(random_idx);
-                threefold_repetition = self.play_move((row, col), &mv);
+        }
+    }
 
-                if self.get_checking_pieces(&self.turn.opposite(), true).len() > 0 {
-                    return Some(GameOutcome::DebugError("Should not find checking pieces here".to_string()));
-                    self.rollback_move();
-                } else {
-                    break;
-                }
-            }
-        } else {
-            let random_idx = rand::random::<usize>() % moves.len();
-            let (row, col, mv) = moves.swap_remove(random_idx);
-            
-            threefold_repetition = self.play_move((row, col), &mv);
-            if self.is_check {
-                println!("CHECK");
+    pub(crate) fn find_move(&self, from: (u8, u8), to: (u8, u8), promotion: Option<PieceType>) -> Option<Move> {
+        self.legal_moves().into_iter()
+            .find(|(row, col, mv)| (*row, *col) == from && mv.to == to && mv.promotion == promotion)
+            .map(|(_, _, mv)| mv)
+    }
+
+    /**
+     * Squares the piece standing on from can legally move to (a promotion square is listed once).
+     */
+    pub(crate) fn legal_targets(&self, from: (u8, u8)) -> Vec<(u8, u8)> {
+        let mut targets: Vec<(u8, u8)> = Vec::new();
+        for (row, col, mv) in self.legal_moves() {
+            if (row, col) == from && !targets.contains(&mv.to) {
+                targets.push(mv.to);
             }
         }
-        let next_moves = self.get_all_possible_moves();
-        if next_moves.is_empty() {
+        targets
+    }
+
+    pub(crate) fn is_promotion(&self, from: (u8, u8), to: (u8, u8)) -> bool {
+        self.find_move(from, to, Some(PieceType::Queen)).is_some()
+    }
+
+    pub(crate) fn color_at(&self, square: (u8, u8)) -> Option<PieceColor> {
+        self.board[square.0 as usize][square.1 as usize].map(|piece| piece.color)
+    }
+
+    /**
+     * A legal move picked at random, None when the game is over.
+     */
+    pub fn random_move(&self) -> Option<(u8, u8, Move)> {
+        let moves = self.legal_moves();
+        (!moves.is_empty()).then(|| moves[rand::random::<usize>() % moves.len()])
+    }
+
+    /**
+     * How the game ended in the current position: checkmate, stalemate or one of the draws of the draw module; None while it goes on.
+     */
+    pub fn outcome(&self) -> Option<GameOutcome> {
+        if self.legal_moves().is_empty() {
             if self.is_check {
                 Some(GameOutcome::Checkmate(self.turn))
             } else {
                 Some(GameOutcome::Draw(DrawType::Stalemate))
             }
         } else {
-            self.prefetched_moves = Some(next_moves);
-            let potential_value = self.material_count(true);
-            if threefold_repetition {
-                Some(GameOutcome::Draw(DrawType::ThreefoldRepetition))
-            } else if potential_value.0 < 4 && potential_value.1 < 4 {
-                Some(GameOutcome::Draw(DrawType::InsufficientMaterial))
-            } else if self.halfmove_clock == 100 {
-                Some(GameOutcome::Draw(DrawType::FiftyMoveRule))
-            } else {
-                None
-            }
+            self.draw().map(GameOutcome::Draw)
         }
     }
 
-    fn play_move(&mut self, from: (u8, u8), mv: &Move) -> bool {
+    /**
+     * Check whether the move which was just played ended the game, prefetching the next moves for the following turn.
+     */
+    pub(crate) fn outcome_after_move(&mut self) -> Option<GameOutcome> {
+        self.prefetched_moves = Some(self.get_all_possible_moves());
+        self.outcome()
+    }
+
+    /**
+     * Play a legal move (as returned by find_move) and check whether it ended the game.
+     */
+    pub(crate) fn play_chosen_move(&mut self, from: (u8, u8), mv: &Move) -> Option<GameOutcome> {
+        self.play_move(from, mv);
+        self.outcome_after_move()
+    }
+
+
+    pub(crate) fn play_move(&mut self, from: (u8, u8), mv: &Move) -> bool {
         self.history.push(HistoryData::new(self, (from.0, from.1), mv));
-        let mut piece = self.board[from.0 as usize][from.1 as usize].take().unwrap();
-        println!("{} {:?} {:?} from {} to {} capture={:?} promote={:?}", if mv.castling {"Castling"} else {"Moving"}, piece.color, piece.piece_type, Board::u8_coords_to_str((from.0 as u8, from.1 as u8)), Board::u8_coords_to_str(mv.to), mv.capture, mv.promotion);
+        self.prefetched_moves = None;
+        self.hash ^= self.state_key();
+        let mut piece = self.take_square(from.0, from.1).unwrap();
         
         if mv.castling {
-            let mut rook = self.board[piece.row as usize][if mv.rook_to.unwrap().1 == 5 {7} else {0}].take().unwrap();
+            let mut rook = self.take_square(piece.row, if mv.rook_to.unwrap().1 == 5 {7} else {0}).unwrap();
             rook.move_piece(mv);
-            self.board[mv.rook_to.unwrap().0 as usize][mv.rook_to.unwrap().1 as usize] = Some(rook);
+            self.set_square(mv.rook_to.unwrap().0, mv.rook_to.unwrap().1, Some(rook));
         }
         if let Some(capture) = mv.capture {
-            if piece.piece_type == PieceType::Pawn && (capture.row, capture.col) == self.en_passant.unwrap_or((9, 9)) {
-                self.board[capture.row as usize][capture.col as usize] = None;
+            // en passant: the captured pawn is not on the target square
+            if (capture.row, capture.col) != mv.to {
+                self.take_square(capture.row, capture.col);
             }
         }
-        
+        // after a double push, the square the pawn skipped can be captured en passant by the next move only
+        self.en_passant = if piece.piece_type == PieceType::Pawn && from.0.abs_diff(mv.to.0) == 2 {
+            Some(((from.0 + mv.to.0) / 2, from.1))
+        } else {
+            None
+        };
 
         if piece.piece_type == PieceType::King {
             match piece.color {
@@ -573,29 +566,16 @@ impl Board {
                 },
             };
         } else if piece.piece_type == PieceType::Rook {
-            match piece.color {
-                PieceColor::White => {
-                    if piece.col == 0 {
-                        self.wq_castle = false;
-                    } else if piece.col == 7 {
-                        self.wk_castle = false;
-                    }
-                },
-                PieceColor::Black => {
-                    if piece.col == 0 {
-                        self.bq_castle = false;
-                    } else if piece.col == 7 {
-                        self.bk_castle = false;
-                    }
-                },
-            };
+            self.revoke_castling_rights((piece.row, piece.col));
         }
+        // capturing a rook which never moved also removes the corresponding right
+        self.revoke_castling_rights(mv.to);
 
         piece.move_piece(mv);
-        self.board[mv.to.0 as usize][mv.to.1 as usize] = match mv.promotion {
+        self.set_square(mv.to.0, mv.to.1, match mv.promotion {
             Some(piece_type) => Some(Piece::new(piece_type, piece.color, mv.to.0, mv.to.1)),
             None => Some(piece),
-        };
+        });
         if piece.piece_type == PieceType::King {
             match piece.color {
                 PieceColor::White => self.white_king_pos = mv.to,
@@ -614,28 +594,40 @@ impl Board {
         }
         self.turn = self.turn.opposite();
         self.is_check = !self.get_checking_pieces(&self.turn, true).is_empty();
+        self.hash ^= self.state_key();
 
-        let fen_board = self.to_fen_board();
-        let cnt = *self.board_config_counts.entry(fen_board).and_modify(|v| *v += 1).or_insert(1);
-        return cnt == 3;
+        self.repetitions() >= 3
+    }
+
+    fn revoke_castling_rights(&mut self, square: (u8, u8)) {
+        match square {
+            (7, 0) => self.wq_castle = false,
+            (7, 7) => self.wk_castle = false,
+            (0, 0) => self.bq_castle = false,
+            (0, 7) => self.bk_castle = false,
+            _ => {},
+        };
     }
 
-    fn rollback_move(&mut self) {
-        let fen_board = self.to_fen_board();
-        self.board_config_counts.entry(fen_board).and_modify(|v| *v -= 1);
+    /**
+     * Number of half-moves played on this board, i.e. how many times rollback_move can be called.
+     */
+    pub(crate) fn plies_played(&self) -> usize {
+        self.history.len()
+    }
+
+    pub(crate) fn rollback_move(&mut self) {
         self.turn = self.turn.opposite();
         let history_data = self.history.pop().unwrap();
         let (row, col) = (history_data.starting_row, history_data.starting_col);
         let mv = &history_data.mv;
-        
-        println!("Rollback");
 
         if mv.castling {
             let (r_rook, c_rook) = mv.rook_to.unwrap();
-            let mut rook = self.board[r_rook as usize][c_rook as usize].take().unwrap();
+            let mut rook = self.take_square(r_rook, c_rook).unwrap();
             let c_to = if c_rook == 5 {7} else {0};
             rook.move_piece(&Move::new((r_rook, c_to), None, None));
-            self.board[r_rook as usize][c_to as usize] = Some(rook);
+            self.set_square(r_rook, c_to, Some(rook));
             if c_to == 7 {
                 match self.turn {
                     PieceColor::White => self.wk_castle = true,
@@ -648,16 +640,16 @@ impl Board {
                 };
             }
         }
-        let mut piece = self.board[mv.to.0 as usize][mv.to.1 as usize].take().unwrap();
+        let mut piece = self.take_square(mv.to.0, mv.to.1).unwrap();
         if let Some(capture) = mv.capture {
-            self.board[capture.row as usize][capture.col as usize] = mv.capture;
+            self.set_square(capture.row, capture.col, Some(capture));
         }
         
         if mv.promotion.is_some() {
             piece.piece_type = PieceType::Pawn;
         }
         piece.move_piece(&Move::new((row, col), None, None));
-        self.board[row as usize][col as usize] = Some(piece);
+        self.set_square(row, col, Some(piece));
         if piece.piece_type == PieceType::King {
             match piece.color {
                 PieceColor::White => self.white_king_pos = (row, col),
@@ -673,9 +665,66 @@ impl Board {
         self.halfmove_clock = history_data.halfmove_clock;
         self.fullmove_number = history_data.fullmove_number;
         self.is_check = history_data.is_check;
+        self.hash = history_data.hash;
+        self.prefetched_moves = None;
     }
 
-    fn u8_coords_to_str(coords: (u8, u8)) -> String {
+    pub(crate) fn u8_coords_to_str(coords: (u8, u8)) -> String {
         format!("{}{}", (b'a' + coords.1) as char, (b'1' + (Board::ROWS as i32-coords.0 as i32-1) as u8) as char)
     }
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use crate::chess::Board;
+
+    fn has_move(board: &Board, uci: &str) -> bool {
+        board.legal_moves().iter().any(|(row, col, mv)| mv.to_uci((*row, *col)) == uci)
+    }
+
+    #[test]
+    fn en_passant_cannot_expose_the_king_along_the_rank() {
+        // both pawns leave the fifth rank, uncovering the rook on h5
+        let board = Board::from_fen("8/8/8/K2pP2r/8/8/8/7k w - d6 0 1").unwrap();
+        assert!(!has_move(&board, "e5d6"));
+        assert!(has_move(&board, "e5e6"));
+    }
+
+    #[test]
+    fn en_passant_can_give_discovered_check() {
+        let mut board = Board::from_fen("8/8/8/k2pP2R/8/8/8/4K3 w - d6 0 1").unwrap();
+        let (from, mv) = board.parse_move("e5d6").unwrap();
+        assert_eq!(board.san(from, &mv), "exd6+");
+        board.play_move(from, &mv);
+        assert!(board.is_check);
+        assert_eq!(board.to_fen(), "8/8/3P4/k6R/8/8/8/4K3 b - - 0 1");
+    }
+
+    #[test]
+    fn en_passant_can_capture_the_checking_pawn() {
+        // the pawn which just moved to d4 gives check, and taking it en passant is one of the evasions
+        let mut board = Board::from_fen("8/8/8/4k3/2pP4/8/8/4K3 b - d3 0 1").unwrap();
+        assert!(board.is_check);
+        assert!(has_move(&board, "c4d3"));
+        let (from, mv) = board.parse_move("c4d3").unwrap();
+        board.play_move(from, &mv);
+        assert_eq!(board.to_fen(), "8/8/8/4k3/8/3p4/8/4K3 w - - 0 2");
+        // with the check given by a piece instead, the capture does not help
+        let board = Board::from_fen("8/8/4k3/8/2pP4/7B/8/4K3 b - d3 0 1").unwrap();
+        assert!(board.is_check);
+        assert!(!has_move(&board, "c4d3"));
+    }
+
+    #[test]
+    fn en_passant_square_without_a_capturer() {
+        let mut board = Board::new();
+        let (from, mv) = board.parse_move("e2e4").unwrap();
+        board.play_move(from, &mv);
+        assert_eq!(board.to_fen(), "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1");
+        assert!(board.legal_moves().iter().all(|(_, _, mv)| mv.to != (5, 4)));
+        // no pawn can take, so the position repeats the one without an en passant square
+        let without = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1").unwrap();
+        assert_eq!(board.hash, without.hash);
+        let with = Board::from_fen("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 0 1").unwrap();
+        assert_eq!(with.hash, without.hash);
+    }
+}
@@ -0,0 +1,155 @@
+use std::fmt;
+
+use crate::chess::{Board, DrawType, GameOutcome, PieceColor, PieceType};
+use crate::chess::bitboard::Bitboard;
+
+// a8 is a light square, as is every square whose row and column add up to an even number
+const LIGHT_SQUARES: Bitboard = 0xAA55_AA55_AA55_AA55;
+
+impl Board {
+    /**
+     * Whether neither side can ever checkmate, whatever the moves (FIDE article 5.2.2).
+     * Recognised dead positions: K v K, K+B v K, K+N v K, and kings with any number of bishops all on squares of the same color.
+     */
+    pub(crate) fn insufficient_material(&self) -> bool {
+        let heavy = [PieceType::Pawn, PieceType::Rook, PieceType::Queen].iter()
+            .any(|piece_type| self.pieces_of(PieceColor::White, *piece_type) | self.pieces_of(PieceColor::Black, *piece_type) != 0);
+        if heavy {
+            return false;
+        }
+        let knights = self.pieces_of(PieceColor::White, PieceType::Knight) | self.pieces_of(PieceColor::Black, PieceType::Knight);
+        let bishops = self.pieces_of(PieceColor::White, PieceType::Bishop) | self.pieces_of(PieceColor::Black, PieceType::Bishop);
+        match (knights.count_ones(), bishops.count_ones()) {
+            (0, _) => bishops & LIGHT_SQUARES == 0 || bishops & !LIGHT_SQUARES == 0,
+            (1, 0) => true,
+            _ => false,
+        }
+    }
+
+    /**
+     * The draw the current position ends the game with, if any; stalemate is left to the caller, which already has the legal moves.
+     * Fivefold repetition and the 75-move rule end the game by themselves, threefold repetition and the 50-move rule are claimed on behalf of the side to move.
+     * A checkmate delivered with the last move takes precedence, so this must only be asked when the side to move has legal moves.
+     */
+    pub(crate) fn draw(&self) -> Option<DrawType> {
+        let repetitions = self.repetitions();
+        if repetitions >= 5 {
+            Some(DrawType::FivefoldRepetition)
+        } else if self.halfmove_clock >= 150 {
+            Some(DrawType::SeventyFiveMoveRule)
+        } else if self.insufficient_material() {
+            Some(DrawType::InsufficientMaterial)
+        } else if repetitions >= 3 {
+            Some(DrawType::ThreefoldRepetition)
+        } else if self.halfmove_clock >= 100 {
+            Some(DrawType::FiftyMoveRule)
+        } else {
+            None
+        }
+    }
+
+    /**
+     * Whether color could checkmate the opponent with some series of legal moves, helped by the opponent's own pieces if needed.
+     * A lone king never can; a king with a single minor piece or same-colored bishops only can if the opponent has something to block its own king with.
+     */
+    pub(crate) fn can_checkmate(&self, color: PieceColor) -> bool {
+        let own = self.occupancy[color.index()] & !self.pieces_of(color, PieceType::King);
+        own != 0 && !self.insufficient_material()
+    }
+
+    /**
+     * Outcome of the game when the player of color runs out of time: a loss, unless the opponent could not checkmate them anyway (FIDE article 6.9).
+     */
+    pub(crate) fn outcome_on_time(&self, color: PieceColor) -> GameOutcome {
+        if self.can_checkmate(color.opposite()) {
+            GameOutcome::Timeout(color)
+        } else {
+            GameOutcome::Draw(DrawType::TimeoutVsInsufficientMaterial)
+        }
+    }
+}
+
+impl fmt::Display for GameOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            GameOutcome::Checkmate(color) => write!(f, "Checkmate, {:?} wins", color.opposite()),
+            GameOutcome::Draw(draw) => {
+                let reason = match draw {
+                    DrawType::Stalemate => "stalemate",
+                    DrawType::ThreefoldRepetition => "threefold repetition",
+                    DrawType::FivefoldRepetition => "fivefold repetition",
+                    DrawType::FiftyMoveRule => "the fifty-move rule",
+                    DrawType::SeventyFiveMoveRule => "the seventy-five-move rule",
+                    DrawType::InsufficientMaterial => "insufficient material",
+                    DrawType::TimeoutVsInsufficientMaterial => "timeout vs insufficient material",
+                };
+                write!(f, "Draw by {}", reason)
+            },
+            GameOutcome::Timeout(color) => write!(f, "{:?} ran out of time, {:?} wins", color, color.opposite()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chess::{Board, DrawType, GameOutcome, PieceColor};
+
+    #[test]
+    fn dead_positions() {
+        for (fen, dead) in [
+            ("4k3/8/8/8/8/8/8/4K3 w - - 0 1", true),
+            ("4k3/8/8/8/8/8/8/2B1K3 w - - 0 1", true),
+            ("4k3/8/8/8/8/8/8/1N2K3 w - - 0 1", true),
+            // a second knight can mate if the other side blunders
+            ("4k3/8/8/8/8/8/8/1N2KN2 w - - 0 1", false),
+            // bishops on dark squares only, on both sides, then on squares of both colors
+            ("4kb2/8/8/8/8/8/8/2B1K3 w - - 0 1", true),
+            ("2b1k3/8/8/8/8/8/8/2B1K3 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/8/1NB1K3 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1", false),
+            ("4k3/8/8/8/8/8/8/R3K3 w - - 0 1", false),
+        ] {
+            assert_eq!(Board::from_fen(fen).unwrap().insufficient_material(), dead, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn draws_ending_the_game_come_before_claims() {
+        let mut board = Board::new();
+        let mut draws = Vec::new();
+        // the initial position comes back every four plies
+        for _ in 0..4 {
+            for notation in ["Nf3", "Nf6", "Ng1", "Ng8"] {
+                let (from, mv) = board.parse_move(notation).unwrap();
+                board.play_move(from, &mv);
+            }
+            draws.push(board.draw());
+        }
+        assert_eq!(draws, [None, Some(DrawType::ThreefoldRepetition), Some(DrawType::ThreefoldRepetition), Some(DrawType::FivefoldRepetition)]);
+
+        for (fen, draw) in [
+            ("4k3/8/8/8/8/8/4P3/R3K3 w - - 99 80", None),
+            ("4k3/8/8/8/8/8/4P3/R3K3 w - - 100 80", Some(DrawType::FiftyMoveRule)),
+            ("4k3/8/8/8/8/8/4P3/R3K3 w - - 150 80", Some(DrawType::SeventyFiveMoveRule)),
+            ("4k3/8/8/8/8/8/8/4K3 w - - 100 80", Some(DrawType::InsufficientMaterial)),
+            ("4k3/8/8/8/8/8/8/4K3 w - - 150 80", Some(DrawType::SeventyFiveMoveRule)),
+        ] {
+            assert_eq!(Board::from_fen(fen).unwrap().draw(), draw, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn running_out_of_time() {
+        // a knight can mate a king whose own pawn blocks it in, a knight alone cannot
+        let board = Board::from_fen("7k/7p/8/8/8/8/8/1N2K3 w - - 0 1").unwrap();
+        assert!(board.can_checkmate(PieceColor::White));
+        assert!(board.can_checkmate(PieceColor::Black));
+        assert_eq!(board.outcome_on_time(PieceColor::Black), GameOutcome::Timeout(PieceColor::Black));
+        assert_eq!(board.outcome_on_time(PieceColor::White), GameOutcome::Timeout(PieceColor::White));
+        let board = Board::from_fen("7k/8/8/8/8/8/8/1N2K3 w - - 0 1").unwrap();
+        assert!(!board.can_checkmate(PieceColor::White));
+        assert!(!board.can_checkmate(PieceColor::Black));
+        assert_eq!(board.outcome_on_time(PieceColor::Black), GameOutcome::Draw(DrawType::TimeoutVsInsufficientMaterial));
+        assert_eq!(board.outcome_on_time(PieceColor::White), GameOutcome::Draw(DrawType::TimeoutVsInsufficientMaterial));
+    }
+}
@@ -0,0 +1,411 @@
+use crate::chess::{Board, Piece, PieceColor, PieceType};
+use crate::chess::bitboard::{self, KING_ATTACKS, KNIGHT_ATTACKS};
+
+// Piece values and piece-square tables from PeSTO, tuned for a tapered evaluation.
+// Tables are written from white's point of view, index 0 being a8 (i.e. row 0, col 0 of Board.board).
+const MG_VALUE: [i32; 6] = [82, 337, 365, 477, 1025, 0];
+const EG_VALUE: [i32; 6] = [94, 281, 297, 512, 936, 0];
+
+#[rustfmt::skip]
+const MG_TABLE: [[i32; 64]; 6] = [
+    [ // pawn
+          0,   0,   0,   0,   0,   0,   0,   0,
+         98, 134,  61,  95,  68, 126,  34, -11,
+         -6,   7,  26,  31,  65,  56,  25, -20,
+        -14,  13,   6,  21,  23,  12,  17, -23,
+        -27,  -2,  -5,  12,  17,   6,  10, -25,
+        -26,  -4,  -4, -10,   3,   3,  33, -12,
+        -35,  -1, -20, -23, -15,  24,  38, -22,
+          0,   0,   0,   0,   0,   0,   0,   0,
+    ],
+    [ // knight
+       -167, -89, -34, -49,  61, -97, -15,-107,
+        -73, -41,  72,  36,  23,  62,   7, -17,
+        -47,  60,  37,  65,  84, 129,  73,  44,
+         -9,  17,  19,  53,  37,  69,  18,  22,
+        -13,   4,  16,  13,  28,  19,  21,  -8,
+        -23,  -9,  12,  10,  19,  17,  25, -16,
+        -29, -53, -12,  -3,  -1,  18, -14, -19,
+       -105, -21, -58, -33, -17, -28, -19, -23,
+    ],
+    [ // bishop
+        -29,   4, -82, -37, -25, -42,   7,  -8,
+        -26,  16, -18, -13,  30,  59,  18, -47,
+        -16,  37,  43,  40,  35,  50,  37,  -2,
+         -4,   5,  19,  50,  37,  37,   7,  -2,
+         -6,  13,  13,  26,  34,  12,  10,   4,
+          0,  15,  15,  15,  14,  27,  18,  10,
+          4,  15,  16,   0,   7,  21,  33,   1,
+        -33,  -3, -14, -21, -13, -12, -39, -21,
+    ],
+    [ // rook
+         32,  42,  32,  51,  63,   9,  31,  43,
+         27,  32,  58,  62,  80,  67,  26,  44,
+         -5,  19,  26,  36,  17,  45,  61,  16,
+        -24, -11,   7,  26,  24,  35,  -8, -20,
+        -36, -26, -12,  -1,   9,  -7,   6, -23,
+        -45, -25, -16, -17,   3,   0,  -5, -33,
+        -44, -16, -20,  -9,  -1,  11,  -6, -71,
+        -19, -13,   1,  17,  16,   7, -37, -26,
+    ],
+    [ // queen
+        -28,   0,  29,  12,  59,  44,  43,  45,
+        -24, -39,  -5,   1, -16,  57,  28,  54,
+        -13, -17,   7,   8,  29,  56,  47,  57,
+        -27, -27, -16, -16,  -1,  17,  -2,   1,
+         -9, -26,  -9, -10,  -2,  -4,   3,  -3,
+        -14,   2, -11,  -2,  -5,   2,  14,   5,
+        -35,  -8,  11,   2,   8,  15,  -3,   1,
+         -1, -18,  -9,  10, -15, -25, -31, -50,
+    ],
+    [ // king
+        -65,  23,  16, -15, -56, -34,   2,  13,
+         29,  -1, -20,  -7,  -8,  -4, -38, -29,
+         -9,  24,   2, -16, -20,   6,  22, -22,
+        -17, -20, -12, -27, -30, -25, -14, -36,
+        -49,  -1, -27, -39, -46, -44, -33, -51,
+        -14, -14, -22, -46, -44, -30, -15, -27,
+          1,   7,  -8, -64, -43, -16,   9,   8,
+        -15,  36,  12, -54,   8, -28,  24,  14,
+    ],
+];
+
+#[rustfmt::skip]
+const EG_TABLE: [[i32; 64]; 6] = [
+    [ // pawn
+          0,   0,   0,   0,   0,   0,   0,   0,
+        178, 173, 158, 134, 147, 132, 165, 187,
+         94, 100,  85,  67,  56,  53,  82,  84,
+         32,  24,  13,   5,  -2,   4,  17,  17,
+         13,   9,  -3,  -7,  -7,  -8,   3,  -1,
+          4,   7,  -6,   1,   0,  -5,  -1,  -8,
+         13,   8,   8,  10,  13,   0,   2,  -7,
+          0,   0,   0,   0,   0,   0,   0,   0,
+    ],
+    [ // knight
+        -58, -38, -13, -28, -31, -27, -63, -99,
+        -25,  -8, -25,  -2,  -9, -25, -24, -52,
+        -24, -20,  10,   9,  -1,  -9, -19, -41,
+        -17,   3,  22,  22,  22,  11,   8, -18,
+        -18,  -6,  16,  25,  16,  17,   4, -18,
+        -23,  -3,  -1,  15,  10,  -3, -20, -22,
+        -42, -20, -10,  -5,  -2, -20, -23, -44,
+        -29, -51, -23, -15, -22, -18, -50, -64,
+    ],
+    [ // bishop
+        -14, -21, -11,  -8,  -7,  -9, -17, -24,
+         -8,  -4,   7, -12,  -3, -13,  -4, -14,
+          2,  -8,   0,  -1,  -2,   6,   0,   4,
+         -3,   9,  12,   9,  14,  10,   3,   2,
+         -6,   3,  13,  19,   7,  10,  -3,  -9,
+        -12,  -3,   8,  10,  13,   3,  -7, -15,
+        -14, -18,  -7,  -1,   4,  -9, -15, -27,
+        -23,  -9, -23,  -5,  -9, -16,  -5, -17,
+    ],
+    [ // rook
+         13,  10,  18,  15,  12,  12,   8,   5,
+         11,  13,  13,  11,  -3,   3,   8,   3,
+          7,   7,   7,   5,   4,  -3,  -5,  -3,
+          4,   3,  13,   1,   2,   1,  -1,   2,
+          3,   5,   8,   4,  -5,  -6,  -8, -11,
+         -4,   0,  -5,  -1,  -7, -12,  -8, -16,
+         -6,  -6,   0,   2,  -9,  -9, -11,  -3,
+         -9,   2,   3,  -1,  -5, -13,   4, -20,
+    ],
+    [ // queen
+         -9,  22,  22,  27,  27,  19,  10,  20,
+        -17,  20,  32,  41,  58,  25,  30,   0,
+        -20,   6,   9,  49,  47,  35,  19,   9,
+          3,  22,  24,  45,  57,  40,  57,  36,
+        -18,  28,  19,  47,  31,  34,  39,  23,
+        -16, -27,  15,   6,   9,  17,  10,   5,
+        -22, -23, -30, -16, -16, -23, -36, -32,
+        -33, -28, -22, -43,  -5, -32, -20, -41,
+    ],
+    [ // king
+        -74, -35, -18, -18, -11,  15,   4, -17,
+        -12,  17,  14,  17,  17,  38,  23,  11,
+         10,  17,  23,  15,  20,  45,  44,  13,
+         -8,  22,  24,  27,  26,  33,  26,   3,
+        -18,  -4,  21,  24,  27,  23,   9, -11,
+        -19,  -3,  11,  21,  23,  16,   7,  -9,
+        -27, -11,   4,  13,  14,   4,  -5, -17,
+        -53, -34, -21, -11, -28, -14, -24, -43,
+    ],
+];
+
+// game phase contributed by each piece type: 24 with all the pieces on the board, 0 with only kings and pawns
+const PHASE_WEIGHT: [i32; 6] = [0, 1, 1, 2, 4, 0];
+const MAX_PHASE: i32 = 24;
+
+const DOUBLED_PAWN: (i32, i32) = (-10, -20);
+const ISOLATED_PAWN: (i32, i32) = (-10, -15);
+// indexed by the rank of the pawn counted from its own back rank, as given by relative_rank:
+// pawns only stand on ranks 1 to 6, so the first and last entries are never used
+const PASSED_PAWN: [(i32, i32); 8] = [(0, 0), (5, 10), (10, 20), (15, 35), (25, 60), (40, 100), (60, 150), (0, 0)];
+const BISHOP_PAIR: (i32, i32) = (30, 50);
+// bonus for every square a piece can move to, indexed like MG_VALUE
+const MOBILITY: [(i32, i32); 6] = [(0, 0), (4, 4), (5, 5), (2, 4), (1, 2), (0, 0)];
+const MISSING_SHIELD_PAWN: i32 = -15;
+// middlegame penalty indexed by the number of attacks on the squares around the king
+const KING_ATTACK: [i32; 16] = [0, 0, 5, 12, 22, 35, 50, 66, 84, 104, 126, 150, 176, 204, 234, 266];
+
+/**
+ * Index of a square in the piece-square tables, mirroring the rows for black pieces.
+ */
+fn table_index(color: PieceColor, row: u8, col: u8) -> usize {
+    match color {
+        PieceColor::White => row as usize * 8 + col as usize,
+        PieceColor::Black => (7 - row as usize) * 8 + col as usize,
+    }
+}
+
+/**
+ * Number of ranks a piece has advanced from its side's back rank.
+ */
+fn relative_rank(color: PieceColor, row: u8) -> usize {
+    match color {
+        PieceColor::White => 7 - row as usize,
+        PieceColor::Black => row as usize,
+    }
+}
+
+/**
+ * Middlegame value of a piece, for the search to estimate what a capture or a promotion gains.
+ */
+pub(crate) fn piece_value(piece_type: PieceType) -> i32 {
+    MG_VALUE[piece_type.index()]
+}
+
+/**
+ * Weight of each term of the evaluation in percent, 100 for all of them by default.
+ * Engines with different weights can be played against each other with the match subcommand.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EvalWeights {
+    pub material: i32,
+    pub piece_squares: i32,
+    pub pawns: i32,
+    pub mobility: i32,
+    pub king_safety: i32,
+    pub bishop_pair: i32,
+}
+
+impl Default for EvalWeights {
+    fn default() -> Self {
+        Self { material: 100, piece_squares: 100, pawns: 100, mobility: 100, king_safety: 100, bishop_pair: 100 }
+    }
+}
+
+impl Board {
+    /**
+     * Static evaluation of the position in centipawns, from the point of view of the side to move, with each term scaled by its weight.
+     * Middlegame and endgame scores are computed separately and blended according to the material left on the board.
+     */
+    pub(crate) fn evaluate(&self, weights: &EvalWeights) -> i32 {
+        // middlegame and endgame score of each side, term by term
+        let mut material = [(0, 0); 2];
+        let mut piece_squares = [(0, 0); 2];
+        let mut mobility = [(0, 0); 2];
+        let mut phase = 0;
+        let mut bishops = [0; 2];
+        let mut pawns: [Vec<(u8, u8)>; 2] = [Vec::new(), Vec::new()];
+
+        for row in 0..Board::ROWS {
+            for col in 0..Board::COLS {
+                if let Some(piece) = &self.board[row][col] {
+                    let (color, kind) = (piece.color.index(), piece.piece_type.index());
+                    let square = table_index(piece.color, piece.row, piece.col);
+                    material[color].0 += MG_VALUE[kind];
+                    material[color].1 += EG_VALUE[kind];
+                    piece_squares[color].0 += MG_TABLE[kind][square];
+                    piece_squares[color].1 += EG_TABLE[kind][square];
+                    phase += PHASE_WEIGHT[kind];
+
+                    match piece.piece_type {
+                        PieceType::Pawn => pawns[color].push((piece.row, piece.col)),
+                        PieceType::King => {},
+                        _ => {
+                            if piece.piece_type == PieceType::Bishop {
+                                bishops[color] += 1;
+                            }
+                            let count = self.mobility(piece);
+                            mobility[color].0 += MOBILITY[kind].0 * count;
+                            mobility[color].1 += MOBILITY[kind].1 * count;
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut mg = [0; 2];
+        let mut eg = [0; 2];
+        for color in [PieceColor::White, PieceColor::Black] {
+            let c = color.index();
+            let pawn_structure = Board::pawn_structure(color, &pawns[c], &pawns[1 - c]);
+            let bishop_pair = if bishops[c] >= 2 {BISHOP_PAIR} else {(0, 0)};
+            let king_safety = self.king_safety(color, &pawns[c]);
+            let terms = [
+                (material[c], weights.material),
+                (piece_squares[c], weights.piece_squares),
+                (pawn_structure, weights.pawns),
+                (mobility[c], weights.mobility),
+                ((king_safety, 0), weights.king_safety),
+                (bishop_pair, weights.bishop_pair),
+            ];
+            for ((term_mg, term_eg), weight) in terms {
+                mg[c] += term_mg * weight;
+                eg[c] += term_eg * weight;
+            }
+        }
+
+        let phase = phase.min(MAX_PHASE);
+        let mg_score = (mg[0] - mg[1]) / 100;
+        let eg_score = (eg[0] - eg[1]) / 100;
+        let score = (mg_score * phase + eg_score * (MAX_PHASE - phase)) / MAX_PHASE;
+        match self.turn {
+            PieceColor::White => score,
+            PieceColor::Black => -score,
+        }
+    }
+
+    /**
+     * Number of squares a piece could move to, ignoring pins and checks.
+     */
+    fn mobility(&self, piece: &Piece) -> i32 {
+        let sq = bitboard::square(piece.row, piece.col);
+        let occupied = self.occupied();
+        let attacks = match piece.piece_type {
+            PieceType::Knight => KNIGHT_ATTACKS[sq],
+            PieceType::Bishop => bitboard::bishop_attacks(sq, occupied),
+            PieceType::Rook => bitboard::rook_attacks(sq, occupied),
+            PieceType::Queen => bitboard::bishop_attacks(sq, occupied) | bitboard::rook_attacks(sq, occupied),
+            _ => 0,
+        };
+        (attacks & !self.occupancy[piece.color.index()]).count_ones() as i32
+    }
+
+    /**
+     * Middlegame and endgame score of doubled, isolated and passed pawns of one side.
+     */
+    fn pawn_structure(color: PieceColor, pawns: &[(u8, u8)], enemy_pawns: &[(u8, u8)]) -> (i32, i32) {
+        let (mut mg, mut eg) = (0, 0);
+        let mut files = [0; Board::COLS];
+        for (_, col) in pawns {
+            files[*col as usize] += 1;
+        }
+        for count in files.iter().filter(|count| **count > 1) {
+            mg += DOUBLED_PAWN.0 * (count - 1);
+            eg += DOUBLED_PAWN.1 * (count - 1);
+        }
+        for (row, col) in pawns {
+            let col = *col as usize;
+            let left = col > 0 && files[col - 1] > 0;
+            let right = col < Board::COLS - 1 && files[col + 1] > 0;
+            if !left && !right {
+                mg += ISOLATED_PAWN.0;
+                eg += ISOLATED_PAWN.1;
+            }
+            let blocked = enemy_pawns.iter().any(|(enemy_row, enemy_col)| {
+                let ahead = match color {
+                    PieceColor::White => enemy_row < row,
+                    PieceColor::Black => enemy_row > row,
+                };
+                ahead && (*enemy_col as i32 - col as i32).abs() <= 1
+            });
+            if !blocked {
+                let (bonus_mg, bonus_eg) = PASSED_PAWN[relative_rank(color, *row)];
+                mg += bonus_mg;
+                eg += bonus_eg;
+            }
+        }
+        (mg, eg)
+    }
+
+    /**
+     * Middlegame score of the pawn shield in front of the king and of the enemy attacks on the squares around it.
+     */
+    fn king_safety(&self, color: PieceColor, pawns: &[(u8, u8)]) -> i32 {
+        let (king_row, king_col) = match color {
+            PieceColor::White => self.white_king_pos,
+            PieceColor::Black => self.black_king_pos,
+        };
+        let forward: i32 = match color {
+            PieceColor::White => -1,
+            PieceColor::Black => 1,
+        };
+        let mut score = 0;
+        if relative_rank(color, king_row) <= 1 {
+            for col in (king_col as i32 - 1)..=(king_col as i32 + 1) {
+                if !(0..Board::COLS as i32).contains(&col) {
+                    continue;
+                }
+                let shielded = pawns.iter().any(|(row, pawn_col)| {
+                    let distance = (*row as i32 - king_row as i32) * forward;
+                    *pawn_col as i32 == col && (1..=2).contains(&distance)
+                });
+                if !shielded {
+                    score += MISSING_SHIELD_PAWN;
+                }
+            }
+        }
+
+        let king_zone = KING_ATTACKS[bitboard::square(king_row, king_col)] | 1 << bitboard::square(king_row, king_col);
+        let attacks: usize = bitboard::squares(king_zone).map(|sq| self.attackers(sq, &color).count_ones() as usize).sum();
+        score - KING_ATTACK[attacks.min(KING_ATTACK.len() - 1)]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chess::{Board, EvalWeights, PieceColor};
+
+    const POSITIONS: [&str; 6] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+        "rnbqkb1r/pp3ppp/4pn2/2pp4/3P1B2/4PN2/PPP2PPP/RN1QKB1R b KQkq - 0 4",
+        "8/5pk1/6p1/3P4/1P6/8/5PPP/2R3K1 b - - 0 40",
+    ];
+
+    /**
+     * The same position with the ranks reversed and the colors swapped, including the side to move.
+     */
+    fn mirror(fen: &str) -> String {
+        let swap_case = |c: char| if c.is_ascii_uppercase() {c.to_ascii_lowercase()} else {c.to_ascii_uppercase()};
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        let placement: Vec<String> = fields[0].split('/').rev().map(|rank| rank.chars().map(swap_case).collect()).collect();
+        let turn = if fields[1] == "w" {"b"} else {"w"};
+        let castling: String = fields[2].chars().map(swap_case).collect();
+        let en_passant = match fields[3].as_bytes() {
+            [file, rank] => format!("{}{}", *file as char, (b'1' + b'8' - rank) as char),
+            _ => "-".to_string(),
+        };
+        format!("{} {} {} {} {} {}", placement.join("/"), turn, castling, en_passant, fields[4], fields[5])
+    }
+
+    #[test]
+    fn mirrored_positions_evaluate_the_same() {
+        for fen in POSITIONS {
+            let board = Board::from_fen(fen).unwrap();
+            let mirrored = Board::from_fen(&mirror(fen)).unwrap();
+            let weights = EvalWeights::default();
+            let white_score = |board: &Board| if board.turn == PieceColor::White {board.evaluate(&weights)} else {-board.evaluate(&weights)};
+            assert_eq!(white_score(&board), -white_score(&mirrored), "{}", fen);
+            assert_eq!(board.evaluate(&weights), mirrored.evaluate(&weights), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn weights_scale_their_terms() {
+        let board = Board::from_fen("4k3/8/8/8/8/8/8/3QK3 w - - 0 1").unwrap();
+        let default = board.evaluate(&EvalWeights::default());
+        let no_material = board.evaluate(&EvalWeights { material: 0, ..Default::default() });
+        let double_material = board.evaluate(&EvalWeights { material: 200, ..Default::default() });
+        assert!(default > 900);
+        assert!(no_material < 100);
+        // up to a point of rounding in the blend of the middlegame and endgame scores
+        assert!(((double_material - default) - (default - no_material)).abs() <= 1);
+    }
+}
@@ -0,0 +1,128 @@
+use std::fmt;
+
+use crate::chess::{FenError, FenField};
+
+impl fmt::Display for FenField {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            FenField::Placement => "piece placement",
+            FenField::SideToMove => "side to move",
+            FenField::Castling => "castling availability",
+            FenField::EnPassant => "en passant target square",
+            FenField::HalfmoveClock => "halfmove clock",
+            FenField::FullmoveNumber => "fullmove number",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl fmt::Display for FenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FenError::FieldCount(count) => write!(f, "expected 6 space-separated fields, found {}", count),
+            FenError::InvalidField(field, value) => write!(f, "invalid {} field '{}'", field, value),
+            FenError::InvalidPiece { index, symbol } => write!(f, "{} field: unexpected '{}' at index {}", FenField::Placement, symbol, index),
+            FenError::RankCount(count) => write!(f, "{} field: expected 8 ranks, found {}", FenField::Placement, count),
+            FenError::RankLength { rank, squares } => write!(f, "{} field: rank {} describes {} squares instead of 8", FenField::Placement, rank, squares),
+            FenError::ConsecutiveDigits { rank, index } => write!(f, "{} field: rank {} has two digits in a row at index {}", FenField::Placement, rank, index),
+            FenError::KingCount(color, count) => write!(f, "{} field: expected one {:?} king, found {}", FenField::Placement, color, count),
+            FenError::PawnOnBackRank(square) => write!(f, "{} field: pawn on back rank square {}", FenField::Placement, square),
+            FenError::CastlingRights(right) => write!(f, "{} field: '{}' does not match king and rook placement", FenField::Castling, right),
+            FenError::EnPassantSquare(square) => write!(f, "{} field: {} is not behind a pawn which just moved two squares", FenField::EnPassant, square),
+            FenError::OpponentInCheck => write!(f, "the side not to move is in check"),
+        }
+    }
+}
+
+impl std::error::Error for FenError {}
+
+#[cfg(test)]
+mod tests {
+    use crate::chess::{Board, FenError, FenField, PieceColor};
+
+    const ROUND_TRIP: [&str; 12] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+        "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+        "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+        "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+        "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+        "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+        "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+        "rnbqkbnr/pppp1ppp/8/8/3Pp3/8/PPP1PPPP/RNBQKBNR b Kq d3 0 3",
+        "r3k3/8/8/8/8/8/8/4K2R b Kq - 99 250",
+        "4k3/8/8/8/8/8/8/4K3 w - - 255 65535",
+        "8/P7/8/8/8/8/7p/K6k b - - 0 1",
+        "4k3/8/8/8/8/8/8/R3K3 w Q - 12 40",
+    ];
+
+    #[test]
+    fn parse_then_serialize_is_identity() {
+        for fen in ROUND_TRIP {
+            let board = Board::from_fen(fen).unwrap_or_else(|e| panic!("{}: {}", fen, e));
+            assert_eq!(board.to_fen(), fen);
+        }
+    }
+
+    #[test]
+    fn played_positions_round_trip() {
+        let mut board = Board::new();
+        for uci in ["e2e4", "c7c5", "e4e5", "d7d5", "e5d6", "e7d6", "g1f3", "b8c6", "f1b5", "g8f6", "e1g1", "c8d7", "b5c6", "d7c6"] {
+            let (from, mv) = board.parse_move(uci).unwrap();
+            board.play_move(from, &mv);
+            let fen = board.to_fen();
+            let parsed = Board::from_fen(&fen).unwrap_or_else(|e| panic!("{}: {}", fen, e));
+            assert_eq!(parsed.to_fen(), fen);
+            assert_eq!(parsed.hash, board.hash, "{}", fen);
+            assert_eq!(parsed.legal_moves().len(), board.legal_moves().len(), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn every_error_is_reported() {
+        let invalid = |field, value: &str| FenError::InvalidField(field, value.to_string());
+        let cases = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0", FenError::FieldCount(5)),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1 e4", FenError::FieldCount(7)),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq", FenError::FieldCount(3)),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR x KQkq - 0 1", invalid(FenField::SideToMove, "x")),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkx - 0 1", invalid(FenField::Castling, "KQkx")),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KK - 0 1", invalid(FenField::Castling, "KK")),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e9 0 1", invalid(FenField::EnPassant, "e9")),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - -1 1", invalid(FenField::HalfmoveClock, "-1")),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 0", invalid(FenField::FullmoveNumber, "0")),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNX w KQkq - 0 1", FenError::InvalidPiece { index: 42, symbol: 'X' }),
+            ("rnbqkbnr/pppppppp/44/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::ConsecutiveDigits { rank: 6, index: 19 }),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKB11 w Qkq - 0 1", FenError::ConsecutiveDigits { rank: 1, index: 42 }),
+            ("rnbqkbnr/pppppppp/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::RankCount(7)),
+            ("rnbqkbnr/pppppppp/8p/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::RankLength { rank: 6, squares: 9 }),
+            ("rnbqkbnr/pppppppp/7/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", FenError::RankLength { rank: 6, squares: 7 }),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQ1BNR w kq - 0 1", FenError::KingCount(PieceColor::White, 0)),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBKKBNR w kq - 0 1", FenError::KingCount(PieceColor::White, 2)),
+            ("rnbq1bnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQ - 0 1", FenError::KingCount(PieceColor::Black, 0)),
+            ("rnbqkbnP/pppppppp/8/8/8/8/PPPPPPP1/RNBQKBNR w KQq - 0 1", FenError::PawnOnBackRank("h8".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBN1 w KQkq - 0 1", FenError::CastlingRights('K')),
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq e3 0 1", FenError::EnPassantSquare("e3".to_string())),
+            ("rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq e3 0 1", FenError::EnPassantSquare("e3".to_string())),
+            ("7k/8/8/8/8/8/8/3K3R w - - 0 1", FenError::OpponentInCheck),
+        ];
+        for (fen, error) in cases {
+            assert_eq!(Board::from_fen(fen).err(), Some(error), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn lenient_parsing_accepts_epd() {
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+        for epd in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm e4; id \"start\";",
+            start,
+        ] {
+            assert_eq!(Board::from_fen_lenient(epd).map(|board| board.to_fen()).ok().as_deref(), Some(start), "{}", epd);
+        }
+        assert_eq!(Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -").err(), Some(FenError::FieldCount(4)));
+        assert_eq!(Board::from_fen_lenient("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0").err(), Some(FenError::FieldCount(5)));
+        assert_eq!(Board::from_fen_lenient("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq").err(), Some(FenError::FieldCount(3)));
+        assert_eq!(Board::from_fen_lenient("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1").map(|board| board.fullmove_number).ok(), Some(1));
+    }
+}
@@ -1,7 +1,33 @@
-use std::collections::HashMap;
-
+mod bitboard;
 mod board;
+mod book;
+mod draw;
+mod eval;
+mod fen;
+mod movepick;
+mod notation;
+mod perft;
+mod pgn;
 mod piece;
+mod search;
+mod see;
+mod syzygy;
+mod time;
+mod tt;
+mod zobrist;
+
+pub use book::{OpeningBook, DEFAULT_BOOK_DEPTH, MAX_BOOK_DEPTH};
+pub use eval::EvalWeights;
+pub(crate) use pgn::{pgn_date, result_token};
+pub use search::{SearchLimits, MAX_THREADS};
+pub(crate) use search::{MoveSource, SearchResult};
+pub use syzygy::Tablebase;
+pub use time::TimeControl;
+pub(crate) use time::{TimeSource, WallClock};
+pub use tt::{TranspositionTable, DEFAULT_HASH_MB, MAX_HASH_MB};
+
+// the name the engine goes by in the UCI handshake, in the terminal and in PGN tags
+pub const ENGINE_NAME: &str = "chess_bot";
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub(crate) enum PieceType {
@@ -20,7 +46,7 @@ pub(crate) enum PieceColor {
 }
 
 #[derive(Debug, Clone, Copy)]
-struct Piece {
+pub(crate) struct Piece {
     piece_type: PieceType,
     color: PieceColor,
     row: u8,
@@ -28,7 +54,7 @@ struct Piece {
 }
 
 #[derive(Debug, Clone, Copy)]
-struct Move {
+pub(crate) struct Move {
     to: (u8, u8),
     capture: Option<Piece>,
     promotion: Option<PieceType>,
@@ -36,6 +62,17 @@ struct Move {
     rook_to: Option<(u8, u8)>,
 }
 
+/**
+ * Which legal moves to generate: tactical moves are the captures and the promotions, quiet moves all the others.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MoveKind {
+    All,
+    Tactical,
+    Quiet,
+}
+
+#[derive(Clone)]
 struct HistoryData {
     starting_row: u8,
     starting_col: u8,
@@ -48,8 +85,10 @@ struct HistoryData {
     halfmove_clock: u8,
     fullmove_number: u16,
     is_check: bool,
+    hash: u64,
 }
 
+#[derive(Clone)]
 pub struct Board {
     board: Vec<Vec<Option<Piece>>>,
     turn: PieceColor,
@@ -62,24 +101,85 @@ pub struct Board {
     fullmove_number: u16,
     is_check: bool,
     history: Vec<HistoryData>,
-    board_config_counts: HashMap<String, u8>,
+    // Zobrist key of the position, see Board::hash
+    hash: u64,
 
     white_king_pos: (u8, u8),
     black_king_pos: (u8, u8),
     prefetched_moves: Option<Vec<(u8, u8, Move)>>,
+    // one bitboard per color and piece type (indexed by PieceColor::index and PieceType::index), mirroring `board`
+    bitboards: [[u64; 6]; 2],
+    occupancy: [u64; 2],
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum DrawType {
     ThreefoldRepetition,
+    // the same position for the fifth time ends the game even if nobody claims the draw
+    FivefoldRepetition,
     FiftyMoveRule,
+    // 75 moves by each side without captures or pawn moves end the game even if nobody claims the draw
+    SeventyFiveMoveRule,
+    // neither side can checkmate with any series of legal moves (a dead position)
     InsufficientMaterial,
     Stalemate,
+    // a player ran out of time, but the opponent could not have checkmated them by any series of legal moves
+    TimeoutVsInsufficientMaterial,
 }
 
-#[derive(Debug)]
+#[derive(Debug, PartialEq, Eq)]
 pub enum GameOutcome {
     Checkmate(PieceColor),
     Draw(DrawType),
-    DebugError(String),
+    // the player of this color ran out of time
+    Timeout(PieceColor),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FenField {
+    Placement,
+    SideToMove,
+    Castling,
+    EnPassant,
+    HalfmoveClock,
+    FullmoveNumber,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FenError {
+    FieldCount(usize),
+    InvalidField(FenField, String),
+    InvalidPiece { index: usize, symbol: char },
+    RankCount(usize),
+    RankLength { rank: u8, squares: usize },
+    // two digits in a row, e.g. "44" instead of "8"
+    ConsecutiveDigits { rank: u8, index: usize },
+    KingCount(PieceColor, usize),
+    PawnOnBackRank(String),
+    CastlingRights(char),
+    EnPassantSquare(String),
+    OpponentInCheck,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NotationError {
+    Invalid(String),
+    Illegal(String),
+    Ambiguous(String),
+}
+
+/**
+ * A game read from PGN: its tag pairs, in the order they were found, and the board after the last move.
+ */
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub board: Board,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PgnError {
+    TagPair(String),
+    Unterminated(char),
+    Fen(FenError),
+    Move { ply: usize, error: NotationError },
 }
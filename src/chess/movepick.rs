@@ -0,0 +1,329 @@
+use crate::chess::{Board, Move, MoveKind, PieceColor};
+use crate::chess::bitboard;
+use crate::chess::search::MAX_DEPTH;
+use crate::chess::tt::pack_move;
+
+// history scores stay within this bound: the more a score grows, the less a new cutoff adds to it
+const MAX_HISTORY: i32 = 16384;
+
+/**
+ * Starting and target squares of a move packed by tt::pack_move.
+ */
+fn move_squares(packed: u16) -> (usize, usize) {
+    ((packed & 63) as usize, (packed >> 6 & 63) as usize)
+}
+
+/**
+ * What the search learned about the quiet moves which refuted the moves of the opponent, kept for the whole search.
+ */
+pub(crate) struct OrderingTables {
+    // the last two quiet moves which caused a beta cutoff at each ply, likely to refute the other moves searched at the same ply too
+    killers: [[u16; 2]; MAX_DEPTH as usize],
+    // how much each quiet move (by color, starting square and target square) caused beta cutoffs, weighted by the depth of the search
+    history: [[[i32; 64]; 64]; 2],
+    // the quiet move which last refuted each move of the opponent, indexed by its starting and target squares
+    counter_moves: [[u16; 64]; 64],
+}
+
+impl OrderingTables {
+    pub(crate) fn new() -> Self {
+        Self {
+            killers: [[0; 2]; MAX_DEPTH as usize],
+            history: [[[0; 64]; 64]; 2],
+            counter_moves: [[0; 64]; 64],
+        }
+    }
+
+    pub(crate) fn killers(&self, ply: u32) -> [u16; 2] {
+        self.killers[ply as usize]
+    }
+
+    /**
+     * The move which refuted previous the last time, 0 if none or if there is no previous move.
+     */
+    pub(crate) fn counter_move(&self, previous: u16) -> u16 {
+        if previous == 0 {
+            return 0;
+        }
+        let (from, to) = move_squares(previous);
+        self.counter_moves[from][to]
+    }
+
+    fn history(&self, color: PieceColor, packed: u16) -> i32 {
+        let (from, to) = move_squares(packed);
+        self.history[color.index()][from][to]
+    }
+
+    fn add_history(&mut self, color: PieceColor, packed: u16, bonus: i32) {
+        let (from, to) = move_squares(packed);
+        let entry = &mut self.history[color.index()][from][to];
+        *entry += bonus - *entry * bonus.abs() / MAX_HISTORY;
+    }
+
+    /**
+     * Remember that the quiet move cut played by color caused a beta cutoff at the given ply and depth, after the move previous of the opponent.
+     * The quiet moves tried before it without success lose as much history as it gains.
+     */
+    pub(crate) fn update(&mut self, color: PieceColor, ply: u32, depth: u32, previous: u16, cut: u16, tried: &[u16]) {
+        let killers = &mut self.killers[ply as usize];
+        if killers[0] != cut {
+            killers[1] = killers[0];
+            killers[0] = cut;
+        }
+        let bonus = ((depth * depth) as i32).min(MAX_HISTORY);
+        self.add_history(color, cut, bonus);
+        for packed in tried {
+            self.add_history(color, *packed, -bonus);
+        }
+        if previous != 0 {
+            let (from, to) = move_squares(previous);
+            self.counter_moves[from][to] = cut;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Stage {
+    HashMove,
+    GenerateTactical,
+    GoodTactical,
+    Refutations,
+    GenerateQuiets,
+    Quiets,
+    BadTactical,
+    Done,
+}
+
+/**
+ * Hands out the legal moves of a position one at a time, most promising first: the hash move, the captures and promotions which do not lose material
+ * (by static exchange evaluation, then most valuable victim and least valuable attacker), the killer moves and the counter move, the other quiet moves
+ * by history, and the losing captures last. Each group is only generated when the previous ones are exhausted, so a cutoff by an early move saves the rest.
+ */
+pub(crate) struct MovePicker {
+    stage: Stage,
+    // the quiescence search only wants the captures and promotions which do not lose material
+    tactical_only: bool,
+    hash_move: u16,
+    // the two killer moves and the counter move
+    refutations: [u16; 3],
+    refutation_index: usize,
+    moves: Vec<(u8, u8, Move, i32)>,
+    bad_tactical: Vec<(u8, u8, Move, i32)>,
+}
+
+impl MovePicker {
+    pub(crate) fn new(hash_move: u16, killers: [u16; 2], counter_move: u16) -> Self {
+        let counter_move = if killers.contains(&counter_move) {0} else {counter_move};
+        Self {
+            stage: Stage::HashMove,
+            tactical_only: false,
+            hash_move,
+            refutations: [killers[0], killers[1], counter_move],
+            refutation_index: 0,
+            moves: Vec::new(),
+            bad_tactical: Vec::new(),
+        }
+    }
+
+    pub(crate) fn tactical() -> Self {
+        Self {
+            tactical_only: true,
+            ..Self::new(0, [0; 2], 0)
+        }
+    }
+
+    /**
+     * Take the move with the highest score out of moves.
+     */
+    fn pick_best(moves: &mut Vec<(u8, u8, Move, i32)>) -> Option<(u8, u8, Move)> {
+        let best = (0..moves.len()).max_by_key(|i| moves[*i].3)?;
+        let (row, col, mv, _) = moves.swap_remove(best);
+        Some((row, col, mv))
+    }
+
+    pub(crate) fn next(&mut self, board: &Board, tables: &OrderingTables) -> Option<(u8, u8, Move)> {
+        loop {
+            match self.stage {
+                Stage::HashMove => {
+                    self.stage = Stage::GenerateTactical;
+                    if self.hash_move != 0 {
+                        if let Some(found) = board.find_packed_move(self.hash_move) {
+                            return Some(found);
+                        }
+                    }
+                },
+                Stage::GenerateTactical => {
+                    for (row, col, mv) in board.get_moves(MoveKind::Tactical) {
+                        if pack_move((row, col), &mv) == self.hash_move {
+                            continue;
+                        }
+                        let see = board.see((row, col), &mv);
+                        if see < 0 {
+                            self.bad_tactical.push((row, col, mv, see));
+                            continue;
+                        }
+                        let attacker = board.board[row as usize][col as usize].map_or(0, |piece| piece.piece_type.index() as i32);
+                        let victim = mv.capture.map_or(0, |piece| piece.piece_type.index() as i32 + 1);
+                        self.moves.push((row, col, mv, see * 64 + victim * 8 - attacker));
+                    }
+                    self.stage = Stage::GoodTactical;
+                },
+                Stage::GoodTactical => {
+                    if let Some(found) = Self::pick_best(&mut self.moves) {
+                        return Some(found);
+                    }
+                    self.stage = if self.tactical_only {Stage::Done} else {Stage::Refutations};
+                },
+                Stage::Refutations => {
+                    while self.refutation_index < self.refutations.len() {
+                        let packed = self.refutations[self.refutation_index];
+                        self.refutation_index += 1;
+                        if packed == 0 || packed == self.hash_move {
+                            continue;
+                        }
+                        // a refutation found in another position is only tried if it is a legal quiet move here
+                        if let Some(found) = board.find_packed_move(packed).filter(|(_, _, mv)| !mv.is_tactical()) {
+                            return Some(found);
+                        }
+                    }
+                    self.stage = Stage::GenerateQuiets;
+                },
+                Stage::GenerateQuiets => {
+                    let color = board.side_to_move();
+                    for (row, col, mv) in board.get_moves(MoveKind::Quiet) {
+                        let packed = pack_move((row, col), &mv);
+                        if packed == self.hash_move || self.refutations.contains(&packed) {
+                            continue;
+                        }
+                        self.moves.push((row, col, mv, tables.history(color, packed)));
+                    }
+                    self.stage = Stage::Quiets;
+                },
+                Stage::Quiets => {
+                    if let Some(found) = Self::pick_best(&mut self.moves) {
+                        return Some(found);
+                    }
+                    self.stage = Stage::BadTactical;
+                },
+                Stage::BadTactical => {
+                    if let Some(found) = Self::pick_best(&mut self.bad_tactical) {
+                        return Some(found);
+                    }
+                    self.stage = Stage::Done;
+                },
+                Stage::Done => return None,
+            }
+        }
+    }
+}
+
+impl Board {
+    /**
+     * The legal move packed by tt::pack_move, if the position has it: moves remembered from other positions must be checked before being played.
+     */
+    pub(crate) fn find_packed_move(&self, packed: u16) -> Option<(u8, u8, Move)> {
+        let (from, _) = move_squares(packed);
+        let (row, col) = bitboard::coords(from);
+        let piece = self.board[row as usize][col as usize].filter(|piece| piece.color == self.turn)?;
+        let checking_pieces = if self.is_check {self.get_checking_pieces(&self.turn, false)} else {Vec::new()};
+        let pins = self.pinned_lines(self.turn);
+        piece.generate_moves(self, &checking_pieces, &pins, MoveKind::All).into_iter()
+            .find(|mv| pack_move((row, col), mv) == packed)
+            .map(|mv| (row, col, mv))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the Chess Programming Wiki "Kiwipete" position: captures winning material (Bxa6), losing it (Qxf6) and even, castling both ways
+    const KIWIPETE: &str = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+    // promotions, with and without capture, some losing the new piece to the rook
+    const PROMOTIONS: &str = "1r2k3/P1P5/8/8/8/8/8/4K3 w - - 0 1";
+
+    fn packed(board: &Board, notation: &str) -> u16 {
+        let (from, mv) = board.parse_move(notation).unwrap();
+        pack_move(from, &mv)
+    }
+
+    fn pick_all(board: &Board, mut picker: MovePicker) -> Vec<String> {
+        let tables = OrderingTables::new();
+        std::iter::from_fn(|| picker.next(board, &tables)).map(|(row, col, mv)| mv.to_uci((row, col))).collect()
+    }
+
+    fn sorted(mut moves: Vec<String>) -> Vec<String> {
+        moves.sort();
+        moves
+    }
+
+    fn legal(board: &Board) -> Vec<String> {
+        sorted(board.legal_moves().iter().map(|(row, col, mv)| mv.to_uci((*row, *col))).collect())
+    }
+
+    fn is_losing(board: &Board, notation: &str) -> bool {
+        let (from, mv) = board.parse_move(notation).unwrap();
+        mv.is_tactical() && board.see(from, &mv) < 0
+    }
+
+    #[test]
+    fn every_legal_move_once() {
+        for fen in [KIWIPETE, PROMOTIONS, "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"] {
+            let board = Board::from_fen(fen).unwrap();
+            assert_eq!(sorted(pick_all(&board, MovePicker::new(0, [0; 2], 0))), legal(&board), "{}", fen);
+        }
+        let board = Board::from_fen(KIWIPETE).unwrap();
+        let picker = MovePicker::new(packed(&board, "e1g1"), [packed(&board, "a2a3"), packed(&board, "e5f7")], packed(&board, "a2a3"));
+        assert_eq!(sorted(pick_all(&board, picker)), legal(&board));
+    }
+
+    #[test]
+    fn hash_move_then_good_captures_then_refutations() {
+        let board = Board::from_fen(KIWIPETE).unwrap();
+        let picker = MovePicker::new(packed(&board, "e1g1"), [packed(&board, "a2a3"), packed(&board, "g2g3")], packed(&board, "b2b3"));
+        let moves = pick_all(&board, picker);
+        assert_eq!(moves[0], "e1g1");
+        // the good captures, the best first, then the killers and the counter move
+        let good = moves.iter().skip(1).take_while(|notation| board.parse_move(notation).unwrap().1.is_tactical()).count();
+        assert_eq!(moves[1], "e2a6");
+        assert!(moves[1..=good].iter().all(|notation| !is_losing(&board, notation)), "{:?}", moves);
+        assert_eq!(moves[good + 1..good + 4], ["a2a3", "g2g3", "b2b3"]);
+    }
+
+    #[test]
+    fn refutations_must_be_legal_quiet_moves() {
+        let board = Board::from_fen(KIWIPETE).unwrap();
+        // Qxf6 loses the queen for a knight, and nothing of white's stands on e7
+        let losing_capture = packed(&board, "f3f6");
+        let illegal = (bitboard::square(1, 4) | bitboard::square(3, 4) << 6) as u16;
+        let moves = pick_all(&board, MovePicker::new(0, [losing_capture, illegal], packed(&board, "a2a3")));
+        assert_eq!(sorted(moves.clone()), legal(&board));
+        let quiets = moves.iter().position(|notation| !board.parse_move(notation).unwrap().1.is_tactical()).unwrap();
+        assert_eq!(moves[quiets], "a2a3");
+        let f3f6 = moves.iter().position(|notation| notation == "f3f6").unwrap();
+        assert!(f3f6 > quiets, "{:?}", moves);
+    }
+
+    #[test]
+    fn losing_captures_come_last() {
+        for fen in [KIWIPETE, PROMOTIONS] {
+            let board = Board::from_fen(fen).unwrap();
+            let moves = pick_all(&board, MovePicker::new(0, [0; 2], 0));
+            let first_losing = moves.iter().position(|notation| is_losing(&board, notation)).expect("no losing capture");
+            assert!(moves[first_losing..].iter().all(|notation| is_losing(&board, notation)), "{}: {:?}", fen, moves);
+            assert!(moves[..first_losing].iter().all(|notation| !is_losing(&board, notation)), "{}: {:?}", fen, moves);
+        }
+    }
+
+    #[test]
+    fn tactical_picker_skips_quiet_and_losing_moves() {
+        for fen in [KIWIPETE, PROMOTIONS] {
+            let board = Board::from_fen(fen).unwrap();
+            let expected: Vec<String> = legal(&board).into_iter()
+                .filter(|notation| board.parse_move(notation).unwrap().1.is_tactical() && !is_losing(&board, notation))
+                .collect();
+            assert!(!expected.is_empty());
+            assert_eq!(sorted(pick_all(&board, MovePicker::tactical())), expected, "{}", fen);
+        }
+    }
+}
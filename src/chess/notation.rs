@@ -0,0 +1,308 @@
+use std::fmt;
+
+use crate::chess::{Board, Move, NotationError, PieceColor, PieceType};
+
+impl PieceType {
+    /**
+     * Letter of the piece in SAN (lowercase in UCI promotions); pawns have none.
+     */
+    pub(crate) fn letter(&self) -> Option<char> {
+        match self {
+            PieceType::Pawn => None,
+            PieceType::Rook => Some('R'),
+            PieceType::Knight => Some('N'),
+            PieceType::Bishop => Some('B'),
+            PieceType::Queen => Some('Q'),
+            PieceType::King => Some('K'),
+        }
+    }
+
+    fn from_letter(letter: char) -> Option<Self> {
+        match letter.to_ascii_uppercase() {
+            'P' => Some(PieceType::Pawn),
+            'R' => Some(PieceType::Rook),
+            'N' => Some(PieceType::Knight),
+            'B' => Some(PieceType::Bishop),
+            'Q' => Some(PieceType::Queen),
+            'K' => Some(PieceType::King),
+            _ => None,
+        }
+    }
+}
+
+impl Move {
+    pub(crate) fn to_uci(self, from: (u8, u8)) -> String {
+        let promotion = self.promotion.and_then(|piece_type| piece_type.letter()).map(|letter| letter.to_ascii_lowercase());
+        format!("{}{}{}", Board::u8_coords_to_str(from), Board::u8_coords_to_str(self.to), promotion.map_or(String::new(), String::from))
+    }
+}
+
+impl fmt::Display for NotationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            NotationError::Invalid(notation) => write!(f, "'{}' is not a move in UCI or SAN notation", notation),
+            NotationError::Illegal(notation) => write!(f, "illegal move '{}'", notation),
+            NotationError::Ambiguous(notation) => write!(f, "ambiguous move '{}'", notation),
+        }
+    }
+}
+
+impl std::error::Error for NotationError {}
+
+fn is_uci(notation: &str) -> bool {
+    let bytes = notation.as_bytes();
+    (bytes.len() == 4 || bytes.len() == 5)
+        && (b'a'..=b'h').contains(&bytes[0]) && (b'1'..=b'8').contains(&bytes[1])
+        && (b'a'..=b'h').contains(&bytes[2]) && (b'1'..=b'8').contains(&bytes[3])
+        && (bytes.len() == 4 || b"qrbn".contains(&bytes[4]))
+}
+
+impl Board {
+    pub(crate) fn find_uci_move(&self, notation: &str) -> Option<(u8, u8, Move)> {
+        if !is_uci(notation) {
+            return None;
+        }
+        let from = Board::coords_to_u8(&notation[0..2]).ok()?;
+        let to = Board::coords_to_u8(&notation[2..4]).ok()?;
+        let promotion = notation.chars().nth(4).and_then(PieceType::from_letter);
+        self.find_move(from, to, promotion).map(|mv| (from.0, from.1, mv))
+    }
+
+    /**
+     * Standard Algebraic Notation of a legal move in the current position, e.g. "Nbd7", "exd5", "e8=Q+" or "O-O-O#".
+     */
+    pub(crate) fn san(&mut self, from: (u8, u8), mv: &Move) -> String {
+        let piece = self.board[from.0 as usize][from.1 as usize].expect("san: no piece on the starting square");
+        let mut san = if mv.castling {
+            if mv.to.1 == 6 {"O-O".to_string()} else {"O-O-O".to_string()}
+        } else {
+            let mut san = String::new();
+            // en passant captures land on an empty square
+            let capture = mv.capture.is_some() || (piece.piece_type == PieceType::Pawn && from.1 != mv.to.1);
+            match piece.piece_type.letter() {
+                Some(letter) => {
+                    san.push(letter);
+                    san.push_str(&self.disambiguation(from, mv));
+                },
+                None if capture => san.push((b'a' + from.1) as char),
+                None => {},
+            }
+            if capture {
+                san.push('x');
+            }
+            san.push_str(&Board::u8_coords_to_str(mv.to));
+            if let Some(letter) = mv.promotion.and_then(|piece_type| piece_type.letter()) {
+                san.push('=');
+                san.push(letter);
+            }
+            san
+        };
+
+        let prefetched_moves = self.prefetched_moves.take();
+        self.play_move(from, mv);
+        if self.is_check {
+            san.push(if self.get_all_possible_moves().is_empty() {'#'} else {'+'});
+        }
+        self.rollback_move();
+        self.prefetched_moves = prefetched_moves;
+        san
+    }
+
+    /**
+     * File, rank or both of the starting square, when other pieces of the same type can reach the same square.
+     */
+    fn disambiguation(&self, from: (u8, u8), mv: &Move) -> String {
+        let piece_type = self.board[from.0 as usize][from.1 as usize].map(|piece| piece.piece_type);
+        let rivals: Vec<(u8, u8)> = self.legal_moves().into_iter()
+            .filter(|(row, col, other)| (*row, *col) != from && other.to == mv.to && self.board[*row as usize][*col as usize].map(|piece| piece.piece_type) == piece_type)
+            .map(|(row, col, _)| (row, col))
+            .collect();
+        let square = Board::u8_coords_to_str(from);
+        if rivals.is_empty() {
+            String::new()
+        } else if rivals.iter().all(|rival| rival.1 != from.1) {
+            square[0..1].to_string()
+        } else if rivals.iter().all(|rival| rival.0 != from.0) {
+            square[1..2].to_string()
+        } else {
+            square
+        }
+    }
+
+    /**
+     * Resolve a move in UCI long algebraic notation ("e2e4", "e7e8q") or in SAN ("e4", "Nbd7", "exd8=Q+", "O-O") to the legal move it stands for.
+     * SAN is read leniently: check and annotation suffixes, "e.p.", a missing "x" or "=" and superfluous disambiguation are accepted.
+     */
+    pub fn parse_move(&self, notation: &str) -> Result<((u8, u8), Move), NotationError> {
+        if is_uci(notation) {
+            return match self.find_uci_move(notation) {
+                Some((row, col, mv)) => Ok(((row, col), mv)),
+                None => Err(NotationError::Illegal(notation.to_string())),
+            };
+        }
+
+        let san = notation.trim_end_matches("e.p.").trim_end().trim_end_matches(['+', '#', '!', '?']);
+        let moves = self.legal_moves();
+        let candidates: Vec<(u8, u8, Move)> = match san {
+            "O-O" | "0-0" => moves.into_iter().filter(|(_, _, mv)| mv.castling && mv.to.1 == 6).collect(),
+            "O-O-O" | "0-0-0" => moves.into_iter().filter(|(_, _, mv)| mv.castling && mv.to.1 == 2).collect(),
+            _ => {
+                let mut chars: Vec<char> = san.chars().filter(|c| !matches!(c, 'x' | ':' | '=' | '-')).collect();
+                let piece_type = match chars.first() {
+                    Some(c) if c.is_ascii_uppercase() => {
+                        let piece_type = PieceType::from_letter(*c).ok_or_else(|| NotationError::Invalid(notation.to_string()))?;
+                        chars.remove(0);
+                        piece_type
+                    },
+                    _ => PieceType::Pawn,
+                };
+                let promotion = match chars.len() {
+                    len if len >= 3 && chars[len - 1].is_ascii_alphabetic() && chars[len - 2].is_ascii_digit() => {
+                        let promotion = PieceType::from_letter(chars.pop().unwrap_or_default());
+                        match promotion {
+                            Some(PieceType::Pawn) | Some(PieceType::King) | None => return Err(NotationError::Invalid(notation.to_string())),
+                            promotion => promotion,
+                        }
+                    },
+                    _ => None,
+                };
+                if chars.len() < 2 || chars.len() > 4 {
+                    return Err(NotationError::Invalid(notation.to_string()));
+                }
+                let to: String = chars[chars.len() - 2..].iter().collect();
+                let to = Board::coords_to_u8(&to).map_err(|_| NotationError::Invalid(notation.to_string()))?;
+                let hints = &chars[..chars.len() - 2];
+                if !hints.iter().all(|c| ('a'..='h').contains(c) || ('1'..='8').contains(c)) {
+                    return Err(NotationError::Invalid(notation.to_string()));
+                }
+                moves.into_iter().filter(|(row, col, mv)| {
+                    let square = Board::u8_coords_to_str((*row, *col));
+                    !mv.castling
+                        && mv.to == to
+                        && mv.promotion == promotion
+                        && self.board[*row as usize][*col as usize].map(|piece| piece.piece_type) == Some(piece_type)
+                        && hints.iter().all(|hint| square.contains(*hint))
+                }).collect()
+            }
+        };
+        match candidates.as_slice() {
+            [] => Err(NotationError::Illegal(notation.to_string())),
+            [(row, col, mv)] => Ok(((*row, *col), *mv)),
+            _ => Err(NotationError::Ambiguous(notation.to_string())),
+        }
+    }
+
+    /**
+     * The move about to be played in SAN, preceded by the move number: "12. Nbd7" or "12... Nbd7".
+     */
+    pub(crate) fn numbered_san(&mut self, from: (u8, u8), mv: &Move) -> String {
+        let number = match self.turn {
+            PieceColor::White => format!("{}.", self.fullmove_number),
+            PieceColor::Black => format!("{}...", self.fullmove_number),
+        };
+        format!("{} {}", number, self.san(from, mv))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chess::{Board, NotationError};
+
+    fn parse(fen: &str, notation: &str) -> Result<String, NotationError> {
+        Board::from_fen(fen).unwrap().parse_move(notation).map(|(from, mv)| mv.to_uci(from))
+    }
+
+    fn san(fen: &str, uci: &str) -> String {
+        let mut board = Board::from_fen(fen).unwrap();
+        let (from, mv) = board.parse_move(uci).unwrap();
+        board.san(from, &mv)
+    }
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+    const PROMOTION: &str = "4k3/P7/8/8/8/8/8/4K3 w - - 0 1";
+    const EN_PASSANT: &str = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1";
+    const CASTLING: &str = "r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1";
+    // knights on b1 and f3 both reaching d2, rooks on a1 and a5 both reaching a3, queens on a1, c1 and a3 all reaching b2
+    const SAME_FILE_OR_RANK: &str = "4k3/8/8/R7/8/5N2/8/RN2K3 w - - 0 1";
+    const THREE_QUEENS: &str = "4k3/8/8/8/8/Q7/8/Q1Q1K3 w - - 0 1";
+
+    #[test]
+    fn parse_moves() {
+        for (fen, notation, expected) in [
+            (START, "e2e4", "e2e4"),
+            (START, "e4", "e2e4"),
+            (START, "Nf3", "g1f3"),
+            (PROMOTION, "a7a8q", "a7a8q"),
+            (PROMOTION, "a8=Q", "a7a8q"),
+            (PROMOTION, "a8Q+", "a7a8q"),
+            (PROMOTION, "a8=N", "a7a8n"),
+            (EN_PASSANT, "exd6", "e5d6"),
+            (EN_PASSANT, "exd6 e.p.", "e5d6"),
+            (EN_PASSANT, "exd6e.p.", "e5d6"),
+            (CASTLING, "O-O", "e1g1"),
+            (CASTLING, "0-0", "e1g1"),
+            (CASTLING, "O-O-O", "e1c1"),
+            (CASTLING, "0-0-0", "e1c1"),
+            (SAME_FILE_OR_RANK, "Nbd2", "b1d2"),
+            (SAME_FILE_OR_RANK, "Nfd2", "f3d2"),
+            (SAME_FILE_OR_RANK, "R1a3", "a1a3"),
+            (SAME_FILE_OR_RANK, "R5a3", "a5a3"),
+            (SAME_FILE_OR_RANK, "Rb5", "a5b5"),
+            (THREE_QUEENS, "Qa1b2", "a1b2"),
+        ] {
+            assert_eq!(parse(fen, notation), Ok(expected.to_string()), "{} in {}", notation, fen);
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        for (fen, notation, expected) in [
+            (SAME_FILE_OR_RANK, "Nd2", NotationError::Ambiguous("Nd2".to_string())),
+            (SAME_FILE_OR_RANK, "Ra3", NotationError::Ambiguous("Ra3".to_string())),
+            (THREE_QUEENS, "Qab2", NotationError::Ambiguous("Qab2".to_string())),
+            (START, "e2e5", NotationError::Illegal("e2e5".to_string())),
+            (START, "Ke2", NotationError::Illegal("Ke2".to_string())),
+            (START, "O-O", NotationError::Illegal("O-O".to_string())),
+            (PROMOTION, "a8", NotationError::Illegal("a8".to_string())),
+            (PROMOTION, "a8=K", NotationError::Invalid("a8=K".to_string())),
+            (START, "Zf3", NotationError::Invalid("Zf3".to_string())),
+            (START, "e9", NotationError::Invalid("e9".to_string())),
+            (START, "hello", NotationError::Invalid("hello".to_string())),
+        ] {
+            assert_eq!(parse(fen, notation), Err(expected), "{} in {}", notation, fen);
+        }
+    }
+
+    #[test]
+    fn san_of_moves() {
+        for (fen, uci, expected) in [
+            (START, "g1f3", "Nf3"),
+            ("r1bqkb1r/pppp1ppp/2n2n2/4p2Q/2B1P3/8/PPPP1PPP/RNB1K1NR w KQkq - 4 4", "h5f7", "Qxf7#"),
+            ("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1", "a1a8", "Ra8+"),
+            ("4k3/8/8/8/8/8/8/R3K3 w Q - 0 1", "e1c1", "O-O-O"),
+            (PROMOTION, "a7a8q", "a8=Q+"),
+            (PROMOTION, "a7a8n", "a8=N"),
+            (EN_PASSANT, "e5d6", "exd6"),
+            (SAME_FILE_OR_RANK, "b1d2", "Nbd2"),
+            (SAME_FILE_OR_RANK, "a1a3", "R1a3"),
+            (SAME_FILE_OR_RANK, "a5b5", "Rb5"),
+            (THREE_QUEENS, "a1b2", "Qa1b2"),
+            (THREE_QUEENS, "a3b2", "Q3b2"),
+            (THREE_QUEENS, "c1b2", "Qcb2"),
+        ] {
+            assert_eq!(san(fen, uci), expected, "{} in {}", uci, fen);
+        }
+    }
+
+    #[test]
+    fn numbered_san_follows_the_side_to_move() {
+        let mut board = Board::new();
+        let mut record = Vec::new();
+        for notation in ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Bxc6", "dxc6", "O-O", "Bg4"] {
+            let (from, mv) = board.parse_move(notation).unwrap();
+            record.push(board.numbered_san(from, &mv));
+            board.play_move(from, &mv);
+        }
+        assert_eq!(record.join(" "), "1. e4 1... e5 2. Nf3 2... Nc6 3. Bb5 3... a6 4. Bxc6 4... dxc6 5. O-O 5... Bg4");
+    }
+}
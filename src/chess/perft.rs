@@ -0,0 +1,78 @@
+use crate::chess::Board;
+
+impl Board {
+    /**
+     * Count the leaf nodes of the legal move tree at the given depth.
+     * Comparing these numbers against published results is the standard way to validate a move generator.
+     */
+    pub fn perft(&mut self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = self.get_all_possible_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        let mut nodes = 0;
+        for (row, col, mv) in moves {
+            self.play_move((row, col), &mv);
+            nodes += self.perft(depth - 1);
+            self.rollback_move();
+        }
+        nodes
+    }
+
+    /**
+     * Same as perft, but the count is split by root move, in long algebraic notation.
+     * This makes it easy to find which subtree disagrees with a reference engine.
+     */
+    pub fn divide(&mut self, depth: u32) -> Vec<(String, u64)> {
+        let mut result = Vec::new();
+        if depth == 0 {
+            return result;
+        }
+        for (row, col, mv) in self.get_all_possible_moves() {
+            self.play_move((row, col), &mv);
+            result.push((mv.to_uci((row, col)), self.perft(depth - 1)));
+            self.rollback_move();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chess::Board;
+
+    // positions, depths and node counts from the Chess Programming Wiki, and from Martin Sedlak's suite for the en passant edge cases
+    const PERFT_POSITIONS: [(&str, u32, u64); 10] = [
+        ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 5, 4865609),
+        ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 4, 4085603),
+        ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 5, 674624),
+        ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 4, 422333),
+        ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 4, 2103487),
+        ("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", 4, 3894594),
+        // captures which would expose the own king along the rank or a diagonal, and captures giving discovered check
+        ("3k4/3p4/8/K1P4r/8/8/8/8 b - - 0 1", 6, 1134888),
+        ("8/8/4k3/8/2p5/8/B2P2K1/8 w - - 0 1", 6, 1015133),
+        ("8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1", 6, 1440467),
+        ("8/5bk1/8/2Pp4/8/1K6/8/8 w - d6 0 1", 6, 824064),
+    ];
+
+    #[test]
+    fn published_node_counts() {
+        for (fen, depth, expected) in PERFT_POSITIONS {
+            let mut board = Board::from_fen(fen).unwrap();
+            assert_eq!(board.perft(depth), expected, "{} depth {}", fen, depth);
+        }
+    }
+
+    #[test]
+    fn divide_sums_to_perft() {
+        let mut board = Board::from_fen(PERFT_POSITIONS[1].0).unwrap();
+        let divided = board.divide(3);
+        assert_eq!(divided.len(), 48);
+        assert_eq!(divided.iter().map(|(_, nodes)| nodes).sum::<u64>(), 97862);
+        assert!(divided.iter().any(|(uci, _)| uci == "e1g1"));
+    }
+}
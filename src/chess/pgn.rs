@@ -0,0 +1,359 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::chess::{Board, GameOutcome, PgnError, PgnGame, PieceColor};
+
+const STANDARD_START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+// the tags every exported game starts with, in the order required by the PGN standard, with their "unknown" values
+const SEVEN_TAG_ROSTER: [(&str, &str); 7] = [
+    ("Event", "?"),
+    ("Site", "?"),
+    ("Date", "????.??.??"),
+    ("Round", "?"),
+    ("White", "?"),
+    ("Black", "?"),
+    ("Result", "*"),
+];
+// tags export_pgn derives from the game itself
+const DERIVED_TAGS: [&str; 3] = ["Result", "SetUp", "FEN"];
+const RESULTS: [&str; 4] = ["1-0", "0-1", "1/2-1/2", "*"];
+const MAX_LINE_LENGTH: usize = 79;
+
+impl fmt::Display for PgnError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PgnError::TagPair(tag) => write!(f, "malformed tag pair '[{}]'", tag),
+            PgnError::Unterminated(open) => write!(f, "'{}' is never closed", open),
+            PgnError::Fen(error) => write!(f, "FEN tag: {}", error),
+            PgnError::Move { ply, error } => write!(f, "half-move {}: {}", ply, error),
+        }
+    }
+}
+
+impl std::error::Error for PgnError {}
+
+/**
+ * Result token of a game: "1-0", "0-1", "1/2-1/2", or "*" while it is still going on.
+ */
+pub(crate) fn result_token(outcome: Option<&GameOutcome>) -> &'static str {
+    match outcome {
+        Some(GameOutcome::Checkmate(PieceColor::White)) | Some(GameOutcome::Timeout(PieceColor::White)) => "0-1",
+        Some(GameOutcome::Checkmate(PieceColor::Black)) | Some(GameOutcome::Timeout(PieceColor::Black)) => "1-0",
+        Some(GameOutcome::Draw(_)) => "1/2-1/2",
+        _ => "*",
+    }
+}
+
+/**
+ * Today's (UTC) date in the "YYYY.MM.DD" format of the Date tag.
+ */
+pub(crate) fn pgn_date() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs() / 86400) as i64;
+    // days since 1970-01-01 to year/month/day in the proleptic Gregorian calendar (Howard Hinnant's civil_from_days)
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {shifted_month + 3} else {shifted_month - 9};
+    let year = year_of_era + era * 400 + if month <= 2 {1} else {0};
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+type TagPairs = Vec<(String, String)>;
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+/**
+ * Parse the inside of a tag pair, e.g. 'White "Leone, Andrea"'.
+ */
+fn parse_tag(tag: &str) -> Result<(String, String), PgnError> {
+    let error = || PgnError::TagPair(tag.to_string());
+    let (name, value) = tag.trim().split_once(char::is_whitespace).ok_or_else(error)?;
+    let value = value.trim();
+    if name.is_empty() || value.len() < 2 || !value.starts_with('"') || !value.ends_with('"') {
+        return Err(error());
+    }
+    let mut unescaped = String::new();
+    let mut chars = value[1..value.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        unescaped.push(if c == '\\' {chars.next().ok_or_else(error)?} else {c});
+    }
+    Ok((name.to_string(), unescaped))
+}
+
+/**
+ * Split the text into the tag pairs and the movetext tokens of its first game.
+ * Comments, escaped lines and variations are dropped; move numbers, NAGs and the result are left to the caller.
+ */
+fn tokenize(pgn: &str) -> Result<(TagPairs, Vec<String>), PgnError> {
+    let mut tags = Vec::new();
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut variation_depth = 0;
+    let mut line_start = true;
+    let mut chars = pgn.chars();
+    while let Some(c) = chars.next() {
+        if !c.is_whitespace() && !matches!(c, '{' | ';' | '(' | ')' | '[' | '%') {
+            token.push(c);
+            line_start = false;
+            continue;
+        }
+        if !token.is_empty() && variation_depth == 0 {
+            tokens.push(token.clone());
+        }
+        token.clear();
+        match c {
+            '{' if !chars.by_ref().any(|c| c == '}') => return Err(PgnError::Unterminated('{')),
+            // both end with their line, so what follows starts a line
+            ';' => {
+                chars.by_ref().find(|c| *c == '\n');
+                line_start = true;
+                continue;
+            },
+            '%' if line_start => {
+                chars.by_ref().find(|c| *c == '\n');
+                line_start = true;
+                continue;
+            },
+            '(' => variation_depth += 1,
+            ')' => variation_depth = 0.max(variation_depth - 1),
+            '[' if tokens.is_empty() && variation_depth == 0 => {
+                let mut tag = String::new();
+                let mut quoted = false;
+                loop {
+                    match chars.next() {
+                        Some(']') if !quoted => break,
+                        Some('\\') if quoted => {
+                            tag.push('\\');
+                            tag.extend(chars.next());
+                        },
+                        Some(c) => {
+                            quoted ^= c == '"';
+                            tag.push(c);
+                        },
+                        None => return Err(PgnError::Unterminated('[')),
+                    }
+                }
+                tags.push(parse_tag(&tag)?);
+            },
+            // the tag section of the next game
+            '[' => break,
+            _ => {},
+        }
+        line_start = c == '\n' || (line_start && c.is_whitespace());
+    }
+    if variation_depth > 0 {
+        return Err(PgnError::Unterminated('('));
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    Ok((tags, tokens))
+}
+
+/**
+ * Strip a leading move number ("12.", "12...") from a movetext token; tokens which are only a number become empty.
+ */
+fn strip_move_number(token: &str) -> &str {
+    let rest = token.trim_start_matches(|c: char| c.is_ascii_digit());
+    if rest.len() == token.len() {
+        token
+    } else if rest.is_empty() || rest.starts_with('.') {
+        rest.trim_start_matches('.')
+    } else {
+        // castling written with zeros
+        token
+    }
+}
+
+impl Board {
+    /**
+     * Export the game played on this board, from the position the board was created with, as PGN.
+     * tags override or extend the seven tag roster (later pairs win over earlier ones with the same name).
+     * Result and the termination marker are derived from outcome, SetUp and FEN from the starting position.
+     */
+    pub fn export_pgn(&mut self, tags: &[(&str, &str)], outcome: Option<&GameOutcome>) -> String {
+        let mut moves = Vec::new();
+        while let Some(data) = self.history.last() {
+            moves.push(((data.starting_row, data.starting_col), data.mv));
+            self.rollback_move();
+        }
+        moves.reverse();
+        let start_fen = self.to_fen();
+
+        let mut movetext = Vec::new();
+        for (i, (from, mv)) in moves.iter().enumerate() {
+            match self.turn {
+                PieceColor::White => movetext.push(format!("{}.", self.fullmove_number)),
+                PieceColor::Black if i == 0 => movetext.push(format!("{}...", self.fullmove_number)),
+                PieceColor::Black => {},
+            }
+            movetext.push(self.san(*from, mv));
+            self.play_move(*from, mv);
+        }
+        let result = result_token(outcome);
+        movetext.push(result.to_string());
+
+        let mut pairs: TagPairs = SEVEN_TAG_ROSTER.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        for (name, value) in tags.iter().filter(|(name, _)| !DERIVED_TAGS.contains(name)) {
+            match pairs.iter_mut().find(|(existing, _)| existing == name) {
+                Some(pair) => pair.1 = value.to_string(),
+                None => pairs.push((name.to_string(), value.to_string())),
+            }
+        }
+        if let Some(pair) = pairs.iter_mut().find(|(name, _)| name == "Result") {
+            pair.1 = result.to_string();
+        }
+        if start_fen != STANDARD_START {
+            pairs.push(("SetUp".to_string(), "1".to_string()));
+            pairs.push(("FEN".to_string(), start_fen));
+        }
+
+        let mut pgn = String::new();
+        for (name, value) in pairs {
+            pgn.push_str(&format!("[{} \"{}\"]\n", name, escape(&value)));
+        }
+        pgn.push('\n');
+        let mut line = String::new();
+        for token in movetext {
+            if !line.is_empty() && line.len() + 1 + token.len() > MAX_LINE_LENGTH {
+                pgn.push_str(&line);
+                pgn.push('\n');
+                line.clear();
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&token);
+        }
+        pgn.push_str(&line);
+        pgn.push('\n');
+        pgn
+    }
+
+    /**
+     * Read the first game of a PGN text and replay its moves, starting from the FEN tag if there is one.
+     * Comments, NAGs, variations and move numbers are skipped; the game ends at its result token or at the end of the text.
+     */
+    pub fn from_pgn(pgn: &str) -> Result<PgnGame, PgnError> {
+        let (tags, tokens) = tokenize(pgn)?;
+        let mut board = match tags.iter().find(|(name, _)| name == "FEN") {
+            Some((_, fen)) => Board::from_fen_lenient(fen).map_err(PgnError::Fen)?,
+            None => Board::new(),
+        };
+        let mut ply = 0;
+        for token in tokens.iter() {
+            if RESULTS.contains(&token.as_str()) {
+                break;
+            }
+            let notation = strip_move_number(token);
+            if notation.is_empty() || notation.starts_with('$') || notation.chars().all(|c| c == '!' || c == '?') {
+                continue;
+            }
+            ply += 1;
+            let (from, mv) = board.parse_move(notation).map_err(|error| PgnError::Move { ply, error })?;
+            board.play_move(from, &mv);
+        }
+        board.prefetched_moves = Some(board.get_all_possible_moves());
+        Ok(PgnGame { tags, board })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chess::{Board, DrawType, GameOutcome, PgnError, PieceColor};
+
+    fn play(board: &mut Board, moves: &str) {
+        for notation in moves.split_whitespace() {
+            let (from, mv) = board.parse_move(notation).unwrap();
+            board.play_move(from, &mv);
+        }
+    }
+
+    fn tag<'a>(tags: &'a [(String, String)], name: &str) -> Option<&'a str> {
+        tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    #[test]
+    fn export_import_round_trip() {
+        // castling both ways, en passant and a promotion with capture
+        let mut board = Board::new();
+        play(&mut board, "e4 d5 exd5 c5 dxc6 Nf6 cxb7 e6 bxa8=Q Be7 Nf3 O-O Bb5 Bd7 O-O");
+        let end_fen = board.to_fen();
+        let pgn = board.export_pgn(&[("White", "Leone"), ("Round", "3")], Some(&GameOutcome::Draw(DrawType::ThreefoldRepetition)));
+        assert!(pgn.starts_with("[Event \"?\"]\n[Site \"?\"]\n"), "{}", pgn);
+        assert!(pgn.contains("[Round \"3\"]\n[White \"Leone\"]\n[Black \"?\"]\n[Result \"1/2-1/2\"]\n"), "{}", pgn);
+        assert!(pgn.contains("3. dxc6 Nf6 "), "{}", pgn);
+        assert!(pgn.contains("5. bxa8=Q "), "{}", pgn);
+        assert!(!pgn.contains("[SetUp"), "{}", pgn);
+        assert!(pgn.trim_end().ends_with("1/2-1/2"), "{}", pgn);
+        let game = Board::from_pgn(&pgn).unwrap();
+        assert_eq!(game.board.to_fen(), end_fen);
+        assert_eq!(tag(&game.tags, "White"), Some("Leone"));
+
+        // from a position with Black to move: the first move is numbered "40...", and the start goes in SetUp and FEN
+        let start = "4k3/1P6/8/8/8/8/6p1/4K2R b K - 3 40";
+        let mut board = Board::from_fen(start).unwrap();
+        play(&mut board, "g1=N Kf1 Ke7 b8=Q");
+        let end_fen = board.to_fen();
+        let pgn = board.export_pgn(&[], None);
+        assert!(pgn.contains("[SetUp \"1\"]\n[FEN \"4k3/1P6/8/8/8/8/6p1/4K2R b K - 3 40\"]\n"), "{}", pgn);
+        assert!(pgn.contains("\n40... g1=N 41. Kf1 Ke7 42. b8=Q *\n"), "{}", pgn);
+        let game = Board::from_pgn(&pgn).unwrap();
+        assert_eq!(game.board.to_fen(), end_fen);
+        assert_eq!(tag(&game.tags, "FEN"), Some(start));
+    }
+
+    #[test]
+    fn comments_nags_and_variations_are_skipped() {
+        let pgn = "1. e4 {the best by test} e5 $1 2. Nf3!? (2. f4 exf4 (2... d5 3. exd5) 3. Nf3 {gambit}) 2... Nc6 ; comment\n3. Bb5 a6?! *";
+        let mut expected = Board::new();
+        play(&mut expected, "e4 e5 Nf3 Nc6 Bb5 a6");
+        assert_eq!(Board::from_pgn(pgn).unwrap().board.to_fen(), expected.to_fen());
+    }
+
+    #[test]
+    fn tags_are_escaped() {
+        let name = "Leone, \"Andrea\" \\ [x]";
+        let pgn = Board::new().export_pgn(&[("White", name)], Some(&GameOutcome::Checkmate(PieceColor::Black)));
+        assert!(pgn.contains("[White \"Leone, \\\"Andrea\\\" \\\\ [x]\"]\n"), "{}", pgn);
+        let game = Board::from_pgn(&pgn).unwrap();
+        assert_eq!(tag(&game.tags, "White"), Some(name));
+        assert_eq!(tag(&game.tags, "Result"), Some("1-0"));
+    }
+
+    #[test]
+    fn only_the_first_game_is_read() {
+        let pgn = "[Event \"first\"]\n\n1. e4 e5 1-0\n\n[Event \"second\"]\n\n1. d4 d5 2. c4 0-1\n";
+        let game = Board::from_pgn(pgn).unwrap();
+        let mut expected = Board::new();
+        play(&mut expected, "e4 e5");
+        assert_eq!(game.board.to_fen(), expected.to_fen());
+        assert_eq!(game.tags, vec![("Event".to_string(), "first".to_string())]);
+    }
+
+    #[test]
+    fn escaped_lines_are_skipped() {
+        let mut expected = Board::new();
+        play(&mut expected, "e4 e5 Nf3");
+        for pgn in ["%escaped line\n1. e4 e5\n%escaped line\n2. Nf3 *", "1. e4 e5 ; x\n%escaped line\n2. Nf3 *"] {
+            assert_eq!(Board::from_pgn(pgn).unwrap().board.to_fen(), expected.to_fen(), "{:?}", pgn);
+        }
+        // only at the start of a line
+        assert!(matches!(Board::from_pgn("1. e4 e5 %x 2. Nf3 *"), Err(PgnError::Move { ply: 3, .. })));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(Board::from_pgn("1. e4 {unclosed e5 *").err(), Some(PgnError::Unterminated('{')));
+        assert_eq!(Board::from_pgn("1. e4 (1. d4 e5 *").err(), Some(PgnError::Unterminated('(')));
+        assert_eq!(Board::from_pgn("[White Leone]\n1. e4 *").err(), Some(PgnError::TagPair("White Leone".to_string())));
+        assert!(matches!(Board::from_pgn("1. e4 e5 2. Ke3 *"), Err(PgnError::Move { ply: 3, .. })));
+        assert!(matches!(Board::from_pgn("[FEN \"8/8 w\"]\n*"), Err(PgnError::Fen(_))));
+    }
+}
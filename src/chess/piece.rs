@@ -1,4 +1,5 @@
-use crate::chess::{PieceColor, PieceType, Piece, Move, Board};
+use crate::chess::{PieceColor, PieceType, Piece, Move, MoveKind, Board};
+use crate::chess::bitboard::{self, Bitboard};
 
 impl Move {
     pub(crate) fn new(to: (u8, u8), capture: Option<Piece>, promotion: Option<PieceType>) -> Self {
@@ -20,6 +21,10 @@ impl Move {
         }
     
     }
+
+    pub(crate) fn is_tactical(&self) -> bool {
+        self.capture.is_some() || self.promotion.is_some()
+    }
 }
 
 impl PieceColor {
@@ -37,6 +42,13 @@ impl PieceColor {
         }
     }
     
+    pub(crate) fn index(&self) -> usize {
+        match self {
+            PieceColor::White => 0,
+            PieceColor::Black => 1,
+        }
+    }
+
     pub(crate) fn opposite(&self) -> Self {
         match self {
             PieceColor::White => PieceColor::Black,
@@ -46,16 +58,6 @@ impl PieceColor {
 }
 
 impl PieceType {
-    fn value(&self) -> usize {
-        match self {
-            PieceType::Pawn => 1,
-            PieceType::Rook => 5,
-            PieceType::Knight => 3,
-            PieceType::Bishop => 3,
-            PieceType::Queen => 9,
-            PieceType::King => 0,
-        }
-    }
     pub fn id(&self) -> u8 {
         match self {
             PieceType::Pawn => 1,
@@ -66,6 +68,16 @@ impl PieceType {
             PieceType::King => 32,
         }
     }
+    pub(crate) fn index(&self) -> usize {
+        match self {
+            PieceType::Pawn => 0,
+            PieceType::Knight => 1,
+            PieceType::Bishop => 2,
+            PieceType::Rook => 3,
+            PieceType::Queen => 4,
+            PieceType::King => 5,
+        }
+    }
     fn img_index(&self) -> i32 {
         match self {
             PieceType::Pawn => 5,
@@ -123,13 +135,23 @@ impl Piece {
         }
     }
 
-    pub(crate) fn get_value(&self) -> usize {
-        self.piece_type.value()
-    }
-    pub(crate) fn get_potential_value(&self) -> usize {
-        match self.piece_type {
-            PieceType::Pawn => PieceType::Queen.value(),
-            _ => self.get_value(),
+    /**
+     * Unicode chess symbol of the piece, for text diagrams.
+     */
+    pub(crate) fn symbol(&self) -> char {
+        match (self.color, self.piece_type) {
+            (PieceColor::White, PieceType::Pawn) => '♙',
+            (PieceColor::Black, PieceType::Pawn) => '♟',
+            (PieceColor::White, PieceType::Rook) => '♖',
+            (PieceColor::Black, PieceType::Rook) => '♜',
+            (PieceColor::White, PieceType::Knight) => '♘',
+            (PieceColor::Black, PieceType::Knight) => '♞',
+            (PieceColor::White, PieceType::Bishop) => '♗',
+            (PieceColor::Black, PieceType::Bishop) => '♝',
+            (PieceColor::White, PieceType::Queen) => '♕',
+            (PieceColor::Black, PieceType::Queen) => '♛',
+            (PieceColor::White, PieceType::King) => '♔',
+            (PieceColor::Black, PieceType::King) => '♚',
         }
     }
 
@@ -182,8 +204,13 @@ impl Piece {
         }
     }
 
-    pub(crate) fn generate_moves(&self, board: &Board, checking_pieces: &Vec<(u8, u8)>) -> Vec<Move> {
+    /**
+     * Legal moves of the piece of the given kind. pins are the pinned pieces of its color, as returned by Board::pinned_lines.
+     */
+    pub(crate) fn generate_moves(&self, board: &Board, checking_pieces: &Vec<(u8, u8)>, pins: &[Bitboard; 4], kind: MoveKind) -> Vec<Move> {
         let mut result = Vec::new();
+        let tactical = kind != MoveKind::Quiet;
+        let quiet = kind != MoveKind::Tactical;
         let king_position = match self.color {
             PieceColor::White => board.white_king_pos,
             PieceColor::Black => board.black_king_pos,
@@ -192,46 +219,41 @@ impl Piece {
             return result;
         }
 
-        let row_pinned = board.row_pin(self.row as usize, self.col as usize, 
-            PieceType::King.id() | self.color.id(),
-            PieceType::Queen.id() | PieceType::Rook.id() | self.color.opposite().id());
-        let col_pinned = board.col_pin(self.row as usize, self.col as usize,
-            PieceType::King.id() | self.color.id(),
-            PieceType::Queen.id() | PieceType::Rook.id() | self.color.opposite().id());
-        let back_diag_pinned = board.back_diag_pin(self.row as usize, self.col as usize,
-            PieceType::King.id() | self.color.id(),
-            PieceType::Queen.id() | PieceType::Bishop.id() | self.color.opposite().id());
-        let forward_diag_pinned = board.forward_diag_pin(self.row as usize, self.col as usize,
-            PieceType::King.id() | self.color.id(),
-            PieceType::Queen.id() | PieceType::Bishop.id() | self.color.opposite().id());
+        let square = 1 << bitboard::square(self.row, self.col);
+        let row_pinned = pins[0] & square != 0;
+        let col_pinned = pins[1] & square != 0;
+        let back_diag_pinned = pins[2] & square != 0;
+        let forward_diag_pinned = pins[3] & square != 0;
         let diag_pinned = back_diag_pinned || forward_diag_pinned;
         //println!("row_pinned: {}, col_pinned: {}, diag_pinned: {}", row_pinned, col_pinned, diag_pinned);
         
         match self.piece_type {
             PieceType::Pawn => {
-                if !(row_pinned || diag_pinned) {
+                if !row_pinned {
                     let (delta, start_row, promotion_row): (i32, u8, usize) = match self.color {
                         PieceColor::White => (-1, 6u8, 0),
                         PieceColor::Black => (1, 1u8, 7),
                     };
                     let (mut r, c) = ((self.row as i32 + delta) as usize, self.col as usize);
-                    if board.board[r][c].is_none() && (!board.is_check || self.solves_check(board, (r as u8, c as u8), king_position, &checking_pieces[0])) {
+                    if !diag_pinned && board.board[r][c].is_none() && (!board.is_check || self.solves_check(board, (r as u8, c as u8), king_position, &checking_pieces[0])) {
                         if r == promotion_row {
-                            result.push(Move::new((r as u8, c as u8), None, Some(PieceType::Queen)));
-                            result.push(Move::new((r as u8, c as u8), None, Some(PieceType::Rook)));
-                            result.push(Move::new((r as u8, c as u8), None, Some(PieceType::Bishop)));
-                            result.push(Move::new((r as u8, c as u8), None, Some(PieceType::Knight)));
-                        } else  {
+                            if tactical {
+                                result.push(Move::new((r as u8, c as u8), None, Some(PieceType::Queen)));
+                                result.push(Move::new((r as u8, c as u8), None, Some(PieceType::Rook)));
+                                result.push(Move::new((r as u8, c as u8), None, Some(PieceType::Bishop)));
+                                result.push(Move::new((r as u8, c as u8), None, Some(PieceType::Knight)));
+                            }
+                        } else if quiet {
                             result.push(Move::new((r as u8, c as u8), None, None));
                         }
                     }
-                    if self.row == start_row {
+                    if quiet && !diag_pinned && self.row == start_row && board.board[r][c].is_none() {
                         r = (r as i32 + delta) as usize;
                         if board.board[r][c].is_none() && (!board.is_check || self.solves_check(board, (r as u8, c as u8), king_position, &checking_pieces[0])) {
                             result.push(Move::new((r as u8, c as u8), None, None));
                         }
                     }
-                    if !col_pinned {
+                    if tactical && !col_pinned {
                         let captures: Vec<i32> = match (self.color, back_diag_pinned, forward_diag_pinned) {
                             (PieceColor::White, true, false) => vec![-1],
                             (PieceColor::White, false, true) => vec![1],
@@ -243,7 +265,15 @@ impl Piece {
                         for dc in captures {
                             let r = (self.row as i32 + delta) as usize;
                             let c = (self.col as i32 + dc) as usize;
-                            if r < Board::ROWS && c < Board::COLS && (!board.is_check || self.solves_check(board, (r as u8, c as u8), king_position, &checking_pieces[0])) {
+                            if r >= Board::ROWS || c >= Board::COLS {
+                                continue;
+                            }
+                            if board.en_passant == Some((r as u8, c as u8)) {
+                                // the captured pawn stands next to ours, not on the target square
+                                if board.en_passant_is_legal((self.row, self.col), (r as u8, c as u8), self.color) {
+                                    result.push(Move::new((r as u8, c as u8), board.board[self.row as usize][c], None));
+                                }
+                            } else if !board.is_check || self.solves_check(board, (r as u8, c as u8), king_position, &checking_pieces[0]) {
                                 if let Some(piece) = &board.board[r][c] {
                                     if piece.color != self.color {
                                         if r == promotion_row {
@@ -255,10 +285,6 @@ impl Piece {
                                             result.push(Move::new((r as u8, c as u8), board.board[r][c], None));
                                         }
                                     }
-                                } else if let Some(en_passant) = board.en_passant {
-                                    if en_passant == (r as u8, c as u8) {
-                                        result.push(Move::new((r as u8, c as u8), board.board[en_passant.0 as usize][en_passant.1 as usize], None));
-                                    }
                                 }
                             }
                         }
@@ -273,11 +299,11 @@ impl Piece {
                         let c = (self.col as i8 + dc) as usize;
                         if r < 8 && c < 8 && (!board.is_check || self.solves_check(board, (r as u8, c as u8), king_position, &checking_pieces[0])) {
                             if let Some(occupying) = &board.board[r][c] {
-                                if occupying.color != self.color {
+                                if tactical && occupying.color != self.color {
                                     result.push(Move::new((r as u8, c as u8), board.board[r][c], None));
                                 }
-                            } else {
-                                result.push(Move::new((r as u8, c as u8), board.board[r][c], None));
+                            } else if quiet {
+                                result.push(Move::new((r as u8, c as u8), None, None));
                             }
                         }
                     }
@@ -297,17 +323,14 @@ impl Piece {
                     for (dr, dc) in directions {
                         let (mut r, mut c) = (self.row as i8 + dr, self.col as i8 + dc);
                         while (0..Board::ROWS as i8).contains(&r) && (0..Board::COLS as i8).contains(&c) {
-                            if !board.is_check || self.solves_check(board, (r as u8, c as u8), king_position, &checking_pieces[0]) {
-                                if let Some(occupying) = &board.board[r as usize][c as usize] {
-                                    if occupying.color == self.color {
-                                        break;
-                                    } else {
-                                        result.push(Move::new((r as u8, c as u8), board.board[r as usize][c as usize], None));
-                                        break;
-                                    }
-                                } else {
-                                    result.push(Move::new((r as u8, c as u8), None, None));
-                                }    
+                            let solves_check = !board.is_check || self.solves_check(board, (r as u8, c as u8), king_position, &checking_pieces[0]);
+                            if let Some(occupying) = &board.board[r as usize][c as usize] {
+                                if tactical && occupying.color != self.color && solves_check {
+                                    result.push(Move::new((r as u8, c as u8), board.board[r as usize][c as usize], None));
+                                }
+                                break;
+                            } else if quiet && solves_check {
+                                result.push(Move::new((r as u8, c as u8), None, None));
                             }
                             r += dr;
                             c += dc;
@@ -329,17 +352,14 @@ impl Piece {
                     for (dr, dc) in directions {
                         let (mut r, mut c) = (self.row as i8 + dr, self.col as i8 + dc);
                         while (0..Board::ROWS as i8).contains(&r) && (0..Board::COLS as i8).contains(&c) {
-                            if !board.is_check || self.solves_check(board, (r as u8, c as u8), king_position, &checking_pieces[0]) {
-                                if let Some(occupying) = &board.board[r as usize][c as usize] {
-                                    if occupying.color == self.color {
-                                        break;
-                                    } else {
-                                        result.push(Move::new((r as u8, c as u8), board.board[r as usize][c as usize], None));
-                                        break;
-                                    }
-                                } else {
-                                    result.push(Move::new((r as u8, c as u8), None, None));
+                            let solves_check = !board.is_check || self.solves_check(board, (r as u8, c as u8), king_position, &checking_pieces[0]);
+                            if let Some(occupying) = &board.board[r as usize][c as usize] {
+                                if tactical && occupying.color != self.color && solves_check {
+                                    result.push(Move::new((r as u8, c as u8), board.board[r as usize][c as usize], None));
                                 }
+                                break;
+                            } else if quiet && solves_check {
+                                result.push(Move::new((r as u8, c as u8), None, None));
                             }
                             r += dr;
                             c += dc;
@@ -361,17 +381,14 @@ impl Piece {
                     for (dr, dc) in directions {
                         let (mut r, mut c) = (self.row as i8 + dr, self.col as i8 + dc);
                         while (0..Board::ROWS as i8).contains(&r) && (0..Board::COLS as i8).contains(&c) {
-                            if !board.is_check || self.solves_check(board, (r as u8, c as u8), king_position, &checking_pieces[0]) {
-                                if let Some(occupying) = &board.board[r as usize][c as usize] {
-                                    if occupying.color == self.color {
-                                        break;
-                                    } else {
-                                        result.push(Move::new((r as u8, c as u8), board.board[r as usize][c as usize], None));
-                                        break;
-                                    }
-                                } else {
-                                    result.push(Move::new((r as u8, c as u8), None, None));
+                            let solves_check = !board.is_check || self.solves_check(board, (r as u8, c as u8), king_position, &checking_pieces[0]);
+                            if let Some(occupying) = &board.board[r as usize][c as usize] {
+                                if tactical && occupying.color != self.color && solves_check {
+                                    result.push(Move::new((r as u8, c as u8), board.board[r as usize][c as usize], None));
                                 }
+                                break;
+                            } else if quiet && solves_check {
+                                result.push(Move::new((r as u8, c as u8), None, None));
                             }
                             r += dr;
                             c += dc;
@@ -391,17 +408,14 @@ impl Piece {
                     for (dr, dc) in directions {
                         let (mut r, mut c) = (self.row as i8 + dr, self.col as i8 + dc);
                         while (0..Board::ROWS as i8).contains(&r) && (0..Board::COLS as i8).contains(&c) {
-                            if !board.is_check || self.solves_check(board, (r as u8, c as u8), king_position, &checking_pieces[0]) {
-                                if let Some(occupying) = &board.board[r as usize][c as usize] {
-                                    if occupying.color == self.color {
-                                        break;
-                                    } else {
-                                        result.push(Move::new((r as u8, c as u8), board.board[r as usize][c as usize], None));
-                                        break;
-                                    }
-                                } else {
-                                    result.push(Move::new((r as u8, c as u8), None, None));
-                                }    
+                            let solves_check = !board.is_check || self.solves_check(board, (r as u8, c as u8), king_position, &checking_pieces[0]);
+                            if let Some(occupying) = &board.board[r as usize][c as usize] {
+                                if tactical && occupying.color != self.color && solves_check {
+                                    result.push(Move::new((r as u8, c as u8), board.board[r as usize][c as usize], None));
+                                }
+                                break;
+                            } else if quiet && solves_check {
+                                result.push(Move::new((r as u8, c as u8), None, None));
                             }
                             r += dr;
                             c += dc;
@@ -414,54 +428,55 @@ impl Piece {
                 for (dr, dc) in directions.iter() {
                     let r = (self.row as i8 + dr) as usize;
                     let c = (self.col as i8 + dc) as usize;
-                    if r < 8 && c < 8 && board.get_attacking_pieces(r, c, &self.color, true).is_empty() {
-                        if let Some(occupying) = &board.board[r][c] {
-                            if occupying.color != self.color {
-                                result.push(Move::new((r as u8, c as u8), board.board[r][c], None));
-                            }
-                        } else {
-                            result.push(Move::new((r as u8, c as u8), None, None));
-                        }
+                    if r >= 8 || c >= 8 {
+                        continue;
+                    }
+                    let wanted = match &board.board[r][c] {
+                        Some(occupying) => tactical && occupying.color != self.color,
+                        None => quiet,
+                    };
+                    if wanted && !board.is_attacked(r, c, &self.color) {
+                        result.push(Move::new((r as u8, c as u8), board.board[r][c], None));
                     }
                 }
                 // println!("{:?} {:?} {:?} {:?}", board.wk_castle, board.wq_castle, board.bk_castle, board.bq_castle);
                 // castling
                 match self.color {
                     PieceColor::White => {
-                        if board.wk_castle 
-                           && board.get_attacking_pieces(7, 4, &self.color, true).is_empty()
+                        if quiet && board.wk_castle 
+                           && !board.is_attacked(7, 4, &self.color)
                            && board.board[7][5].is_none()
                            && board.board[7][6].is_none()
-                           && board.get_attacking_pieces(7, 5, &self.color, true).is_empty()
-                           && board.get_attacking_pieces(7, 6, &self.color, true).is_empty() {
+                           && !board.is_attacked(7, 5, &self.color)
+                           && !board.is_attacked(7, 6, &self.color) {
                             result.push(Move::castle((7, 6), (7, 5)));
                         }
-                        if board.wq_castle
-                           && board.get_attacking_pieces(7, 4, &self.color, true).is_empty()
+                        if quiet && board.wq_castle
+                           && !board.is_attacked(7, 4, &self.color)
                            && board.board[7][1].is_none()
                            && board.board[7][2].is_none()
                            && board.board[7][3].is_none()
-                           && board.get_attacking_pieces(7, 2, &self.color, true).is_empty()
-                           && board.get_attacking_pieces(7, 3, &self.color, true).is_empty() {
+                           && !board.is_attacked(7, 2, &self.color)
+                           && !board.is_attacked(7, 3, &self.color) {
                             result.push(Move::castle((7, 2), (7, 3)));
                         }
                     },
                     PieceColor::Black => {
-                        if board.bk_castle
-                           && board.get_attacking_pieces(0, 4, &self.color, true).is_empty()
+                        if quiet && board.bk_castle
+                           && !board.is_attacked(0, 4, &self.color)
                            && board.board[0][5].is_none()
                            && board.board[0][6].is_none()
-                           && board.get_attacking_pieces(0, 5, &self.color, true).is_empty()
-                           && board.get_attacking_pieces(0, 6, &self.color, true).is_empty() {
+                           && !board.is_attacked(0, 5, &self.color)
+                           && !board.is_attacked(0, 6, &self.color) {
                             result.push(Move::castle((0, 6), (0, 5)));
                         }
-                        if board.bq_castle
-                           && board.get_attacking_pieces(0, 4, &self.color, true).is_empty()
+                        if quiet && board.bq_castle
+                           && !board.is_attacked(0, 4, &self.color)
                            && board.board[0][1].is_none()
                            && board.board[0][2].is_none()
                            && board.board[0][3].is_none()
-                           && board.get_attacking_pieces(0, 2, &self.color, true).is_empty()
-                           && board.get_attacking_pieces(0, 3, &self.color, true).is_empty() {
+                           && !board.is_attacked(0, 2, &self.color)
+                           && !board.is_attacked(0, 3, &self.color) {
                             result.push(Move::castle((0, 2), (0, 3)));
                         }
                     },
@@ -0,0 +1,522 @@
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::chess::{Board, EvalWeights, Move, OpeningBook, PieceColor, PieceType, Tablebase, TranspositionTable};
+use crate::chess::syzygy::Wdl;
+use crate::chess::eval;
+use crate::chess::movepick::{MovePicker, OrderingTables};
+use crate::chess::time::{TimeControl, TimeManager, TimeSource};
+use crate::chess::tt::{self, Bound};
+
+pub const MAX_DEPTH: u32 = 64;
+pub const MATE_SCORE: i32 = 30000;
+pub const MAX_THREADS: usize = 256;
+// score of a position the tablebases say is won, below the mate scores since the mate itself is still to be found
+pub(crate) const TB_WIN_SCORE: i32 = MATE_SCORE - 2 * MAX_DEPTH as i32;
+const INFINITY: i32 = 32000;
+// how often (in nodes) the clock and the stop flag are polled
+const CHECK_INTERVAL: u64 = 1024;
+// a capture is not searched by quiescence when even winning this much on top of the captured piece would not raise alpha
+const DELTA_MARGIN: i32 = 200;
+
+#[derive(Clone, Default)]
+pub struct SearchLimits {
+    pub depth: Option<u32>,
+    pub movetime: Option<Duration>,
+    // clock of the side to move, which the time manager turns into time limits for this move
+    pub time_control: Option<TimeControl>,
+    pub stop: Option<Arc<AtomicBool>>,
+    // the wall clock if None
+    pub time_source: Option<Arc<dyn TimeSource>>,
+    // probed before searching, a move found there is played without searching
+    pub book: Option<Arc<OpeningBook>>,
+    // probed at the root to play the moves keeping the best result, and in the search to cut off positions with few pieces
+    pub tablebase: Option<Arc<Tablebase>>,
+    // threads searching the position together (lazy SMP), one if 0
+    pub threads: usize,
+    pub eval_weights: EvalWeights,
+}
+
+/**
+ * Where the move of a search result comes from.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MoveSource {
+    Search,
+    // nothing was searched
+    Book,
+    Tablebase,
+}
+
+#[derive(Clone)]
+pub(crate) struct SearchResult {
+    pub(crate) best_move: Option<(u8, u8, Move)>,
+    pub(crate) score: i32,
+    pub(crate) depth: u32,
+    pub(crate) nodes: u64,
+    pub(crate) elapsed: Duration,
+    pub(crate) pv: Vec<(u8, u8, Move)>,
+    // permille of the transposition table in use
+    pub(crate) hashfull: usize,
+    pub(crate) source: MoveSource,
+}
+
+impl SearchResult {
+    /**
+     * Number of moves to the mate found by the search (negative if the side to move is getting mated), if any.
+     */
+    pub(crate) fn mate_in(&self) -> Option<i32> {
+        if self.score.abs() < MATE_SCORE - MAX_DEPTH as i32 {
+            return None;
+        }
+        let plies = MATE_SCORE - self.score.abs();
+        Some(if self.score > 0 {(plies + 1) / 2} else {-(plies + 1) / 2})
+    }
+}
+
+/**
+ * Score of a position with the given tablebase result, ply plies from the root: the sooner the win, the better.
+ * Cursed wins and blessed losses are draws, as the search applies the 50-move rule.
+ */
+fn tablebase_score(wdl: Wdl, ply: u32) -> i32 {
+    match wdl {
+        Wdl::Win => TB_WIN_SCORE - ply as i32,
+        Wdl::Loss => -TB_WIN_SCORE + ply as i32,
+        _ => 0,
+    }
+}
+
+struct Searcher<'a> {
+    board: &'a mut Board,
+    limits: &'a SearchLimits,
+    tt: &'a TranspositionTable,
+    tables: OrderingTables,
+    // moves leading from the root to the current node, packed
+    played: Vec<u16>,
+    time: TimeManager,
+    nodes: u64,
+    // nodes of all the threads of the search, added in batches of CHECK_INTERVAL
+    shared_nodes: &'a AtomicU64,
+    stopped: bool,
+}
+
+impl<'a> Searcher<'a> {
+    fn new(board: &'a mut Board, limits: &'a SearchLimits, tt: &'a TranspositionTable, shared_nodes: &'a AtomicU64) -> Self {
+        Self {
+            board,
+            limits,
+            tt,
+            tables: OrderingTables::new(),
+            played: Vec::new(),
+            time: TimeManager::new(limits),
+            nodes: 0,
+            shared_nodes,
+            stopped: false,
+        }
+    }
+
+    /**
+     * Count a node; every CHECK_INTERVAL nodes, add them to the nodes of all threads and check whether the search has to stop.
+     */
+    fn count_node(&mut self) {
+        self.nodes += 1;
+        if self.nodes.is_multiple_of(CHECK_INTERVAL) {
+            self.shared_nodes.fetch_add(CHECK_INTERVAL, Ordering::Relaxed);
+            if self.should_stop() {
+                self.stopped = true;
+            }
+        }
+    }
+
+    /**
+     * Nodes searched so far by all the threads, give or take the ones not added yet by the other threads.
+     */
+    fn total_nodes(&self) -> u64 {
+        self.shared_nodes.load(Ordering::Relaxed) + self.nodes % CHECK_INTERVAL
+    }
+
+    /**
+     * Add the last nodes of the thread to the nodes of all threads, once it is done searching.
+     */
+    fn finish(&self) {
+        self.shared_nodes.fetch_add(self.nodes % CHECK_INTERVAL, Ordering::Relaxed);
+    }
+
+    fn should_stop(&self) -> bool {
+        if let Some(stop) = &self.limits.stop {
+            if stop.load(Ordering::Relaxed) {
+                return true;
+            }
+        }
+        self.time.out_of_time()
+    }
+
+    /**
+     * Search captures and promotions only, until the position is quiet, so that the evaluation is never taken in the middle of an exchange.
+     * The side to move may also "stand pat" on the static evaluation instead of capturing, except when in check, where every evasion is searched.
+     * Captures losing material by static exchange evaluation are skipped, as are those which cannot raise alpha even with a margin (delta pruning).
+     */
+    fn quiescence(&mut self, mut alpha: i32, beta: i32, ply: u32) -> i32 {
+        self.count_node();
+        if self.stopped {
+            return 0;
+        }
+        if ply >= MAX_DEPTH {
+            return self.board.evaluate(&self.limits.eval_weights);
+        }
+
+        let in_check = self.board.is_check;
+        let mut best = -INFINITY;
+        let mut stand_pat = 0;
+        if !in_check {
+            stand_pat = self.board.evaluate(&self.limits.eval_weights);
+            if stand_pat >= beta {
+                return stand_pat;
+            }
+            alpha = alpha.max(stand_pat);
+            best = stand_pat;
+        }
+        let mut picker = if in_check {MovePicker::new(0, [0; 2], 0)} else {MovePicker::tactical()};
+
+        while let Some((row, col, mv)) = picker.next(self.board, &self.tables) {
+            if !in_check {
+                let gain = mv.capture.map_or(0, |piece| eval::piece_value(piece.piece_type))
+                    + mv.promotion.map_or(0, |piece_type| eval::piece_value(piece_type) - eval::piece_value(PieceType::Pawn));
+                if stand_pat + gain + DELTA_MARGIN <= alpha {
+                    continue;
+                }
+            }
+            self.board.play_move((row, col), &mv);
+            let score = -self.quiescence(-beta, -alpha, ply + 1);
+            self.board.rollback_move();
+            if self.stopped {
+                return 0;
+            }
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                    if alpha >= beta {
+                        break;
+                    }
+                }
+            }
+        }
+        // when in check, every evasion was searched; otherwise a stalemate is left to the main search
+        if best == -INFINITY {
+            return -MATE_SCORE + ply as i32;
+        }
+        best
+    }
+
+    fn negamax(&mut self, depth: u32, mut alpha: i32, beta: i32, ply: u32, pv: &mut Vec<(u8, u8, Move)>, pv_move: Option<&(u8, u8, Move)>) -> i32 {
+        pv.clear();
+        self.count_node();
+        if self.stopped {
+            return 0;
+        }
+        if ply > 0 && self.board.insufficient_material() {
+            return 0;
+        }
+        // a checkmate given by the move reaching the 50-move limit still wins
+        if ply > 0 && self.board.halfmove_clock >= 100 {
+            let mated = self.board.is_check && self.board.get_all_possible_moves().is_empty();
+            return if mated {-MATE_SCORE + ply as i32} else {0};
+        }
+        if depth == 0 {
+            return self.quiescence(alpha, beta, ply);
+        }
+
+        let key = self.board.hash();
+        let hit = self.tt.probe(key, ply);
+        if let Some(hit) = hit {
+            // at the root the move has to be searched anyway, to know which one it is
+            if ply > 0 && hit.depth >= depth {
+                match hit.bound {
+                    Bound::Exact => return hit.score,
+                    Bound::Lower if hit.score >= beta => return hit.score,
+                    Bound::Upper if hit.score <= alpha => return hit.score,
+                    _ => {},
+                }
+            }
+        }
+
+        // with few pieces left, the tablebases give the result under perfect play; only right after a capture or a pawn move,
+        // since the 50-move rule could change it otherwise
+        if let Some(tablebase) = self.limits.tablebase.as_ref().filter(|_| ply > 0 && self.board.halfmove_clock == 0) {
+            if let Some(wdl) = self.board.probe_wdl(tablebase) {
+                let score = tablebase_score(wdl, ply);
+                let bound = match wdl {
+                    Wdl::Win => Bound::Lower,
+                    Wdl::Loss => Bound::Upper,
+                    _ => Bound::Exact,
+                };
+                if bound == Bound::Exact || (bound == Bound::Lower && score >= beta) || (bound == Bound::Upper && score <= alpha) {
+                    self.tt.store(key, MAX_DEPTH - 1, score, ply, bound, 0);
+                    return score;
+                }
+            }
+        }
+
+        let hash_move = match pv_move {
+            Some((row, col, mv)) => tt::pack_move((*row, *col), mv),
+            None => hit.map_or(0, |hit| hit.best_move),
+        };
+        let previous = self.played.last().copied().unwrap_or(0);
+        let mut picker = MovePicker::new(hash_move, self.tables.killers(ply), self.tables.counter_move(previous));
+
+        let original_alpha = alpha;
+        let mut line = Vec::new();
+        let mut best = -INFINITY;
+        let mut best_move = 0;
+        // quiet moves which did not cause a cutoff, to lower their history if a later one does
+        let mut quiets_tried = Vec::new();
+        while let Some((row, col, mv)) = picker.next(self.board, &self.tables) {
+            let packed = tt::pack_move((row, col), &mv);
+            let repetition = self.board.play_move((row, col), &mv);
+            self.played.push(packed);
+            let score = if repetition {
+                0
+            } else {
+                -self.negamax(depth - 1, -beta, -alpha, ply + 1, &mut line, None)
+            };
+            self.played.pop();
+            self.board.rollback_move();
+            if self.stopped {
+                return 0;
+            }
+            if score > best {
+                best = score;
+                if score > alpha {
+                    alpha = score;
+                    best_move = packed;
+                    pv.clear();
+                    pv.push((row, col, mv));
+                    pv.extend_from_slice(&line);
+                    if alpha >= beta {
+                        if !mv.is_tactical() {
+                            self.tables.update(self.board.side_to_move(), ply, depth, previous, packed, &quiets_tried);
+                        }
+                        break;
+                    }
+                }
+            }
+            if !mv.is_tactical() {
+                quiets_tried.push(packed);
+            }
+        }
+        if best == -INFINITY {
+            return if self.board.is_check {-MATE_SCORE + ply as i32} else {0};
+        }
+        let bound = if best >= beta {
+            Bound::Lower
+        } else if best > original_alpha {
+            Bound::Exact
+        } else {
+            Bound::Upper
+        };
+        self.tt.store(key, depth, best, ply, bound, best_move);
+        best
+    }
+}
+
+impl Searcher<'_> {
+    /**
+     * Iterative deepening on a helper thread of a parallel search, until stopped: its results only reach the main thread through
+     * the transposition table. Odd helpers search one ply deeper than the main thread at each iteration, so that the threads
+     * do not all search the same tree at the same time.
+     */
+    fn help(&mut self, id: usize, max_depth: u32) {
+        let mut pv_move = None;
+        for depth in 1..=max_depth {
+            let mut pv = Vec::new();
+            self.negamax((depth + id as u32 % 2).min(max_depth), -INFINITY, INFINITY, 0, &mut pv, pv_move.as_ref());
+            if self.stopped {
+                break;
+            }
+            pv_move = pv.first().copied();
+        }
+        self.finish();
+    }
+}
+
+impl Board {
+    pub(crate) fn side_to_move(&self) -> PieceColor {
+        self.turn
+    }
+
+    /**
+     * Iterative deepening negamax search with alpha-beta pruning.
+     * Every completed iteration is reported through on_iteration; the result of the deepest completed iteration is returned.
+     * No new iteration is started past the soft time limit of the time manager, and an iteration still running at the hard limit is abandoned.
+     * If the limits expire before the first iteration completes, the first legal move is returned.
+     * Results are stored in tt, which keeps them for the following searches.
+     * With more than one thread in the limits, helper threads search the same position on copies of the board (lazy SMP): sharing tt,
+     * each finds many positions already searched by the others, so the main thread, whose result is returned, gets deeper in the same time.
+     * When the limits have an opening book with a move for the position, that move is returned at once, with depth 0.
+     * So is the best move according to the tablebases of the limits, with TB_WIN_SCORE for a win (0 when the 50-move rule saves the game).
+     */
+    pub(crate) fn search<F: FnMut(&SearchResult)>(&mut self, limits: &SearchLimits, tt: &TranspositionTable, mut on_iteration: F) -> SearchResult {
+        if let Some(book_move) = limits.book.as_ref().and_then(|book| self.book_move(book, &mut rand::thread_rng())) {
+            return SearchResult {
+                best_move: Some(book_move),
+                score: 0,
+                depth: 0,
+                nodes: 0,
+                elapsed: Duration::ZERO,
+                pv: vec![book_move],
+                hashfull: tt.hashfull(),
+                source: MoveSource::Book,
+            };
+        }
+        if let Some((tablebase_move, wdl)) = limits.tablebase.as_ref().and_then(|tablebase| self.tablebase_move(tablebase)) {
+            return SearchResult {
+                best_move: Some(tablebase_move),
+                score: tablebase_score(wdl, 0),
+                depth: 0,
+                nodes: 0,
+                elapsed: Duration::ZERO,
+                pv: vec![tablebase_move],
+                hashfull: tt.hashfull(),
+                source: MoveSource::Tablebase,
+            };
+        }
+        let max_depth = limits.depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH);
+        tt.new_search();
+        let shared_nodes = AtomicU64::new(0);
+        let mut searcher = Searcher::new(self, limits, tt, &shared_nodes);
+        let mut result = SearchResult {
+            best_move: searcher.board.get_all_possible_moves().first().copied(),
+            score: 0,
+            depth: 0,
+            nodes: 0,
+            elapsed: Duration::ZERO,
+            pv: Vec::new(),
+            hashfull: 0,
+            source: MoveSource::Search,
+        };
+        if result.best_move.is_none() {
+            return result;
+        }
+
+        // the helpers are not limited by time: they stop when the main thread is done
+        let done = Arc::new(AtomicBool::new(false));
+        let helper_limits = SearchLimits {
+            depth: limits.depth,
+            stop: Some(done.clone()),
+            tablebase: limits.tablebase.clone(),
+            eval_weights: limits.eval_weights,
+            ..Default::default()
+        };
+        thread::scope(|scope| {
+            let helpers: Vec<_> = (1..limits.threads.clamp(1, MAX_THREADS)).map(|id| {
+                let mut board = searcher.board.clone();
+                let (helper_limits, shared_nodes) = (&helper_limits, &shared_nodes);
+                scope.spawn(move || Searcher::new(&mut board, helper_limits, tt, shared_nodes).help(id, max_depth))
+            }).collect();
+            for depth in 1..=max_depth {
+                let mut pv = Vec::new();
+                let previous_best = result.pv.first().copied();
+                let score = searcher.negamax(depth, -INFINITY, INFINITY, 0, &mut pv, previous_best.as_ref());
+                if searcher.stopped || pv.is_empty() {
+                    break;
+                }
+                result = SearchResult {
+                    best_move: pv.first().copied(),
+                    score,
+                    depth,
+                    nodes: searcher.total_nodes(),
+                    elapsed: searcher.time.elapsed(),
+                    pv,
+                    hashfull: searcher.tt.hashfull(),
+                    source: MoveSource::Search,
+                };
+                on_iteration(&result);
+                // stop once the mate is proven: a mate further away than the depth comes from the transposition table, and a shorter one may exist
+                if result.mate_in().is_some() && MATE_SCORE - score.abs() <= depth as i32 {
+                    break;
+                }
+                let (row, col, mv) = result.pv[0];
+                if !searcher.time.iteration_done(tt::pack_move((row, col), &mv), score) {
+                    break;
+                }
+            }
+            done.store(true, Ordering::Relaxed);
+            for helper in helpers {
+                helper.join().expect("search thread panicked");
+            }
+        });
+        searcher.finish();
+        result.nodes = shared_nodes.load(Ordering::Relaxed);
+        result.elapsed = searcher.time.elapsed();
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chess::{Board, SearchLimits, TranspositionTable};
+    use super::MATE_SCORE;
+
+    fn best_move(fen: &str, depth: u32) -> (String, i32) {
+        let mut board = Board::from_fen(fen).unwrap();
+        let result = board.search(&SearchLimits { depth: Some(depth), ..Default::default() }, &TranspositionTable::new(1), |_| {});
+        let (row, col, mv) = result.best_move.expect("no best move");
+        (mv.to_uci((row, col)), result.score)
+    }
+
+    // positions with a single good move: two where the most valuable capture loses the queen, so only quiescence sees the recapture
+    // past depth 1, and four from Win at Chess
+    const SEARCH_POSITIONS: [(&str, u32, &str); 6] = [
+        ("7k/8/1p3n2/r7/8/2Q5/8/7K w - - 0 1", 1, "c3f6"),
+        ("7k/8/2q5/8/R7/1P3N2/8/7K b - - 0 1", 1, "c6f3"),
+        ("2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1", 5, "g3g6"),
+        ("5rk1/1ppb3p/p1pb4/6q1/3P1p1r/2P1R2P/PP1BQ1P1/5RKN w - - 0 1", 5, "e3g3"),
+        ("r1bq2rk/pp3pbp/2p1p1pQ/7P/3P4/2PB1N2/PP3PPR/2KR4 w - - 0 1", 5, "h6h7"),
+        ("5k2/6pp/p1qN4/1p1p4/3P4/2PKP2Q/PP3r2/3R4 b - - 0 1", 5, "c6c4"),
+    ];
+
+    // forced mates in two, the last two with enough pieces for the helper threads to search positions of their own
+    const MATE_IN_TWO_POSITIONS: [&str; 3] = [
+        "kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1",
+        "r2qkb1r/pp2nppp/3p4/2pNN1B1/2BnP3/3P4/PPP2PPP/R2bK2R w KQkq - 1 1",
+        "6k1/pp4p1/2p5/2bp4/8/P5Pb/1P3rrP/2BRRN1K b - - 0 1",
+    ];
+
+    #[test]
+    fn finds_the_best_move() {
+        for (fen, depth, expected) in SEARCH_POSITIONS {
+            assert_eq!(best_move(fen, depth).0, expected, "{} depth {}", fen, depth);
+        }
+    }
+
+    #[test]
+    fn deepening_stops_at_a_proven_mate() {
+        let mut board = Board::from_fen("kbK5/pp6/1P6/8/8/8/8/R7 w - - 0 1").unwrap();
+        let result = board.search(&SearchLimits { depth: Some(10), ..Default::default() }, &TranspositionTable::new(1), |_| {});
+        assert_eq!((result.score, result.mate_in(), result.depth), (MATE_SCORE - 3, Some(2), 3));
+    }
+
+    #[test]
+    fn threads_find_the_same_mate() {
+        for fen in MATE_IN_TWO_POSITIONS {
+            let mut board = Board::from_fen(fen).unwrap();
+            let legal: Vec<String> = board.get_all_possible_moves().iter().map(|(row, col, mv)| mv.to_uci((*row, *col))).collect();
+            for threads in [1, 2, 4] {
+                let limits = SearchLimits { depth: Some(5), threads, ..Default::default() };
+                let result = board.search(&limits, &TranspositionTable::new(1), |_| {});
+                let (row, col, mv) = result.best_move.expect("no best move");
+                assert!(legal.contains(&mv.to_uci((row, col))), "{} with {} threads: {} is not legal", fen, threads, mv.to_uci((row, col)));
+                assert_eq!(result.score, MATE_SCORE - 3, "{} with {} threads", fen, threads);
+            }
+        }
+    }
+
+    #[test]
+    fn mate_on_the_hundredth_halfmove_wins() {
+        // Qb8 is mate and brings the halfmove clock to 100
+        assert_eq!(best_move("7k/8/6K1/8/8/8/8/1Q6 w - - 99 80", 2), ("b1b8".to_string(), MATE_SCORE - 1));
+    }
+}
@@ -0,0 +1,99 @@
+use crate::chess::{Board, Move, PieceColor, PieceType};
+use crate::chess::bitboard::{self, bishop_attacks, rook_attacks, Bitboard, KING_ATTACKS, KNIGHT_ATTACKS, PAWN_ATTACKS};
+use crate::chess::eval::piece_value;
+
+// attackers are always taken from the least valuable one
+const ATTACKER_ORDER: [PieceType; 6] = [PieceType::Pawn, PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen, PieceType::King];
+
+impl Board {
+    /**
+     * Pieces of both colors attacking the square, with only the pieces in occupied blocking the sliders.
+     */
+    fn attackers_through(&self, square: usize, occupied: Bitboard) -> Bitboard {
+        let both = |piece_type| self.pieces_of(PieceColor::White, piece_type) | self.pieces_of(PieceColor::Black, piece_type);
+        let queens = both(PieceType::Queen);
+        let attackers = (KNIGHT_ATTACKS[square] & both(PieceType::Knight))
+            | (KING_ATTACKS[square] & both(PieceType::King))
+            | (rook_attacks(square, occupied) & (both(PieceType::Rook) | queens))
+            | (bishop_attacks(square, occupied) & (both(PieceType::Bishop) | queens))
+            // a white pawn attacks the square if a black pawn on the square would attack it, and the other way around
+            | (PAWN_ATTACKS[PieceColor::Black.index()][square] & self.pieces_of(PieceColor::White, PieceType::Pawn))
+            | (PAWN_ATTACKS[PieceColor::White.index()][square] & self.pieces_of(PieceColor::Black, PieceType::Pawn));
+        attackers & occupied
+    }
+
+    /**
+     * Static exchange evaluation: material won (in centipawns, negative if lost) by the side to move when playing mv and then
+     * both sides keep capturing on the target square with their least valuable attacker, each one free to stop when going on would lose more.
+     * Sliders lined up behind a capturing piece join the exchange once it has left (x-rays). The king only captures last.
+     * Pins and checks are ignored, as are promotions by recapturing pawns.
+     */
+    pub fn see(&self, from: (u8, u8), mv: &Move) -> i32 {
+        let target = bitboard::square(mv.to.0, mv.to.1);
+        let mut occupied = self.occupied() & !(1 << bitboard::square(from.0, from.1));
+        // an en passant capture takes a pawn which is not on the target square
+        if mv.capture.is_some() && self.board[mv.to.0 as usize][mv.to.1 as usize].is_none() {
+            occupied &= !(1 << bitboard::square(from.0, mv.to.1));
+        }
+        let mut on_target = match mv.promotion {
+            Some(piece_type) => piece_type,
+            None => self.board[from.0 as usize][from.1 as usize].expect("no piece to move").piece_type,
+        };
+
+        // gains[i] is the material won by the side making the i-th capture if the exchange stops right after it
+        let mut gains = [0; 32];
+        gains[0] = mv.capture.map_or(0, |piece| piece_value(piece.piece_type))
+            + mv.promotion.map_or(0, |piece_type| piece_value(piece_type) - piece_value(PieceType::Pawn));
+        let mut depth = 0;
+        let mut side = self.turn.opposite();
+        loop {
+            let attackers = self.attackers_through(target, occupied);
+            let own = attackers & self.occupancy[side.index()];
+            let Some(attacker) = ATTACKER_ORDER.iter().find(|piece_type| own & self.pieces_of(side, **piece_type) != 0) else {
+                break;
+            };
+            // the king cannot capture a defended piece
+            if *attacker == PieceType::King && attackers & self.occupancy[side.opposite().index()] != 0 {
+                break;
+            }
+            depth += 1;
+            gains[depth] = piece_value(on_target) - gains[depth - 1];
+            let square = (own & self.pieces_of(side, *attacker)).trailing_zeros();
+            occupied &= !(1 << square);
+            on_target = *attacker;
+            side = side.opposite();
+        }
+        // going back from the last capture, each side only captures if it gains more than by stopping
+        while depth > 0 {
+            gains[depth - 1] = -(-gains[depth - 1]).max(gains[depth]);
+            depth -= 1;
+        }
+        gains[0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chess::Board;
+
+    // static exchange evaluations, with the middlegame piece values of the evaluation (pawn 82, knight 337, bishop 365, rook 477, queen 1025)
+    const SEE_POSITIONS: [(&str, &str, i32); 8] = [
+        ("1k1r4/1pp4p/p7/4p3/8/P5P1/1PP4P/2K1R3 w - - 0 1", "e1e5", 82),
+        ("1k1r3q/1ppn3p/p4b2/4p3/8/P2N2P1/1PP1R1BP/2K1Q3 w - - 0 1", "d3e5", -255),
+        ("4k3/4r3/8/4p3/8/8/4R3/4RK2 w - - 0 1", "e2e5", 82),
+        ("4k3/8/5p2/4n3/8/8/8/4R1K1 w - - 0 1", "e1e5", -140),
+        ("8/8/8/3pk3/8/8/8/3RK3 w - - 0 1", "d1d5", -395),
+        ("8/8/8/3pk3/8/8/3R4/3RK3 w - - 0 1", "d2d5", 82),
+        ("4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1", "e5d6", 82),
+        ("1r2k3/P7/8/8/8/8/8/4K3 w - - 0 1", "a7a8q", -82),
+    ];
+
+    #[test]
+    fn exchange_values() {
+        for (fen, notation, expected) in SEE_POSITIONS {
+            let board = Board::from_fen(fen).unwrap();
+            let (from, mv) = board.parse_move(notation).unwrap();
+            assert_eq!(board.see(from, &mv), expected, "{} {}", fen, notation);
+        }
+    }
+}
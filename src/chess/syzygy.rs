@@ -0,0 +1,1421 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use crate::chess::{Board, Move, PieceColor, PieceType};
+use crate::chess::bitboard;
+
+// first bytes of the WDL (.rtbw) and DTZ (.rtbz) files
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+// no table has more pieces, kings included
+const MAX_PIECES: usize = 7;
+// flags of a compressed table: the side to move of a DTZ table, DTZ values mapped through a table (of 16-bit values if wide),
+// wins and losses counted in plies rather than moves, and every position having the same value
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+// no move is ever this far from a zeroing move, see Board::tablebase_move
+const MAX_DTZ: i32 = 1 << 18;
+
+/**
+ * Outcome of a position with perfect play, for the side to move. A cursed win takes more than 50 moves without capture or pawn move,
+ * so it is a draw under the 50-move rule; a blessed loss is the same from the other side.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum Wdl {
+    Loss = -2,
+    BlessedLoss = -1,
+    Draw = 0,
+    CursedWin = 1,
+    Win = 2,
+}
+
+impl Wdl {
+    fn from_value(value: i32) -> Option<Self> {
+        match value {
+            -2 => Some(Wdl::Loss),
+            -1 => Some(Wdl::BlessedLoss),
+            0 => Some(Wdl::Draw),
+            1 => Some(Wdl::CursedWin),
+            2 => Some(Wdl::Win),
+            _ => None,
+        }
+    }
+
+    fn opposite(self) -> Self {
+        Self::from_value(-(self as i32)).unwrap()
+    }
+
+    /**
+     * DTZ of a position whose best move is a capture or a pawn move.
+     */
+    fn dtz_before_zeroing(self) -> i32 {
+        match self {
+            Wdl::Win => 1,
+            Wdl::CursedWin => 101,
+            Wdl::Draw => 0,
+            Wdl::BlessedLoss => -101,
+            Wdl::Loss => -1,
+        }
+    }
+}
+
+/**
+ * Tables turning the squares of the pieces into the index of the position in a table, as the generator of the tables does.
+ * Squares are numbered from a1 (0) to h8 (63) here, unlike on the board.
+ */
+struct Encoding {
+    // squares a2-h7 to 0-47, the highest values to the pawns nearest to the edge and to the first rank, the first of which leads
+    map_pawns: [usize; 64],
+    // squares below the a1-h8 diagonal to 0-27
+    map_b1h1h7: [usize; 64],
+    // squares of the a1-d1-d4 triangle to 0-9, the ones on the diagonal last
+    map_a1d1d4: [usize; 64],
+    // the 462 placements of two kings which are not mirrors of each other, by the square of the first in the triangle and the square of the second
+    map_kk: [[usize; 64]; 10],
+    // binomial[k][n]: ways to choose k squares out of n
+    binomial: [[u64; 64]; 6],
+    // index of the leading pawns, by their number and the square of the leading one, and the number of such indices by file
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+// how far a square is above the a1-h8 diagonal (negative below it)
+const fn off_diagonal(square: usize) -> i32 {
+    (square >> 3) as i32 - (square & 7) as i32
+}
+
+const fn generate_encoding() -> Encoding {
+    let mut encoding = Encoding {
+        map_pawns: [0; 64],
+        map_b1h1h7: [0; 64],
+        map_a1d1d4: [0; 64],
+        map_kk: [[0; 64]; 10],
+        binomial: [[0; 64]; 6],
+        lead_pawn_idx: [[0; 64]; 6],
+        lead_pawns_size: [[0; 4]; 6],
+    };
+    let mut code = 0;
+    let mut square = 0;
+    while square < 64 {
+        if off_diagonal(square) < 0 {
+            encoding.map_b1h1h7[square] = code;
+            code += 1;
+        }
+        square += 1;
+    }
+
+    code = 0;
+    let mut on_diagonal = 0;
+    while on_diagonal < 2 {
+        square = 0;
+        while square < 32 {
+            if square & 7 < 4 && (off_diagonal(square) == 0) == (on_diagonal == 1) && off_diagonal(square) <= 0 {
+                encoding.map_a1d1d4[square] = code;
+                code += 1;
+            }
+            square += 1;
+        }
+        on_diagonal += 1;
+    }
+
+    // when the first king is on the diagonal, the second one is not above it; placements with both on the diagonal come last
+    code = 0;
+    on_diagonal = 0;
+    while on_diagonal < 2 {
+        let mut idx = 0;
+        while idx < 10 {
+            let mut first = 0;
+            // up to d4
+            while first <= 27 {
+                // b1 is the square mapped to 0, the other squares outside the triangle are too
+                if encoding.map_a1d1d4[first] == idx && (idx != 0 || first == 1) {
+                    let mut second = 0;
+                    while second < 64 {
+                        let adjacent = (first >> 3).abs_diff(second >> 3) <= 1 && (first & 7).abs_diff(second & 7) <= 1;
+                        let above = off_diagonal(first) == 0 && off_diagonal(second) > 0;
+                        let both_on_diagonal = off_diagonal(first) == 0 && off_diagonal(second) == 0;
+                        if !adjacent && !above && both_on_diagonal == (on_diagonal == 1) {
+                            encoding.map_kk[idx][second] = code;
+                            code += 1;
+                        }
+                        second += 1;
+                    }
+                }
+                first += 1;
+            }
+            idx += 1;
+        }
+        on_diagonal += 1;
+    }
+
+    encoding.binomial[0][0] = 1;
+    let mut n = 1;
+    while n < 64 {
+        let mut k = 0;
+        while k < 6 && k <= n {
+            encoding.binomial[k][n] = if k > 0 {encoding.binomial[k - 1][n - 1]} else {0} + if k < n {encoding.binomial[k][n - 1]} else {0};
+            k += 1;
+        }
+        n += 1;
+    }
+
+    // with the leading pawn on a square, the other pawns of the group can only be on the squares with a lower map_pawns
+    let mut available = 48;
+    let mut count = 1;
+    while count <= 5 {
+        let mut file = 0;
+        while file < 4 {
+            let mut idx = 0;
+            let mut rank = 1;
+            while rank <= 6 {
+                square = rank * 8 + file;
+                if count == 1 {
+                    available -= 1;
+                    encoding.map_pawns[square] = available;
+                    available -= 1;
+                    encoding.map_pawns[square ^ 7] = available;
+                }
+                encoding.lead_pawn_idx[count][square] = idx;
+                idx += encoding.binomial[count - 1][encoding.map_pawns[square]];
+                rank += 1;
+            }
+            encoding.lead_pawns_size[count][file] = idx;
+            file += 1;
+        }
+        count += 1;
+    }
+    encoding
+}
+
+const ENCODING: Encoding = generate_encoding();
+
+fn read_u8(data: &[u8], offset: usize) -> Option<u8> {
+    data.get(offset).copied()
+}
+
+fn read_u16_le(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32_le(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_u32_be(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn read_u64_be(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TableKind {
+    Wdl,
+    Dtz,
+}
+
+/**
+ * Pieces of a table, from its file name: "KRPvKR" has a white king, rook and pawn against a black king and rook.
+ */
+struct Material {
+    name: String,
+    pieces: usize,
+    has_pawns: bool,
+    // some piece other than a king is alone of its kind and color
+    has_unique_pieces: bool,
+    // pawns of the leading color (the only color with pawns, or the one with fewer) and of the other
+    pawn_count: [usize; 2],
+    // both sides have the same pieces, so the table only has white to move
+    symmetric: bool,
+}
+
+impl Material {
+    fn parse(name: &str) -> Option<Self> {
+        let (white, black) = name.split_once('v')?;
+        for side in [white, black] {
+            if !side.starts_with('K') || side[1..].contains('K') || !side.chars().all(|c| "KQRBNP".contains(c)) {
+                return None;
+            }
+        }
+        let pieces = white.len() + black.len();
+        if pieces > MAX_PIECES {
+            return None;
+        }
+        let pawns = |side: &str| side.matches('P').count();
+        let (white_pawns, black_pawns) = (pawns(white), pawns(black));
+        let white_leads = black_pawns == 0 || (white_pawns > 0 && black_pawns >= white_pawns);
+        Some(Self {
+            name: name.to_string(),
+            pieces,
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces: [white, black].iter().any(|side| "QRBNP".chars().any(|c| side.matches(c).count() == 1)),
+            pawn_count: if white_leads {[white_pawns, black_pawns]} else {[black_pawns, white_pawns]},
+            symmetric: white == black,
+        })
+    }
+}
+
+/**
+ * How the positions of one side to move and one file of the leading pawn are stored: the order of the pieces, how they are grouped
+ * into the index, and where the compressed values are.
+ */
+#[derive(Debug, Clone, Default)]
+struct PairsData {
+    flags: u8,
+    // piece codes: 1 pawn, 2 knight, 3 bishop, 4 rook, 5 queen, 6 king, plus 8 for black
+    pieces: [u8; MAX_PIECES],
+    // number of pieces of each group, zero-terminated, and the factor of each group in the index (the last one being the size of the table)
+    group_len: [usize; MAX_PIECES + 1],
+    group_idx: [u64; MAX_PIECES + 1],
+    block_size: usize,
+    // number of values between two entries of the sparse index
+    span: u64,
+    sparse_index_size: usize,
+    num_blocks: usize,
+    block_length_size: usize,
+    // shortest symbol length in bits, or the value of every position with FLAG_SINGLE_VALUE
+    min_sym_len: usize,
+    // canonical Huffman code: the lowest symbol of each length, and the lowest code of each length aligned to the left of 64 bits
+    lowest_sym: usize,
+    base64: Vec<u64>,
+    // number of values a symbol stands for, minus one
+    symlen: Vec<u32>,
+    // the two symbols each symbol stands for, 12 bits each, or its value in the first 12 bits
+    btree: usize,
+    sparse_index: usize,
+    block_length: usize,
+    data: usize,
+    // offset of the DTZ values of a loss, a win, a cursed win and a blessed loss, from the start of the map
+    map_idx: [usize; 4],
+}
+
+impl PairsData {
+    /**
+     * Group the pieces and compute the factor of each group in the index, in the order the table stores them.
+     */
+    fn set_groups(&mut self, material: &Material, order: [u8; 2], file: usize) {
+        let mut first_len: i32 = if material.has_pawns {0} else if material.has_unique_pieces {3} else {2};
+        let mut n = 0;
+        self.group_len[0] = 1;
+        for i in 1..material.pieces {
+            first_len -= 1;
+            if first_len > 0 || self.pieces[i] == self.pieces[i - 1] {
+                self.group_len[n] += 1;
+            } else {
+                n += 1;
+                self.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        self.group_len[n] = 0;
+
+        // the leading group comes at order[0], the pawns of the other color (if both sides have pawns) at order[1]
+        let both_pawns = material.has_pawns && material.pawn_count[1] > 0;
+        let mut next = if both_pawns {2} else {1};
+        let mut free_squares = 64 - self.group_len[0] - if both_pawns {self.group_len[1]} else {0};
+        let mut idx = 1;
+        let mut k = 0;
+        while next < n || k == order[0] as usize || k == order[1] as usize {
+            if k == order[0] as usize {
+                self.group_idx[0] = idx;
+                idx *= if material.has_pawns {
+                    ENCODING.lead_pawns_size[self.group_len[0]][file]
+                } else if material.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] as usize {
+                self.group_idx[1] = idx;
+                idx *= ENCODING.binomial[self.group_len[1]][48 - self.group_len[0]];
+            } else {
+                self.group_idx[next] = idx;
+                idx *= ENCODING.binomial[self.group_len[next]][free_squares];
+                free_squares -= self.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        self.group_idx[n] = idx;
+    }
+
+    /**
+     * Index of a position in the table, the squares being in the order of the pieces of the table, with the leading pawns first.
+     * The board is mirrored so that the leading piece is on files a-d (and, without pawns, on ranks 1-4 and below the a1-h8 diagonal),
+     * which leaves one index for all the positions which are mirrors of each other.
+     */
+    fn index(&self, material: &Material, squares: &mut [usize], lead_pawns: usize) -> u64 {
+        // mirror the board so that the leading piece is on files a-d
+        if squares[0] & 7 > 3 {
+            squares.iter_mut().for_each(|square| *square ^= 7);
+        }
+
+        let mut idx;
+        if material.has_pawns {
+            idx = ENCODING.lead_pawn_idx[lead_pawns][squares[0]];
+            squares[1..lead_pawns].sort_by_key(|square| ENCODING.map_pawns[*square]);
+            for (i, square) in squares.iter().enumerate().take(lead_pawns).skip(1) {
+                idx += ENCODING.binomial[i][ENCODING.map_pawns[*square]];
+            }
+        } else {
+            // without pawns, the board is also mirrored so that the leading piece is on ranks 1-4, then below the a1-h8 diagonal
+            if squares[0] >> 3 > 3 {
+                squares.iter_mut().for_each(|square| *square ^= 56);
+            }
+            if let Some(i) = (0..self.group_len[0]).find(|i| off_diagonal(squares[*i]) != 0) {
+                if off_diagonal(squares[i]) > 0 {
+                    squares[i..].iter_mut().for_each(|square| *square = (*square >> 3 | *square << 3) & 63);
+                }
+            }
+            idx = if material.has_unique_pieces {
+                // the first three pieces are encoded together, by how many of them are on the diagonal
+                let rank = |square: usize| (square >> 3) as u64;
+                let adjust1 = (squares[1] > squares[0]) as u64;
+                let adjust2 = (squares[2] > squares[0]) as u64 + (squares[2] > squares[1]) as u64;
+                if off_diagonal(squares[0]) != 0 {
+                    (ENCODING.map_a1d1d4[squares[0]] as u64 * 63 + squares[1] as u64 - adjust1) * 62 + squares[2] as u64 - adjust2
+                } else if off_diagonal(squares[1]) != 0 {
+                    (6 * 63 + rank(squares[0]) * 28 + ENCODING.map_b1h1h7[squares[1]] as u64) * 62 + squares[2] as u64 - adjust2
+                } else if off_diagonal(squares[2]) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + rank(squares[0]) * 7 * 28 + (rank(squares[1]) - adjust1) * 28 + ENCODING.map_b1h1h7[squares[2]] as u64
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank(squares[0]) * 7 * 6 + (rank(squares[1]) - adjust1) * 6 + rank(squares[2]) - adjust2
+                }
+            } else {
+                // only the two kings
+                ENCODING.map_kk[ENCODING.map_a1d1d4[squares[0]]][squares[1]] as u64
+            };
+        }
+
+        // the other groups, each as a combination of the squares not taken by the previous groups
+        idx *= self.group_idx[0];
+        let mut start = self.group_len[0];
+        let mut remaining_pawns = material.has_pawns && material.pawn_count[1] > 0;
+        let mut next = 1;
+        while self.group_len[next] != 0 {
+            let end = start + self.group_len[next];
+            squares[start..end].sort_unstable();
+            let mut n = 0;
+            for i in start..end {
+                let adjust = squares[..start].iter().filter(|square| squares[i] > **square).count();
+                // the pawns of the other color are never on the first rank
+                n += ENCODING.binomial[i - start + 1][squares[i] - adjust - if remaining_pawns {8} else {0}];
+            }
+            remaining_pawns = false;
+            idx += n * self.group_idx[next];
+            start = end;
+            next += 1;
+        }
+        idx
+    }
+
+    /**
+     * Read the sizes and the Huffman code of the compressed values, starting at offset, and return the offset after them.
+     */
+    fn set_sizes(&mut self, data: &[u8], mut offset: usize) -> Option<usize> {
+        self.flags = read_u8(data, offset)?;
+        offset += 1;
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            self.min_sym_len = read_u8(data, offset)? as usize;
+            return Some(offset + 1);
+        }
+        let groups = self.group_len.iter().position(|len| *len == 0)?;
+        let size = self.group_idx[groups];
+        // corrupt sizes fail here rather than overflow
+        self.block_size = 1usize.checked_shl(read_u8(data, offset)? as u32)?;
+        self.span = 1u64.checked_shl(read_u8(data, offset + 1)? as u32)?;
+        self.sparse_index_size = size.div_ceil(self.span) as usize;
+        let padding = read_u8(data, offset + 2)? as usize;
+        self.num_blocks = read_u32_le(data, offset + 3)? as usize;
+        // padded so that the sparse index never points past the end
+        self.block_length_size = self.num_blocks + padding;
+        let max_sym_len = read_u8(data, offset + 7)? as usize;
+        self.min_sym_len = read_u8(data, offset + 8)? as usize;
+        offset += 9;
+        if max_sym_len < self.min_sym_len || max_sym_len > 64 {
+            return None;
+        }
+        self.lowest_sym = offset;
+        let lengths = max_sym_len - self.min_sym_len + 1;
+        // longer codes have lower values, so the lowest code of each length is half the lowest code of the next length plus the symbols in between
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            let lowest = read_u16_le(data, self.lowest_sym + 2 * i)? as u64;
+            let next_lowest = read_u16_le(data, self.lowest_sym + 2 * (i + 1))? as u64;
+            self.base64[i] = self.base64[i + 1].wrapping_add(lowest).wrapping_sub(next_lowest) / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base = base.checked_shl((64 - i - self.min_sym_len) as u32).unwrap_or(0);
+        }
+        offset += 2 * lengths;
+        let symbols = read_u16_le(data, offset)? as usize;
+        offset += 2;
+        self.btree = offset;
+        if offset + 3 * symbols > data.len() {
+            return None;
+        }
+        // symbols are pairs of symbols (recursive pairing), so the number of values of each one is found by walking the tree
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for symbol in 0..symbols {
+            if !visited[symbol] {
+                self.symlen[symbol] = self.set_symlen(data, symbol, &mut visited);
+            }
+        }
+        Some(offset + 3 * symbols + (symbols & 1))
+    }
+
+    fn children(&self, data: &[u8], symbol: usize) -> (usize, usize) {
+        let offset = self.btree + 3 * symbol;
+        let (b0, b1, b2) = (data[offset] as usize, data[offset + 1] as usize, data[offset + 2] as usize);
+        ((b1 & 0xF) << 8 | b0, b2 << 4 | b1 >> 4)
+    }
+
+    fn set_symlen(&mut self, data: &[u8], symbol: usize, visited: &mut [bool]) -> u32 {
+        visited[symbol] = true;
+        let (left, right) = self.children(data, symbol);
+        if right == 0xFFF || left >= self.symlen.len() || right >= self.symlen.len() {
+            return 0;
+        }
+        for child in [left, right] {
+            if !visited[child] {
+                self.symlen[child] = self.set_symlen(data, child, visited);
+            }
+        }
+        self.symlen[left] + self.symlen[right] + 1
+    }
+
+    fn block_length(&self, data: &[u8], block: usize) -> Option<i64> {
+        read_u16_le(data, self.block_length + 2 * block).map(|length| length as i64)
+    }
+
+    /**
+     * The value stored at index idx. The sparse index tells in which block it is, approximately; the block is a sequence of Huffman codes,
+     * each standing for one or more values.
+     */
+    fn decompress(&self, data: &[u8], idx: u64) -> Option<i32> {
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            return Some(self.min_sym_len as i32);
+        }
+        let entry = self.sparse_index + 6 * (idx / self.span) as usize;
+        let mut block = read_u32_le(data, entry)? as usize;
+        // the sparse entry points to the value in the middle of its span
+        let mut offset = read_u16_le(data, entry + 4)? as i64 + (idx % self.span) as i64 - (self.span / 2) as i64;
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += self.block_length(data, block)? + 1;
+        }
+        while offset > self.block_length(data, block)? {
+            offset -= self.block_length(data, block)? + 1;
+            block += 1;
+        }
+
+        let mut position = self.data + block * self.block_size;
+        let mut buffer = read_u64_be(data, position)?;
+        position += 8;
+        let mut buffer_size = 64;
+        let mut symbol;
+        loop {
+            let mut len = 0;
+            while len + 1 < self.base64.len() && buffer < self.base64[len] {
+                len += 1;
+            }
+            symbol = ((buffer - self.base64[len]) >> (64 - len - self.min_sym_len)) as usize;
+            symbol += read_u16_le(data, self.lowest_sym + 2 * len)? as usize;
+            let values = *self.symlen.get(symbol)? as i64 + 1;
+            if offset < values {
+                break;
+            }
+            offset -= values;
+            let bits = len + self.min_sym_len;
+            buffer = buffer.checked_shl(bits as u32).unwrap_or(0);
+            buffer_size -= bits;
+            if buffer_size <= 32 {
+                buffer_size += 32;
+                buffer |= (read_u32_be(data, position).unwrap_or(0) as u64) << (64 - buffer_size);
+                position += 4;
+            }
+        }
+        while self.symlen[symbol] != 0 {
+            let (left, right) = self.children(data, symbol);
+            let values = *self.symlen.get(left)? as i64 + 1;
+            if offset < values {
+                symbol = left;
+            } else {
+                offset -= values;
+                symbol = right;
+            }
+        }
+        Some(self.children(data, symbol).0 as i32)
+    }
+}
+
+/**
+ * A table file read into memory.
+ */
+struct Table {
+    data: Vec<u8>,
+    // [side to move][file of the leading pawn]: two sides for the WDL tables of different material, four files for tables with pawns
+    pairs: Vec<Vec<PairsData>>,
+    // start of the DTZ value maps
+    map: usize,
+}
+
+impl Table {
+    fn load(path: &Path, material: &Material, kind: TableKind) -> Option<Self> {
+        let data = fs::read(path).ok()?;
+        // files are 64-byte aligned after the magic number and the header
+        if data.len() % 64 != 16 || data[0..4] != if kind == TableKind::Wdl {WDL_MAGIC} else {DTZ_MAGIC} {
+            return None;
+        }
+        let flags = data[4];
+        if (flags & 2 != 0) != material.has_pawns || (flags & 1 != 0) == material.symmetric {
+            return None;
+        }
+        let mut offset = 5;
+        let sides = if kind == TableKind::Wdl && !material.symmetric {2} else {1};
+        let files = if material.has_pawns {4} else {1};
+        let both_pawns = material.has_pawns && material.pawn_count[1] > 0;
+        let mut pairs = vec![vec![PairsData::default(); files]; sides];
+        for file in 0..files {
+            let first = read_u8(&data, offset)?;
+            let second = if both_pawns {read_u8(&data, offset + 1)?} else {0xFF};
+            let order = [[first & 0xF, second & 0xF], [first >> 4, second >> 4]];
+            offset += if both_pawns {2} else {1};
+            for k in 0..material.pieces {
+                let pieces = read_u8(&data, offset)?;
+                for (side, side_pairs) in pairs.iter_mut().enumerate() {
+                    side_pairs[file].pieces[k] = if side == 0 {pieces & 0xF} else {pieces >> 4};
+                }
+                offset += 1;
+            }
+            for (side, side_pairs) in pairs.iter_mut().enumerate() {
+                side_pairs[file].set_groups(material, order[side], file);
+            }
+        }
+        offset += offset & 1;
+
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                offset = side_pairs[file].set_sizes(&data, offset)?;
+            }
+        }
+        let map = offset;
+        if kind == TableKind::Dtz {
+            for file in 0..files {
+                let pairs = &mut pairs[0][file];
+                if pairs.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+                // each map starts with its length
+                if pairs.flags & FLAG_WIDE != 0 {
+                    offset += offset & 1;
+                    for i in 0..4 {
+                        pairs.map_idx[i] = offset + 2 - map;
+                        offset += 2 * read_u16_le(&data, offset)? as usize + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        pairs.map_idx[i] = offset + 1 - map;
+                        offset += read_u8(&data, offset)? as usize + 1;
+                    }
+                }
+            }
+            offset += offset & 1;
+        }
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                side_pairs[file].sparse_index = offset;
+                offset += 6 * side_pairs[file].sparse_index_size;
+            }
+        }
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                side_pairs[file].block_length = offset;
+                offset += 2 * side_pairs[file].block_length_size;
+            }
+        }
+        for file in 0..files {
+            for side_pairs in pairs.iter_mut() {
+                offset = offset.next_multiple_of(64);
+                side_pairs[file].data = offset;
+                offset = offset.checked_add(side_pairs[file].num_blocks.checked_mul(side_pairs[file].block_size)?)?;
+            }
+        }
+        if offset > data.len() {
+            return None;
+        }
+        Some(Self { data, pairs, map })
+    }
+
+    fn pairs(&self, side: usize, file: usize) -> &PairsData {
+        let side_pairs = &self.pairs[side % self.pairs.len()];
+        &side_pairs[file.min(side_pairs.len() - 1)]
+    }
+
+    /**
+     * Turn a DTZ value as stored into plies, for a position of the given WDL.
+     */
+    fn dtz_plies(&self, file: usize, mut value: i32, wdl: Wdl) -> Option<i32> {
+        let pairs = self.pairs(0, file);
+        if pairs.flags & FLAG_MAPPED != 0 {
+            let map_idx = pairs.map_idx[match wdl {
+                Wdl::Loss => 1,
+                Wdl::BlessedLoss => 3,
+                Wdl::CursedWin => 2,
+                _ => 0,
+            }];
+            value = if pairs.flags & FLAG_WIDE != 0 {
+                read_u16_le(&self.data, self.map + map_idx + 2 * value as usize)? as i32
+            } else {
+                read_u8(&self.data, self.map + map_idx + value as usize)? as i32
+            };
+        }
+        let in_moves = match wdl {
+            Wdl::Win => pairs.flags & FLAG_WIN_PLIES == 0,
+            Wdl::Loss => pairs.flags & FLAG_LOSS_PLIES == 0,
+            _ => true,
+        };
+        Some(if in_moves {value * 2} else {value} + 1)
+    }
+}
+
+/**
+ * The WDL and DTZ files of one material, read when first probed.
+ */
+struct TableFiles {
+    material: Material,
+    wdl_path: PathBuf,
+    dtz_path: Option<PathBuf>,
+    wdl: OnceLock<Option<Table>>,
+    dtz: OnceLock<Option<Table>>,
+}
+
+impl TableFiles {
+    fn table(&self, kind: TableKind) -> Option<&Table> {
+        match kind {
+            TableKind::Wdl => self.wdl.get_or_init(|| Table::load(&self.wdl_path, &self.material, kind)).as_ref(),
+            TableKind::Dtz => self.dtz.get_or_init(|| Table::load(self.dtz_path.as_ref()?, &self.material, kind)).as_ref(),
+        }
+    }
+}
+
+/**
+ * Syzygy endgame tablebases: for every position with few pieces, whether it is won, drawn or lost (WDL) and how many plies it takes
+ * to reach the next capture or pawn move while keeping that result (DTZ). Tables missing from the directories are simply not probed.
+ */
+pub struct Tablebase {
+    // by the material of the position, white first: "KRvK", and "KvKR" for the same table with the colors swapped
+    tables: HashMap<String, Arc<TableFiles>>,
+    count: usize,
+    max_pieces: usize,
+}
+
+/**
+ * What a table says about a position: its value, or that the table is for the other side to move (DTZ tables are one-sided).
+ */
+enum Probe {
+    Found(i32),
+    OtherSide,
+}
+
+impl Tablebase {
+    /**
+     * Find the WDL (.rtbw) and DTZ (.rtbz) files in a list of directories, separated as in the PATH environment variable.
+     * The files are only read when probed; directories which cannot be read are skipped.
+     */
+    pub fn open(paths: &str) -> Self {
+        let mut tablebase = Self { tables: HashMap::new(), count: 0, max_pieces: 0 };
+        for directory in std::env::split_paths(paths) {
+            let Ok(entries) = fs::read_dir(&directory) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let path = entry.path();
+                if path.extension().is_none_or(|extension| extension != "rtbw") {
+                    continue;
+                }
+                let Some(material) = path.file_stem().and_then(|name| name.to_str()).and_then(Material::parse) else {
+                    continue;
+                };
+                if tablebase.tables.contains_key(&material.name) {
+                    continue;
+                }
+                let dtz_path = path.with_extension("rtbz");
+                let (white, black) = material.name.split_once('v').unwrap();
+                let swapped = format!("{}v{}", black, white);
+                tablebase.max_pieces = tablebase.max_pieces.max(material.pieces);
+                tablebase.count += 1;
+                let files = Arc::new(TableFiles {
+                    dtz_path: dtz_path.exists().then_some(dtz_path),
+                    wdl_path: path,
+                    material,
+                    wdl: OnceLock::new(),
+                    dtz: OnceLock::new(),
+                });
+                tablebase.tables.insert(swapped, files.clone());
+                tablebase.tables.insert(files.material.name.clone(), files);
+            }
+        }
+        tablebase
+    }
+
+    pub fn len(&self) -> usize {
+        self.count
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.count == 0
+    }
+
+    /**
+     * Number of pieces (kings included) of the largest tables found.
+     */
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+}
+
+impl Board {
+    /**
+     * Pieces in the order of the table names: "KQRBNP" for each side, white first.
+     */
+    fn material_name(&self) -> String {
+        let side = |color| {
+            let mut name = String::from("K");
+            for (piece_type, letter) in [(PieceType::Queen, 'Q'), (PieceType::Rook, 'R'), (PieceType::Bishop, 'B'), (PieceType::Knight, 'N'), (PieceType::Pawn, 'P')] {
+                for _ in 0..self.pieces_of(color, piece_type).count_ones() {
+                    name.push(letter);
+                }
+            }
+            name
+        };
+        format!("{}v{}", side(PieceColor::White), side(PieceColor::Black))
+    }
+
+    /**
+     * The pieces as squares from a1 (0) to h8 (63) and piece codes of the tables, by square.
+     */
+    fn table_pieces(&self) -> Vec<(usize, u8)> {
+        (0..64).filter_map(|square| {
+            // our squares go from a8 to h1
+            let (row, col) = bitboard::coords(square ^ 56);
+            let piece = self.board[row as usize][col as usize]?;
+            let code = match piece.piece_type {
+                PieceType::Pawn => 1,
+                PieceType::Knight => 2,
+                PieceType::Bishop => 3,
+                PieceType::Rook => 4,
+                PieceType::Queen => 5,
+                PieceType::King => 6,
+            };
+            Some((square, code + if piece.color == PieceColor::Black {8} else {0}))
+        }).collect()
+    }
+
+    /**
+     * Look the position up in its table, ignoring en passant (the tables have no such positions). The table is written for the side with
+     * the stronger pieces as white, so the colors may have to be swapped, and the board mirrored, before computing the index of the position.
+     */
+    fn probe_table(&self, tablebase: &Tablebase, kind: TableKind, wdl: Wdl) -> Option<Probe> {
+        let pieces = self.table_pieces();
+        if pieces.len() == 2 {
+            return Some(Probe::Found(0));
+        }
+        let name = self.material_name();
+        let files = tablebase.tables.get(&name)?;
+        let material = &files.material;
+        let table = files.table(kind)?;
+        let black_to_move = self.turn == PieceColor::Black;
+        // symmetric tables only have white to move
+        let flip = (material.symmetric && black_to_move) || name != material.name;
+        let (flip_color, flip_squares) = if flip {(8, 56)} else {(0, 0)};
+        let side = (flip ^ black_to_move) as usize;
+
+        let mut squares = Vec::with_capacity(pieces.len());
+        let mut codes = Vec::with_capacity(pieces.len());
+        let mut file = 0;
+        let mut lead_pawns = 0;
+        let lead_code = table.pairs(0, 0).pieces[0] ^ flip_color;
+        if material.has_pawns {
+            // the pawns of the leading color come first, the leading pawn being the one with the highest map_pawns
+            for (square, code) in pieces.iter() {
+                if *code == lead_code {
+                    squares.push(square ^ flip_squares);
+                    codes.push(code ^ flip_color);
+                }
+            }
+            lead_pawns = squares.len();
+            let lead = (0..lead_pawns).max_by_key(|i| ENCODING.map_pawns[squares[*i]])?;
+            squares.swap(0, lead);
+            file = (squares[0] & 7).min(7 - (squares[0] & 7));
+        }
+        if kind == TableKind::Dtz && (table.pairs(side, file).flags & FLAG_STM) as usize != side && (!material.symmetric || material.has_pawns) {
+            return Some(Probe::OtherSide);
+        }
+        for (square, code) in pieces.iter() {
+            if !material.has_pawns || *code != lead_code {
+                squares.push(square ^ flip_squares);
+                codes.push(code ^ flip_color);
+            }
+        }
+        let pairs = table.pairs(side, file);
+
+        // put the pieces in the order of the table
+        for i in lead_pawns..squares.len() - 1 {
+            if let Some(j) = (i + 1..squares.len()).find(|j| codes[*j] == pairs.pieces[i]) {
+                codes.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+        let idx = pairs.index(material, &mut squares, lead_pawns);
+        let value = pairs.decompress(&table.data, idx)?;
+        match kind {
+            TableKind::Wdl => Some(Probe::Found(value - 2)),
+            TableKind::Dtz => table.dtz_plies(file, value, wdl).map(Probe::Found),
+        }
+    }
+
+    fn is_pawn_move(&self, from: (u8, u8)) -> bool {
+        self.board[from.0 as usize][from.1 as usize].is_some_and(|piece| piece.piece_type == PieceType::Pawn)
+    }
+
+    /**
+     * WDL of the position, searching the captures (and, with zeroing, the pawn moves) first: the tables have no positions with en passant rights,
+     * and their value for a position whose best move is a capture may be wrong. Also tells whether the best move is one of those searched.
+     */
+    fn search_wdl(&mut self, tablebase: &Tablebase, zeroing: bool) -> Option<(Wdl, bool)> {
+        let moves = self.get_all_possible_moves();
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+        for (row, col, mv) in moves.iter() {
+            if mv.capture.is_none() && !(zeroing && self.is_pawn_move((*row, *col))) {
+                continue;
+            }
+            searched += 1;
+            self.play_move((*row, *col), mv);
+            let value = self.search_wdl(tablebase, false).map(|(wdl, _)| wdl.opposite());
+            self.rollback_move();
+            let value = value?;
+            if value > best {
+                best = value;
+                if value == Wdl::Win {
+                    return Some((value, true));
+                }
+            }
+        }
+        let all_searched = searched > 0 && searched == moves.len();
+        let value = if all_searched {
+            best
+        } else {
+            match self.probe_table(tablebase, TableKind::Wdl, Wdl::Draw)? {
+                Probe::Found(value) => Wdl::from_value(value)?,
+                Probe::OtherSide => return None,
+            }
+        };
+        if best >= value {
+            return Some((best, best > Wdl::Draw || all_searched));
+        }
+        Some((value, false))
+    }
+
+    /**
+     * DTZ of the position in plies, positive when winning, negative when losing, 100 more for cursed wins and blessed losses, 0 for draws.
+     */
+    fn dtz(&mut self, tablebase: &Tablebase) -> Option<i32> {
+        let (wdl, zeroing) = self.search_wdl(tablebase, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        // the table may store anything for a position whose best move is a capture or a pawn move
+        if zeroing {
+            return Some(wdl.dtz_before_zeroing());
+        }
+        let sign = (wdl as i32).signum();
+        match self.probe_table(tablebase, TableKind::Dtz, wdl)? {
+            Probe::Found(dtz) => Some((dtz + if matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss) {100} else {0}) * sign),
+            Probe::OtherSide => {
+                // the table only has the other side to move: take the best DTZ after each move
+                let mut best = i32::MAX;
+                for (row, col, mv) in self.get_all_possible_moves() {
+                    let zeroing = mv.capture.is_some() || self.is_pawn_move((row, col));
+                    self.play_move((row, col), &mv);
+                    let dtz = if zeroing {
+                        self.search_wdl(tablebase, false).map(|(wdl, _)| -wdl.dtz_before_zeroing())
+                    } else {
+                        self.dtz(tablebase).map(|dtz| -dtz)
+                    };
+                    let mate = dtz == Some(1) && self.is_check && self.get_all_possible_moves().is_empty();
+                    self.rollback_move();
+                    let mut dtz = dtz?;
+                    if mate {
+                        best = 1;
+                    }
+                    if !zeroing {
+                        dtz += dtz.signum();
+                    }
+                    if dtz < best && dtz.signum() == sign {
+                        best = dtz;
+                    }
+                }
+                // no legal move: mated
+                Some(if best == i32::MAX {-1} else {best})
+            },
+        }
+    }
+
+    /**
+     * The tables only cover positions without castling rights, and up to the number of pieces of the largest table found.
+     */
+    fn in_tablebase(&self, tablebase: &Tablebase) -> bool {
+        !(self.wk_castle || self.wq_castle || self.bk_castle || self.bq_castle)
+            && self.occupied().count_ones() as usize <= tablebase.max_pieces
+    }
+
+    /**
+     * WDL of the position for the side to move, if the tables have it.
+     */
+    pub(crate) fn probe_wdl(&mut self, tablebase: &Tablebase) -> Option<Wdl> {
+        if !self.in_tablebase(tablebase) {
+            return None;
+        }
+        self.search_wdl(tablebase, false).map(|(wdl, _)| wdl)
+    }
+
+    /**
+     * The move keeping the best result in the fewest plies to the next capture or pawn move (so that the 50-move rule never gets in the way),
+     * with the WDL after it, if the tables have the position and the ones after every move. A losing side takes the longest way instead.
+     */
+    pub(crate) fn tablebase_move(&mut self, tablebase: &Tablebase) -> Option<((u8, u8, Move), Wdl)> {
+        if !self.in_tablebase(tablebase) {
+            return None;
+        }
+        let halfmove_clock = self.halfmove_clock as i32;
+        let mut best: Option<((u8, u8, Move), i32)> = None;
+        for (row, col, mv) in self.get_all_possible_moves() {
+            let repetition = self.play_move((row, col), &mv);
+            // DTZ from the current position, counting the move
+            let dtz = if self.halfmove_clock == 0 {
+                self.search_wdl(tablebase, false).map(|(wdl, _)| wdl.opposite().dtz_before_zeroing())
+            } else if repetition || self.halfmove_clock >= 100 {
+                Some(0)
+            } else {
+                self.dtz(tablebase).map(|dtz| -dtz - dtz.signum())
+            };
+            let mate = self.is_check && self.get_all_possible_moves().is_empty();
+            self.rollback_move();
+            let dtz = if mate {1} else {dtz?};
+            // certain wins first, the fastest first, then wins too slow for the 50-move rule, draws, losses too slow for the 50-move rule
+            // (the longest first), and certain losses
+            let rank = if dtz > 0 {
+                if dtz + halfmove_clock <= 100 {2 * MAX_DTZ - dtz} else {MAX_DTZ - dtz}
+            } else if dtz < 0 {
+                if -dtz + halfmove_clock <= 100 {-2 * MAX_DTZ - dtz} else {-MAX_DTZ - dtz}
+            } else {
+                0
+            };
+            if best.is_none_or(|(_, best_rank)| rank > best_rank) {
+                best = Some(((row, col, mv), rank));
+            }
+        }
+        let (best_move, rank) = best?;
+        let wdl = match rank {
+            rank if rank > MAX_DTZ => Wdl::Win,
+            rank if rank > 0 => Wdl::CursedWin,
+            0 => Wdl::Draw,
+            rank if rank > -MAX_DTZ => Wdl::BlessedLoss,
+            _ => Wdl::Loss,
+        };
+        Some((best_move, wdl))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+    use std::ops::Range;
+
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    use super::*;
+    use crate::chess::{MoveSource, SearchLimits, TranspositionTable};
+
+    /**
+     * A directory of its own for the table files of one test, removed when dropped.
+     */
+    struct TableDir(PathBuf);
+
+    impl TableDir {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("syzygy-{}-{}", name, std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(&dir).unwrap();
+            Self(dir)
+        }
+
+        fn write(&self, file: &str, data: &[u8]) {
+            fs::write(self.0.join(file), data).unwrap();
+        }
+
+        fn open(&self) -> Tablebase {
+            Tablebase::open(self.0.to_str().unwrap())
+        }
+    }
+
+    impl Drop for TableDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /**
+     * A table of a king and a piece against a king (piece codes for white) with the same value for every position, for white to move
+     * and, in WDL tables, black to move: the header, the flags and the value of each side, and padding to the 64-byte alignment.
+     */
+    fn single_value_table(magic: [u8; 4], piece: u8, sides: &[(u8, u8)]) -> Vec<u8> {
+        // neither symmetric nor with pawns, and the order of the pieces, unused by single values
+        let mut data = magic.to_vec();
+        data.extend([1, 0]);
+        for piece in [6, piece, 14] {
+            data.push(piece | (piece ^ 8) << 4);
+        }
+        data.push(0);
+        for (flags, value) in sides {
+            data.extend([FLAG_SINGLE_VALUE | flags, *value]);
+        }
+        data.resize(80, 0);
+        data
+    }
+
+    fn wdl_table(piece: u8, white: Wdl, black: Wdl) -> Vec<u8> {
+        single_value_table(WDL_MAGIC, piece, &[(0, (white as i32 + 2) as u8), (0, (black as i32 + 2) as u8)])
+    }
+
+    fn dtz_table(piece: u8, flags: u8, value: u8) -> Vec<u8> {
+        single_value_table(DTZ_MAGIC, piece, &[(flags, value)])
+    }
+
+    #[test]
+    fn encoding_tables() {
+        let adjacent = |a: usize, b: usize| (a >> 3).abs_diff(b >> 3) <= 1 && (a & 7).abs_diff(b & 7) <= 1;
+        // the first king in the a1-d1-d4 triangle and the second anywhere else, not both mirrored along the diagonal
+        let mut codes = HashSet::new();
+        for first in (0..28).filter(|square| square & 7 < 4 && off_diagonal(*square) <= 0) {
+            for second in (0..64).filter(|square| !adjacent(first, *square) && (off_diagonal(first) != 0 || off_diagonal(*square) <= 0)) {
+                codes.insert(ENCODING.map_kk[ENCODING.map_a1d1d4[first]][second]);
+            }
+        }
+        assert_eq!((codes.len(), codes.iter().max().copied()), (462, Some(461)));
+        let mut pawns: Vec<usize> = (8..56).map(|square| ENCODING.map_pawns[square]).collect();
+        pawns.sort();
+        assert_eq!(pawns, (0..48).collect::<Vec<_>>());
+        assert_eq!((ENCODING.map_a1d1d4[0], ENCODING.map_a1d1d4[1], ENCODING.map_a1d1d4[27]), (6, 0, 9));
+        assert_eq!((ENCODING.binomial[2][5], ENCODING.binomial[5][47]), (10, 1533939));
+        assert_eq!(ENCODING.lead_pawns_size[1].iter().sum::<u64>(), 24);
+    }
+
+    /**
+     * Index every legal placement of the pieces (in the order of the table, with groups of like pieces sorted) and check that two placements
+     * share an index only when they are mirrors of each other: every mirroring without pawns, only left to right with pawns.
+     */
+    fn check_index(name: &str, pieces: &[u8], groups: &[Range<usize>], lead_pawns: usize, kings: (usize, usize)) {
+        let material = Material::parse(name).unwrap();
+        let mirror = |square: usize, symmetry: usize| {
+            let mut square = square;
+            if symmetry & 1 != 0 {
+                square ^= 7;
+            }
+            if symmetry & 2 != 0 {
+                square ^= 56;
+            }
+            if symmetry & 4 != 0 {
+                square = ((square >> 3) | (square << 3)) & 63;
+            }
+            square
+        };
+        let canonical = |squares: &[usize]| {
+            (0..if material.has_pawns {2} else {8}).map(|symmetry| {
+                let mut mirrored: Vec<usize> = squares.iter().map(|square| mirror(*square, symmetry)).collect();
+                for group in groups {
+                    mirrored[group.clone()].sort();
+                }
+                mirrored
+            }).min().unwrap()
+        };
+        let adjacent = |a: usize, b: usize| (a >> 3).abs_diff(b >> 3) <= 1 && (a & 7).abs_diff(b & 7) <= 1;
+        let mut seen: HashMap<(usize, u64), Vec<usize>> = HashMap::new();
+        let mut classes = HashSet::new();
+        let mut squares = vec![0; pieces.len()];
+        for code in 0..64usize.pow(pieces.len() as u32) {
+            let mut rest = code;
+            for square in squares.iter_mut() {
+                *square = rest % 64;
+                rest /= 64;
+            }
+            if (0..squares.len()).any(|i| (0..i).any(|j| squares[i] == squares[j]))
+                || (0..squares.len()).any(|i| pieces[i] & 7 == 1 && !(8..56).contains(&squares[i]))
+                || adjacent(squares[kings.0], squares[kings.1])
+                || groups.iter().any(|group| (group.start + 1..group.end).any(|i| squares[i] <= squares[i - 1])) {
+                continue;
+            }
+            let mut ordered = squares.clone();
+            let mut file = 0;
+            if lead_pawns > 0 {
+                let lead = (0..lead_pawns).max_by_key(|i| ENCODING.map_pawns[ordered[*i]]).unwrap();
+                ordered.swap(0, lead);
+                file = (ordered[0] & 7).min(7 - (ordered[0] & 7));
+            }
+            let mut pairs = PairsData::default();
+            pairs.pieces[..pieces.len()].copy_from_slice(pieces);
+            pairs.set_groups(&material, [0, 15], file);
+            let size = pairs.group_idx[pairs.group_len.iter().position(|len| *len == 0).unwrap()];
+            let idx = pairs.index(&material, &mut ordered, lead_pawns);
+            assert!(idx < size, "{} {:?}: index {} past the size {}", name, squares, idx, size);
+            let class = canonical(&squares);
+            classes.insert(class.clone());
+            if let Some(previous) = seen.insert((file, idx), class.clone()) {
+                assert_eq!(previous, class, "{}: index {} of file {} shared", name, idx, file);
+            }
+        }
+        assert_eq!(classes.len(), seen.len(), "{}: mirrored placements with different indices", name);
+    }
+
+    #[test]
+    fn index_without_pawns() {
+        check_index("KRvK", &[6, 4, 14], &[0..1, 1..2, 2..3], 0, (0, 2));
+    }
+
+    #[test]
+    fn index_with_pawns() {
+        check_index("KPvK", &[1, 6, 14], &[0..1, 1..2, 2..3], 1, (1, 2));
+    }
+
+    #[test]
+    fn decompress_blocks() {
+        // four symbols: the values 0, 1 and 2, and the pair (2, 0), with Huffman codes 000, 001, 01 and 1
+        let codes: [(u64, usize); 4] = [(0b000, 3), (0b001, 3), (0b01, 2), (0b1, 1)];
+        let block_size = 16;
+        let span = 8;
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut blocks = Vec::new();
+        let mut block_lengths = Vec::new();
+        let mut values = Vec::new();
+        let mut bits: Vec<bool> = Vec::new();
+        let mut count = 0;
+        let flush = |bits: &mut Vec<bool>, blocks: &mut Vec<Vec<u8>>| {
+            let mut bytes = vec![0u8; block_size];
+            for (i, bit) in bits.iter().enumerate() {
+                if *bit {
+                    bytes[i / 8] |= 0x80 >> (i % 8);
+                }
+            }
+            blocks.push(bytes);
+            bits.clear();
+        };
+        while values.len() < 4000 {
+            let symbol = rng.gen_range(0..4);
+            let (code, len) = codes[symbol];
+            // the decoder reads 32 bits ahead
+            if bits.len() + len > block_size * 8 - 32 {
+                flush(&mut bits, &mut blocks);
+                block_lengths.push(count - 1);
+                count = 0;
+            }
+            bits.extend((0..len).rev().map(|i| code >> i & 1 == 1));
+            let expanded = if symbol == 3 {vec![2, 0]} else {vec![symbol as i32]};
+            count += expanded.len();
+            values.extend(expanded);
+        }
+        flush(&mut bits, &mut blocks);
+        block_lengths.push(count - 1);
+        let total = values.len() as u64 / span * span;
+
+        // the sizes: flags, block size and span (as powers of two), padding, blocks, longest and shortest code lengths,
+        // the lowest symbol of each length, and the symbols, each the pair of its children or a value and 0xFFF
+        let mut data = vec![0, 4, 3, 0];
+        data.extend((blocks.len() as u32).to_le_bytes());
+        data.extend([3, 1]);
+        for lowest in [3u16, 2, 0] {
+            data.extend(lowest.to_le_bytes());
+        }
+        data.extend(4u16.to_le_bytes());
+        for (left, right) in [(0, 0xFFF), (1, 0xFFF), (2, 0xFFF), (2, 0)] {
+            data.extend([(left & 0xFF) as u8, ((left >> 8) | ((right & 0xF) << 4)) as u8, (right >> 4) as u8]);
+        }
+        let mut pairs = PairsData::default();
+        pairs.group_len[0] = 1;
+        pairs.group_idx[1] = total;
+        assert_eq!(pairs.set_sizes(&data, 0), Some(data.len()));
+        assert_eq!(pairs.symlen, vec![0, 0, 0, 1]);
+
+        // the sparse index points to the middle of every span, then come the block lengths and the blocks
+        pairs.sparse_index = data.len();
+        let starts: Vec<usize> = block_lengths.iter().scan(0, |start, len| {
+            let block_start = *start;
+            *start += len + 1;
+            Some(block_start)
+        }).collect();
+        for k in 0..pairs.sparse_index_size {
+            let target = k as u64 * span + span / 2;
+            let block = starts.iter().rposition(|start| *start as u64 <= target).unwrap();
+            data.extend((block as u32).to_le_bytes());
+            data.extend(((target - starts[block] as u64) as u16).to_le_bytes());
+        }
+        pairs.block_length = data.len();
+        for len in block_lengths.iter() {
+            data.extend((*len as u16).to_le_bytes());
+        }
+        data.resize(data.len().next_multiple_of(64), 0);
+        pairs.data = data.len();
+        for block in blocks.iter() {
+            data.extend(block);
+        }
+        data.extend([0; 8]);
+        for idx in 0..total {
+            assert_eq!(pairs.decompress(&data, idx), Some(values[idx as usize]), "index {}", idx);
+        }
+    }
+
+    #[test]
+    fn probe_results() {
+        let dir = TableDir::new("probe");
+        dir.write("KQvK.rtbw", &wdl_table(5, Wdl::Win, Wdl::Loss));
+        dir.write("KRvK.rtbw", &wdl_table(4, Wdl::CursedWin, Wdl::BlessedLoss));
+        dir.write("not-a-table.rtbw", &wdl_table(5, Wdl::Win, Wdl::Loss));
+        let tablebase = Tablebase::open(&format!("/nonexistent/syzygy:{}", dir.0.display()));
+        assert_eq!((tablebase.len(), tablebase.max_pieces()), (2, 3));
+        // the side to move, the colors swapped, a capture found before the table, two kings, no table, castling rights
+        for (fen, expected) in [
+            ("8/8/8/4k3/8/8/8/4KQ2 w - - 0 1", Some(Wdl::Win)),
+            ("8/8/8/4k3/8/8/8/4KQ2 b - - 0 1", Some(Wdl::Loss)),
+            ("4kq2/8/8/8/4K3/8/8/8 b - - 0 1", Some(Wdl::Win)),
+            ("4kq2/8/8/8/4K3/8/8/8 w - - 0 1", Some(Wdl::Loss)),
+            ("8/8/8/8/3k4/8/8/R3K3 w - - 0 1", Some(Wdl::CursedWin)),
+            ("8/8/8/8/3k4/8/8/R3K3 b - - 0 1", Some(Wdl::BlessedLoss)),
+            ("8/8/8/8/8/3kQ3/8/4K3 b - - 0 1", Some(Wdl::Draw)),
+            ("8/8/8/4k3/8/8/8/4K3 w - - 0 1", Some(Wdl::Draw)),
+            ("8/8/8/4k3/8/8/8/4KB2 w - - 0 1", None),
+            ("8/8/8/4k3/8/8/8/R3K3 w Q - 0 1", None),
+        ] {
+            assert_eq!(Board::from_fen(fen).unwrap().probe_wdl(&tablebase), expected, "{}", fen);
+        }
+    }
+
+    /**
+     * DTZ of the position for the side to move (see dtz), if the tables have it.
+     */
+    fn probe_dtz(board: &mut Board, tablebase: &Tablebase) -> Option<i32> {
+        if !board.in_tablebase(tablebase) {
+            return None;
+        }
+        board.dtz(tablebase)
+    }
+
+    #[test]
+    fn dtz_sign_and_rounding() {
+        let dir = TableDir::new("dtz");
+        dir.write("KQvK.rtbw", &wdl_table(5, Wdl::Win, Wdl::Loss));
+        dir.write("KRvK.rtbw", &wdl_table(4, Wdl::CursedWin, Wdl::BlessedLoss));
+        // a DTZ stored in moves is rounded up to an odd number of plies; the tables only have white to move, so the DTZ of black to move
+        // comes from the positions after each move, one ply further; cursed wins and blessed losses count 100 more
+        dir.write("KQvK.rtbz", &dtz_table(5, 0, 5));
+        dir.write("KRvK.rtbz", &dtz_table(4, 0, 5));
+        let tablebase = dir.open();
+        for (fen, expected) in [
+            ("8/8/8/4k3/8/8/8/4KQ2 w - - 0 1", Some(11)),
+            ("8/8/8/4k3/8/8/8/4KQ2 b - - 0 1", Some(-12)),
+            ("8/8/8/8/3k4/8/8/R3K3 w - - 0 1", Some(111)),
+            ("8/8/8/8/3k4/8/8/R3K3 b - - 0 1", Some(-112)),
+            ("8/8/8/8/8/3kQ3/8/4K3 b - - 0 1", Some(0)),
+            ("8/8/8/4k3/8/8/8/4K3 w - - 0 1", Some(0)),
+        ] {
+            assert_eq!(probe_dtz(&mut Board::from_fen(fen).unwrap(), &tablebase), expected, "{}", fen);
+        }
+
+        // stored in plies
+        dir.write("KQvK.rtbz", &dtz_table(5, FLAG_WIN_PLIES, 5));
+        let tablebase = dir.open();
+        assert_eq!(probe_dtz(&mut Board::from_fen("8/8/8/4k3/8/8/8/4KQ2 w - - 0 1").unwrap(), &tablebase), Some(6));
+        assert_eq!(probe_dtz(&mut Board::from_fen("8/8/8/4k3/8/8/8/4KQ2 b - - 0 1").unwrap(), &tablebase), Some(-7));
+    }
+
+    #[test]
+    fn tablebase_move_mates() {
+        let dir = TableDir::new("move");
+        dir.write("KQvK.rtbw", &wdl_table(5, Wdl::Win, Wdl::Loss));
+        let mut board = Board::from_fen("7k/8/5K2/8/8/8/8/6Q1 w - - 0 1").unwrap();
+        // without the DTZ file there is no tablebase move, and the search goes on
+        let tablebase = Arc::new(dir.open());
+        assert!(board.tablebase_move(&tablebase).is_none());
+        let limits = SearchLimits { depth: Some(2), tablebase: Some(tablebase), ..Default::default() };
+        assert_eq!(board.search(&limits, &TranspositionTable::new(1), |_| {}).source, MoveSource::Search);
+
+        dir.write("KQvK.rtbz", &dtz_table(5, 0, 5));
+        let tablebase = Arc::new(dir.open());
+        let ((row, col, mv), wdl) = board.tablebase_move(&tablebase).unwrap();
+        assert_eq!((mv.to_uci((row, col)), wdl), ("g1g7".to_string(), Wdl::Win));
+        let limits = SearchLimits { depth: Some(2), tablebase: Some(tablebase), ..Default::default() };
+        let result = board.search(&limits, &TranspositionTable::new(1), |_| {});
+        assert_eq!(result.source, MoveSource::Tablebase);
+        assert_eq!(result.best_move.map(|(row, col, mv)| mv.to_uci((row, col))), Some("g1g7".to_string()));
+    }
+
+    #[test]
+    fn search_goes_on_without_tables() {
+        let tablebase = Arc::new(Tablebase::open("/nonexistent/syzygy"));
+        assert!(tablebase.is_empty());
+        let mut board = Board::from_fen("8/8/8/4k3/8/8/8/4KQ2 w - - 0 1").unwrap();
+        assert_eq!(board.probe_wdl(&tablebase), None);
+        let limits = SearchLimits { depth: Some(4), tablebase: Some(tablebase), ..Default::default() };
+        let result = board.search(&limits, &TranspositionTable::new(1), |_| {});
+        assert_eq!(result.source, MoveSource::Search);
+        assert!(result.best_move.is_some());
+    }
+
+    #[test]
+    fn corrupt_files_are_rejected() {
+        let dir = TableDir::new("corrupt");
+        let table = wdl_table(5, Wdl::Win, Wdl::Loss);
+        let mut longer = table.clone();
+        longer.push(0);
+        let mut wrong_magic = table.clone();
+        wrong_magic[..4].copy_from_slice(&DTZ_MAGIC);
+        let mut with_pawns = table.clone();
+        with_pawns[4] |= 2;
+        let mut symmetric = table.clone();
+        symmetric[4] &= !1;
+        let mut compressed = table.clone();
+        compressed[10] &= !FLAG_SINGLE_VALUE;
+        for (case, data) in [
+            ("empty", &[][..]),
+            ("one byte too long", &longer[..]),
+            ("truncated to the header", &table[..16]),
+            ("wrong magic", &wrong_magic[..]),
+            ("pawn flag without pawns", &with_pawns[..]),
+            ("symmetric flag", &symmetric[..]),
+            ("compressed data past the end", &compressed[..]),
+        ] {
+            dir.write("KQvK.rtbw", data);
+            let tablebase = dir.open();
+            assert_eq!(tablebase.len(), 1, "{}", case);
+            assert_eq!(Board::from_fen("8/8/8/4k3/8/8/8/4KQ2 w - - 0 1").unwrap().probe_wdl(&tablebase), None, "{}", case);
+        }
+
+        // a DTZ file with the magic of a WDL file leaves the WDL table usable
+        dir.write("KQvK.rtbw", &table);
+        dir.write("KQvK.rtbz", &table);
+        let tablebase = dir.open();
+        let mut board = Board::from_fen("8/8/8/4k3/8/8/8/4KQ2 w - - 0 1").unwrap();
+        assert_eq!(board.probe_wdl(&tablebase), Some(Wdl::Win));
+        assert_eq!(probe_dtz(&mut board, &tablebase), None);
+        assert!(board.tablebase_move(&tablebase).is_none());
+    }
+}
@@ -0,0 +1,250 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::chess::SearchLimits;
+
+// moves the remaining time is shared between when the time control does not tell
+pub(crate) const DEFAULT_MOVES_TO_GO: u32 = 30;
+// kept on the clock for the time the GUI takes to receive the move and press the clock
+const MOVE_OVERHEAD: Duration = Duration::from_millis(50);
+// score drop between two iterations (in centipawns) at which the soft limit is doubled
+const SCORE_DROP_SCALE: i32 = 100;
+
+/**
+ * Clock of the side to move: remaining time, increment per move and number of moves to play before the next time control, if any.
+ */
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TimeControl {
+    pub remaining: Duration,
+    pub increment: Duration,
+    pub moves_to_go: Option<u32>,
+}
+
+/**
+ * Where the search reads the time from: time elapsed since an arbitrary origin, which must never go back.
+ */
+pub trait TimeSource: Send + Sync {
+    fn now(&self) -> Duration;
+}
+
+/**
+ * The real time, since the clock was created.
+ */
+pub(crate) struct WallClock {
+    origin: Instant,
+}
+
+impl WallClock {
+    pub(crate) fn new() -> Self {
+        Self { origin: Instant::now() }
+    }
+}
+
+impl TimeSource for WallClock {
+    fn now(&self) -> Duration {
+        self.origin.elapsed()
+    }
+}
+
+impl TimeControl {
+    /**
+     * Soft and hard time limits for the next move. No new iteration is started past the soft limit, the search is aborted at the hard one.
+     * The remaining time is shared between the moves to go, the increment being spent as it comes; an iteration started at the soft limit,
+     * half of that share, usually completes before the share is used up. The hard limit lets the search go on up to twice the share
+     * when the soft limit is extended, without ever using more than half of the remaining time (or all of it on the last move before the time control).
+     */
+    pub fn budgets(&self) -> (Duration, Duration) {
+        let available = self.remaining.saturating_sub(MOVE_OVERHEAD);
+        let moves_to_go = self.moves_to_go.unwrap_or(DEFAULT_MOVES_TO_GO).max(1);
+        let share = (available / moves_to_go + self.increment).min(available);
+        let cap = if moves_to_go == 1 {available} else {available / 2};
+        let hard = (share * 2).min(cap);
+        ((share / 2).min(hard), hard)
+    }
+}
+
+/**
+ * Decides when the search stops: the hard limit is checked while searching, the soft limit between two iterations of iterative deepening.
+ * The soft limit is extended when the best move keeps changing between iterations, or when the score drops (the new iteration failed low
+ * against the previous one): in both cases a deeper search is more likely to change the move played.
+ */
+pub(crate) struct TimeManager {
+    source: Arc<dyn TimeSource>,
+    start: Duration,
+    soft: Option<Duration>,
+    hard: Option<Duration>,
+    // grows by one each time the best move changes, and halves at each iteration
+    instability: f64,
+    // how much the score fell in the last iteration, up to SCORE_DROP_SCALE
+    score_drop: i32,
+    previous: Option<(u16, i32)>,
+}
+
+impl TimeManager {
+    pub(crate) fn new(limits: &SearchLimits) -> Self {
+        let source = limits.time_source.clone().unwrap_or_else(|| Arc::new(WallClock::new()));
+        let (mut soft, mut hard) = match limits.time_control {
+            Some(time_control) => {
+                let (soft, hard) = time_control.budgets();
+                (Some(soft), Some(hard))
+            },
+            None => (None, None),
+        };
+        if let Some(movetime) = limits.movetime {
+            hard = Some(hard.map_or(movetime, |hard| hard.min(movetime)));
+            soft = Some(soft.map_or(movetime / 2, |soft| soft.min(movetime / 2)));
+        }
+        Self {
+            start: source.now(),
+            source,
+            soft,
+            hard,
+            instability: 0.0,
+            score_drop: 0,
+            previous: None,
+        }
+    }
+
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.source.now().saturating_sub(self.start)
+    }
+
+    pub(crate) fn out_of_time(&self) -> bool {
+        self.hard.is_some_and(|hard| self.elapsed() >= hard)
+    }
+
+    /**
+     * Soft limit after the iterations reported so far.
+     */
+    pub(crate) fn soft_limit(&self) -> Option<Duration> {
+        let (soft, hard) = (self.soft?, self.hard?);
+        Some(soft.mul_f64(self.scale()).min(hard))
+    }
+
+    fn scale(&self) -> f64 {
+        (1.0 + self.instability) * (1.0 + self.score_drop as f64 / SCORE_DROP_SCALE as f64)
+    }
+
+    /**
+     * Report the best move (packed) and the score of the iteration just completed, and tell whether another one should be started.
+     */
+    pub(crate) fn iteration_done(&mut self, best_move: u16, score: i32) -> bool {
+        self.instability /= 2.0;
+        self.score_drop = 0;
+        if let Some((previous_move, previous_score)) = self.previous {
+            if previous_move != best_move {
+                self.instability += 1.0;
+            }
+            self.score_drop = (previous_score - score).clamp(0, SCORE_DROP_SCALE);
+        }
+        self.previous = Some((best_move, score));
+        self.soft_limit().is_none_or(|soft| self.elapsed() < soft)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::chess::{Board, SearchLimits, TranspositionTable};
+    use super::{TimeControl, TimeManager, TimeSource};
+
+    /**
+     * Clock advancing by a fixed step every time it is read, so that a search limited by time always stops at the same node.
+     */
+    struct MockClock {
+        micros: AtomicU64,
+        step: Duration,
+    }
+
+    impl MockClock {
+        fn new(step: Duration) -> Self {
+            Self { micros: AtomicU64::new(0), step }
+        }
+
+        fn advance(&self, duration: Duration) {
+            self.micros.fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+        }
+    }
+
+    impl TimeSource for MockClock {
+        fn now(&self) -> Duration {
+            Duration::from_micros(self.micros.fetch_add(self.step.as_micros() as u64, Ordering::Relaxed))
+        }
+    }
+
+    // remaining time and increment (in ms) and moves to go, with the soft and hard limits (in ms) the time manager gives them
+    const TIME_CONTROLS: [(u64, u64, Option<u32>, u64, u64); 6] = [
+        (60000, 0, None, 999, 3996),
+        (60000, 1000, None, 1499, 5996),
+        (10000, 0, Some(1), 4975, 9950),
+        (1000, 100, Some(10), 97, 390),
+        (2000, 3000, None, 975, 975),
+        (40, 0, None, 0, 0),
+    ];
+    // iterations completed by a search with a minute on the clock and iterations of 400 ms, when the best move stays the same,
+    // when it changes at every iteration, and when the score drops by a pawn at the third iteration
+    // best move (packed) and score of each iteration
+    type Iterations = &'static [(u16, i32)];
+    const ITERATION_RUNS: [(&str, Iterations, u32); 3] = [
+        ("stable best move", &[(1, 20); 10], 3),
+        ("changing best move", &[(1, 20), (2, 20), (1, 20), (2, 20), (1, 20), (2, 20), (1, 20), (2, 20), (1, 20), (2, 20)], 8),
+        ("score dropping", &[(1, 20), (1, 20), (1, -80), (1, -80), (1, -80), (1, -80), (1, -80), (1, -80), (1, -80), (1, -80)], 4),
+    ];
+
+    #[test]
+    fn soft_and_hard_budgets() {
+        for (remaining, increment, moves_to_go, soft, hard) in TIME_CONTROLS {
+            let time_control = TimeControl {
+                remaining: Duration::from_millis(remaining),
+                increment: Duration::from_millis(increment),
+                moves_to_go,
+            };
+            let (got_soft, got_hard) = time_control.budgets();
+            assert_eq!((got_soft.as_millis() as u64, got_hard.as_millis() as u64), (soft, hard), "{:?}", time_control);
+        }
+    }
+
+    #[test]
+    fn soft_limit_extensions() {
+        for (name, iterations, expected) in ITERATION_RUNS {
+            let clock = Arc::new(MockClock::new(Duration::ZERO));
+            let limits = SearchLimits {
+                time_control: Some(TimeControl { remaining: Duration::from_secs(60), ..Default::default() }),
+                time_source: Some(clock.clone()),
+                ..Default::default()
+            };
+            let mut time = TimeManager::new(&limits);
+            let mut completed = 0;
+            for (best_move, score) in iterations.iter().copied() {
+                clock.advance(Duration::from_millis(400));
+                completed += 1;
+                if !time.iteration_done(best_move, score) {
+                    break;
+                }
+            }
+            assert_eq!(completed, expected, "{}", name);
+        }
+    }
+
+    #[test]
+    fn search_stops_at_the_hard_limit() {
+        // with a clock advancing by 1 ms every 1024 nodes, the search stops at the same node every time
+        let search = || {
+            let limits = SearchLimits {
+                time_control: Some(TimeControl { remaining: Duration::from_secs(1), ..Default::default() }),
+                time_source: Some(Arc::new(MockClock::new(Duration::from_millis(1)))),
+                ..Default::default()
+            };
+            Board::new().search(&limits, &TranspositionTable::new(1), |_| {})
+        };
+        let (first, second) = (search(), search());
+        assert_eq!((first.nodes, first.depth), (second.nodes, second.depth));
+        assert!(first.best_move.is_some());
+        // the last poll of the clock may come one step after the limit
+        let (_, hard) = TimeControl { remaining: Duration::from_secs(1), ..Default::default() }.budgets();
+        assert!(first.elapsed <= hard + Duration::from_millis(1), "{:?} past {:?}", first.elapsed, hard);
+    }
+}
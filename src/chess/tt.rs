@@ -0,0 +1,315 @@
+use std::mem;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+
+use crate::chess::{Move, PieceType};
+use crate::chess::bitboard;
+use crate::chess::search::{MATE_SCORE, MAX_DEPTH};
+
+pub const DEFAULT_HASH_MB: usize = 16;
+pub const MAX_HASH_MB: usize = 4096;
+
+/**
+ * How the stored score relates to the real score of the position.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Bound {
+    // the search returned the exact score
+    Exact,
+    // the search failed high: the score is at least the stored one
+    Lower,
+    // the search failed low: the score is at most the stored one
+    Upper,
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    best_move: u16,
+    score: i16,
+    depth: u8,
+    bound: Bound,
+    age: u8,
+}
+
+impl Entry {
+    /**
+     * The entry in 64 bits: best move, score, depth, bound and age, from the lowest bits. An empty slot is all zeros, with depth 0.
+     */
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Upper => 0,
+            Bound::Lower => 1,
+            Bound::Exact => 2,
+        };
+        self.best_move as u64 | (self.score as u16 as u64) << 16 | (self.depth as u64) << 32 | bound << 40 | (self.age as u64) << 48
+    }
+
+    fn unpack(data: u64) -> Self {
+        Self {
+            best_move: data as u16,
+            score: (data >> 16) as u16 as i16,
+            depth: (data >> 32) as u8,
+            bound: match (data >> 40) & 3 {
+                0 => Bound::Upper,
+                1 => Bound::Lower,
+                _ => Bound::Exact,
+            },
+            age: (data >> 48) as u8,
+        }
+    }
+}
+
+/**
+ * One slot of the table, read and written by all the search threads without locking. The key is stored xored with the data:
+ * when two threads write the slot at the same time and it ends up with the key of one and the data of the other, it matches no key.
+ */
+#[derive(Default)]
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+impl Slot {
+    /**
+     * The key and the entry of the slot; a torn slot gives a key which matches no position.
+     */
+    fn load(&self) -> (u64, Entry) {
+        let data = self.data.load(Ordering::Relaxed);
+        let key = self.key.load(Ordering::Relaxed) ^ data;
+        (key, Entry::unpack(data))
+    }
+
+    fn save(&self, key: u64, entry: &Entry) {
+        let data = entry.pack();
+        self.key.store(key ^ data, Ordering::Relaxed);
+        self.data.store(data, Ordering::Relaxed);
+    }
+}
+
+/**
+ * What the table knows about a position.
+ */
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct TtHit {
+    pub(crate) best_move: u16,
+    pub(crate) score: i32,
+    pub(crate) depth: u32,
+    pub(crate) bound: Bound,
+}
+
+/**
+ * Fixed-size hash table of search results, indexed by the Zobrist key of the position.
+ * Each slot holds one position; a new result replaces the old one unless the old one is from the current search and was searched deeper.
+ * The table is shared by the threads of a search, which read and write it at the same time without locks.
+ */
+pub struct TranspositionTable {
+    slots: Vec<Slot>,
+    // incremented at every search, so that entries of previous searches are replaced first
+    age: AtomicU8,
+}
+
+/**
+ * Move packed in 16 bits: starting square, target square and promotion piece. 0 means no move.
+ */
+pub(crate) fn pack_move(from: (u8, u8), mv: &Move) -> u16 {
+    let promotion = match mv.promotion {
+        None => 0,
+        Some(PieceType::Knight) => 1,
+        Some(PieceType::Bishop) => 2,
+        Some(PieceType::Rook) => 3,
+        Some(_) => 4,
+    };
+    (bitboard::square(from.0, from.1) as u16) | (bitboard::square(mv.to.0, mv.to.1) as u16) << 6 | promotion << 12
+}
+
+/**
+ * Mate scores are stored as the distance to the mate from the stored position rather than from the root.
+ */
+fn score_to_tt(score: i32, ply: u32) -> i16 {
+    let score = if score >= MATE_SCORE - MAX_DEPTH as i32 {
+        score + ply as i32
+    } else if score <= -MATE_SCORE + MAX_DEPTH as i32 {
+        score - ply as i32
+    } else {
+        score
+    };
+    score as i16
+}
+
+fn score_from_tt(score: i16, ply: u32) -> i32 {
+    let score = score as i32;
+    if score >= MATE_SCORE - MAX_DEPTH as i32 {
+        score - ply as i32
+    } else if score <= -MATE_SCORE + MAX_DEPTH as i32 {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+impl TranspositionTable {
+    pub fn new(megabytes: usize) -> Self {
+        let mut table = Self {
+            slots: Vec::new(),
+            age: AtomicU8::new(0),
+        };
+        table.resize(megabytes);
+        table
+    }
+
+    /**
+     * Reallocate the table to use (about) the given amount of memory, forgetting everything stored so far.
+     */
+    pub fn resize(&mut self, megabytes: usize) {
+        let megabytes = megabytes.clamp(1, MAX_HASH_MB);
+        self.slots = (0..megabytes * 1024 * 1024 / mem::size_of::<Slot>()).map(|_| Slot::default()).collect();
+        *self.age.get_mut() = 0;
+    }
+
+    /**
+     * Forget everything stored, e.g. before a new game.
+     */
+    pub fn clear(&mut self) {
+        for slot in self.slots.iter_mut() {
+            *slot = Slot::default();
+        }
+        *self.age.get_mut() = 0;
+    }
+
+    /**
+     * Start a new search: entries stored from now on take precedence over the older ones.
+     */
+    pub(crate) fn new_search(&self) {
+        self.age.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn slot(&self, key: u64) -> &Slot {
+        &self.slots[((key as u128 * self.slots.len() as u128) >> 64) as usize]
+    }
+
+    pub(crate) fn probe(&self, key: u64, ply: u32) -> Option<TtHit> {
+        let (stored_key, entry) = self.slot(key).load();
+        // nothing is stored at depth 0, so that marks empty slots
+        if stored_key != key || entry.depth == 0 {
+            return None;
+        }
+        Some(TtHit {
+            best_move: entry.best_move,
+            score: score_from_tt(entry.score, ply),
+            depth: entry.depth as u32,
+            bound: entry.bound,
+        })
+    }
+
+    pub(crate) fn store(&self, key: u64, depth: u32, score: i32, ply: u32, bound: Bound, best_move: u16) {
+        let age = self.age.load(Ordering::Relaxed);
+        let slot = self.slot(key);
+        let (stored_key, entry) = slot.load();
+        if stored_key != key && entry.age == age && entry.depth as u32 > depth {
+            return;
+        }
+        // a fail low does not know the best move, keep the one found by an earlier search of the same position
+        let best_move = if best_move == 0 && stored_key == key {entry.best_move} else {best_move};
+        slot.save(key, &Entry {
+            best_move,
+            score: score_to_tt(score, ply),
+            depth: depth as u8,
+            bound,
+            age,
+        });
+    }
+
+    /**
+     * Permille of the table filled by the current search, estimated on the first thousand slots, as reported by UCI "hashfull".
+     */
+    pub(crate) fn hashfull(&self) -> usize {
+        let age = self.age.load(Ordering::Relaxed);
+        let sample = &self.slots[..self.slots.len().min(1000)];
+        sample.iter().map(|slot| slot.load().1).filter(|entry| entry.age == age && entry.depth != 0).count() * 1000 / sample.len()
+    }
+}
+
+impl Default for TranspositionTable {
+    fn default() -> Self {
+        Self::new(DEFAULT_HASH_MB)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // two keys of the same slot, in a table of any size
+    const KEY: u64 = 0x9E37_79B9_7F4A_7C15;
+    const OTHER_KEY: u64 = KEY + 1;
+
+    #[test]
+    fn mate_scores_are_stored_from_the_position() {
+        // a mate in 5 plies from the root found 3 plies deep is a mate in 2 plies from there, and in 9 plies when met again 7 plies deep
+        assert_eq!(score_to_tt(MATE_SCORE - 5, 3), (MATE_SCORE - 2) as i16);
+        assert_eq!(score_from_tt((MATE_SCORE - 2) as i16, 7), MATE_SCORE - 9);
+        assert_eq!(score_to_tt(-MATE_SCORE + 5, 3), (-MATE_SCORE + 2) as i16);
+        assert_eq!(score_from_tt((-MATE_SCORE + 2) as i16, 7), -MATE_SCORE + 9);
+        for score in [0, 150, -150, MATE_SCORE - MAX_DEPTH as i32 - 1, -MATE_SCORE + MAX_DEPTH as i32 + 1] {
+            assert_eq!(score_from_tt(score_to_tt(score, 10), 4), score);
+        }
+
+        let tt = TranspositionTable::new(1);
+        tt.store(KEY, 6, MATE_SCORE - 5, 3, Bound::Exact, 0);
+        assert_eq!(tt.probe(KEY, 7).map(|hit| hit.score), Some(MATE_SCORE - 9));
+    }
+
+    #[test]
+    fn deeper_entries_of_the_search_are_kept() {
+        let tt = TranspositionTable::new(1);
+        assert!(std::ptr::eq(tt.slot(KEY), tt.slot(OTHER_KEY)));
+        tt.new_search();
+        tt.store(KEY, 8, 10, 0, Bound::Exact, 1);
+        tt.store(OTHER_KEY, 4, 20, 0, Bound::Exact, 2);
+        assert_eq!(tt.probe(KEY, 0).map(|hit| (hit.depth, hit.score)), Some((8, 10)));
+        assert!(tt.probe(OTHER_KEY, 0).is_none());
+        // the same position is always replaced, however shallow
+        tt.store(KEY, 2, 30, 0, Bound::Lower, 3);
+        assert_eq!(tt.probe(KEY, 0).map(|hit| (hit.depth, hit.score, hit.bound)), Some((2, 30, Bound::Lower)));
+
+        // an entry of an earlier search is replaced, however deep
+        tt.store(KEY, 9, 10, 0, Bound::Exact, 1);
+        tt.new_search();
+        tt.store(OTHER_KEY, 1, 20, 0, Bound::Upper, 2);
+        assert!(tt.probe(KEY, 0).is_none());
+        assert_eq!(tt.probe(OTHER_KEY, 0).map(|hit| (hit.depth, hit.score, hit.bound)), Some((1, 20, Bound::Upper)));
+    }
+
+    #[test]
+    fn fail_low_keeps_the_best_move() {
+        let tt = TranspositionTable::new(1);
+        tt.store(KEY, 3, 10, 0, Bound::Exact, 123);
+        tt.store(KEY, 4, -50, 0, Bound::Upper, 0);
+        assert_eq!(tt.probe(KEY, 0).map(|hit| (hit.best_move, hit.depth)), Some((123, 4)));
+        // not the one of another position
+        tt.store(OTHER_KEY, 5, -50, 0, Bound::Upper, 0);
+        assert_eq!(tt.probe(OTHER_KEY, 0).map(|hit| hit.best_move), Some(0));
+    }
+
+    #[test]
+    fn clear_and_resize_forget_everything() {
+        let mut tt = TranspositionTable::new(1);
+        tt.store(KEY, 3, 10, 0, Bound::Exact, 1);
+        tt.clear();
+        assert!(tt.probe(KEY, 0).is_none());
+        tt.store(KEY, 3, 10, 0, Bound::Exact, 1);
+        tt.resize(2);
+        assert!(tt.probe(KEY, 0).is_none());
+        assert_eq!(tt.hashfull(), 0);
+    }
+
+    #[test]
+    fn probe_needs_the_same_key() {
+        let tt = TranspositionTable::new(1);
+        // an empty slot is all zeros, the same as an entry of key 0
+        assert!(tt.probe(0, 0).is_none());
+        assert!(tt.probe(KEY, 0).is_none());
+        tt.store(KEY, 3, 10, 0, Bound::Exact, 1);
+        assert!(tt.probe(OTHER_KEY, 0).is_none());
+        assert!(tt.probe(KEY, 0).is_some());
+    }
+}
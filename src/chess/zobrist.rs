@@ -0,0 +1,114 @@
+use crate::chess::{Board, PieceColor, PieceType};
+use crate::chess::bitboard::{self, PAWN_ATTACKS};
+
+/**
+ * Pseudo-random keys generated at compile time with splitmix64, so that hashes are the same on every run.
+ */
+struct Keys {
+    pieces: [[[u64; 64]; 6]; 2],
+    castling: [u64; 4],
+    en_passant: [u64; 8],
+    black_to_move: u64,
+}
+
+const fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+const fn generate_keys() -> Keys {
+    let mut state = 0x5EED_C0FF_EE15_600D;
+    let mut keys = Keys {
+        pieces: [[[0; 64]; 6]; 2],
+        castling: [0; 4],
+        en_passant: [0; 8],
+        black_to_move: 0,
+    };
+    let mut i = 0;
+    while i < 2 * 6 * 64 {
+        keys.pieces[i / 384][i / 64 % 6][i % 64] = splitmix64(&mut state);
+        i += 1;
+    }
+    i = 0;
+    while i < 4 {
+        keys.castling[i] = splitmix64(&mut state);
+        i += 1;
+    }
+    i = 0;
+    while i < 8 {
+        keys.en_passant[i] = splitmix64(&mut state);
+        i += 1;
+    }
+    keys.black_to_move = splitmix64(&mut state);
+    keys
+}
+
+const KEYS: Keys = generate_keys();
+
+pub(crate) fn piece_key(color: PieceColor, piece_type: PieceType, square: usize) -> u64 {
+    KEYS.pieces[color.index()][piece_type.index()][square]
+}
+
+impl Board {
+    /**
+     * Zobrist key of the position: pieces, side to move, castling rights and en passant file.
+     * Two positions have the same key when they are the same position in the sense of the FIDE repetition rule.
+     */
+    pub fn hash(&self) -> u64 {
+        self.hash
+    }
+
+    /**
+     * Part of the key which does not depend on the pieces: side to move, castling rights and en passant.
+     * The en passant file only counts when a pawn of the side to move is next to the double-pushed pawn, since otherwise the position is the same as without the right.
+     */
+    pub(crate) fn state_key(&self) -> u64 {
+        let mut key = 0;
+        if self.turn == PieceColor::Black {
+            key ^= KEYS.black_to_move;
+        }
+        for (i, right) in [self.wk_castle, self.wq_castle, self.bk_castle, self.bq_castle].iter().enumerate() {
+            if *right {
+                key ^= KEYS.castling[i];
+            }
+        }
+        if let Some((row, col)) = self.en_passant {
+            let capturers = PAWN_ATTACKS[self.turn.opposite().index()][bitboard::square(row, col)] & self.pieces_of(self.turn, PieceType::Pawn);
+            if capturers != 0 {
+                key ^= KEYS.en_passant[col as usize];
+            }
+        }
+        key
+    }
+
+    /**
+     * Compute the key from scratch; play_move and rollback_move keep it up to date incrementally afterwards.
+     */
+    pub(crate) fn compute_hash(&self) -> u64 {
+        let mut key = self.state_key();
+        for color in [PieceColor::White, PieceColor::Black] {
+            for piece_type in [PieceType::Pawn, PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen, PieceType::King] {
+                for square in bitboard::squares(self.pieces_of(color, piece_type)) {
+                    key ^= piece_key(color, piece_type, square);
+                }
+            }
+        }
+        key
+    }
+
+    /**
+     * Number of times the current position occurred, counting the current one.
+     * Only positions since the last capture or pawn move can be repetitions, and only every other one has the same side to move.
+     */
+    pub(crate) fn repetitions(&self) -> usize {
+        1 + self.history.iter().rev()
+            .take(self.halfmove_clock as usize)
+            .skip(1)
+            .step_by(2)
+            .filter(|data| data.hash == self.hash())
+            .count()
+    }
+}
@@ -0,0 +1,288 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+use crate::chess::{Board, PieceColor, SearchLimits, TranspositionTable, ENGINE_NAME};
+
+const HELP: &str = "Moves are entered in SAN (e4, Nf3, exd5, O-O, e8=Q) or UCI notation (e2e4, e7e8q).
+Commands:
+  undo        take back your last move and the reply to it
+  fen         print the position as FEN
+  fen FEN     set up the position described by FEN
+  board       print the board
+  flip        turn the board around
+  new         start a new game
+  help        print this message
+  quit        leave";
+
+/**
+ * Play against the engine in the terminal, reading moves and commands from stdin one per line.
+ */
+pub struct Cli<W: Write = io::Stdout> {
+    board: Board,
+    human: PieceColor,
+    limits: SearchLimits,
+    tt: TranspositionTable,
+    unicode: bool,
+    flipped: bool,
+    // where the board, the engine moves and the messages are printed
+    output: W,
+}
+
+impl Cli {
+    pub fn new(board: Board, human: PieceColor, limits: SearchLimits, unicode: bool) -> Self {
+        Self::with_output(board, human, limits, unicode, io::stdout())
+    }
+
+    pub fn run(&mut self) {
+        self.play(io::stdin().lock());
+    }
+}
+
+impl<W: Write> Cli<W> {
+    fn with_output(board: Board, human: PieceColor, limits: SearchLimits, unicode: bool, output: W) -> Self {
+        Self {
+            board,
+            human,
+            limits,
+            tt: TranspositionTable::default(),
+            unicode,
+            flipped: human == PieceColor::Black,
+            output,
+        }
+    }
+
+    /**
+     * Start the game and handle the lines of input until "quit" or the end of the input.
+     */
+    fn play(&mut self, input: impl BufRead) {
+        self.print(format!("You play {:?}, type help for the list of commands.", self.human));
+        self.start();
+        self.prompt();
+        for line in input.lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if !self.handle_command(&line) {
+                break;
+            }
+            self.prompt();
+        }
+    }
+
+    /**
+     * Handle a single line typed by the user, returning false when the program should quit.
+     */
+    fn handle_command(&mut self, line: &str) -> bool {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.first() {
+            Some(&"quit") | Some(&"exit") => return false,
+            Some(&"help") => self.print(HELP),
+            Some(&"new") => {
+                self.board = Board::new();
+                self.tt.clear();
+                self.start();
+            },
+            Some(&"undo") => self.undo(),
+            Some(&"fen") if tokens.len() == 1 => self.print(self.board.to_fen()),
+            Some(&"fen") => match Board::from_fen_lenient(&tokens[1..].join(" ")) {
+                Ok(board) => {
+                    self.board = board;
+                    self.start();
+                },
+                Err(e) => self.print(format_args!("Invalid FEN: {}", e)),
+            },
+            Some(&"board") => self.show(),
+            Some(&"flip") => {
+                self.flipped = !self.flipped;
+                self.show();
+            },
+            Some(notation) => self.human_move(notation),
+            None => {},
+        }
+        true
+    }
+
+    /**
+     * Show a newly set up position and let the engine move first if it is its turn.
+     */
+    fn start(&mut self) {
+        self.show();
+        if !self.report_outcome() {
+            self.engine_move();
+        }
+    }
+
+    fn human_move(&mut self, notation: &str) {
+        if let Some(outcome) = self.board.outcome() {
+            self.print(format_args!("The game is over ({}), type new or undo.", outcome));
+            return;
+        }
+        match self.board.parse_move(notation) {
+            Ok((from, mv)) => {
+                self.board.play_move(from, &mv);
+                if self.report_outcome() {
+                    self.show();
+                } else {
+                    self.engine_move();
+                }
+            },
+            Err(e) => self.print(format_args!("{}, type help for the list of commands.", e)),
+        }
+    }
+
+    /**
+     * Let the engine reply when it is its turn and the game is not over.
+     */
+    fn engine_move(&mut self) {
+        if self.board.side_to_move() == self.human || self.board.outcome().is_some() {
+            return;
+        }
+        let result = self.board.search(&self.limits, &self.tt, |_| {});
+        if let Some((row, col, mv)) = result.best_move {
+            let san = self.board.san((row, col), &mv);
+            self.print(format_args!("{} plays {}", ENGINE_NAME, san));
+            self.board.play_move((row, col), &mv);
+        }
+        self.show();
+        self.report_outcome();
+    }
+
+    /**
+     * Take back moves until it is the user's turn again: usually the engine's reply and the user's move before it.
+     */
+    fn undo(&mut self) {
+        if self.board.plies_played() == 0 {
+            self.print("There is no move to take back.");
+            return;
+        }
+        self.board.rollback_move();
+        while self.board.side_to_move() != self.human && self.board.plies_played() > 0 {
+            self.board.rollback_move();
+        }
+        self.show();
+        self.engine_move();
+    }
+
+    fn show(&mut self) {
+        let diagram = self.board.diagram(self.unicode, self.flipped);
+        let _ = write!(self.output, "{}", diagram);
+    }
+
+    fn print(&mut self, message: impl fmt::Display) {
+        let _ = writeln!(self.output, "{}", message);
+    }
+
+    fn prompt(&mut self) {
+        let _ = write!(self.output, "> ");
+        let _ = self.output.flush();
+    }
+
+    /**
+     * Print how the game ended, if it did.
+     */
+    fn report_outcome(&mut self) -> bool {
+        match self.board.outcome() {
+            Some(outcome) => {
+                self.print(outcome);
+                true
+            },
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::chess::{Board, PieceColor, SearchLimits};
+    use super::Cli;
+
+    const START: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+    /**
+     * Feed the lines to the game, returning what was printed.
+     */
+    fn play(cli: &mut Cli<Vec<u8>>, input: &str) -> String {
+        cli.output.clear();
+        cli.play(input.as_bytes());
+        String::from_utf8(cli.output.clone()).unwrap()
+    }
+
+    // the engine only searches to depth 1
+    fn game(human: PieceColor) -> Cli<Vec<u8>> {
+        let limits = SearchLimits { depth: Some(1), ..Default::default() };
+        Cli::with_output(Board::new(), human, limits, false, Vec::new())
+    }
+
+    #[test]
+    fn moves_are_answered() {
+        let mut cli = game(PieceColor::White);
+        let output = play(&mut cli, "e4\nNf3\n");
+        assert_eq!(cli.board.plies_played(), 4);
+        assert_eq!(output.matches(" plays ").count(), 2);
+        let output = play(&mut cli, "Qh8\nfoo\n");
+        assert!(output.contains("illegal move 'Qh8', type help"), "{}", output);
+        assert!(output.contains("'foo' is not a move"), "{}", output);
+        assert_eq!(cli.board.plies_played(), 4);
+
+        // the engine starts when it plays white
+        let mut cli = game(PieceColor::Black);
+        play(&mut cli, "");
+        assert_eq!((cli.board.plies_played(), cli.board.side_to_move()), (1, PieceColor::Black));
+    }
+
+    #[test]
+    fn undo_takes_back_the_reply_too() {
+        let mut cli = game(PieceColor::White);
+        play(&mut cli, "e4\nundo\n");
+        assert_eq!(cli.board.to_fen(), START);
+        let output = play(&mut cli, "undo\n");
+        assert!(output.contains("There is no move to take back."), "{}", output);
+
+        // the engine moved first: only the user's move and the reply are taken back, and the engine plays its first move again
+        let mut cli = game(PieceColor::Black);
+        play(&mut cli, "undo\n");
+        assert_eq!(cli.board.plies_played(), 1);
+    }
+
+    #[test]
+    fn fen_sets_up_and_prints_the_position() {
+        let mut cli = game(PieceColor::White);
+        let fen = "4k3/8/8/8/8/8/8/4K2R w K - 0 1";
+        let output = play(&mut cli, &format!("fen {}\nfen\n", fen));
+        assert!(output.contains(&format!("> {}\n", fen)), "{}", output);
+        assert_eq!(cli.board.to_fen(), fen);
+
+        let output = play(&mut cli, "fen 4k3/8/8 w\n");
+        assert!(output.contains("Invalid FEN"), "{}", output);
+        assert_eq!(cli.board.to_fen(), fen);
+
+        // the engine answers a position where it is to move
+        play(&mut cli, "fen 4k3/8/8/8/8/8/8/4K2R b K - 0 1\n");
+        assert_eq!(cli.board.side_to_move(), PieceColor::White);
+
+        // a finished game takes no more moves
+        let output = play(&mut cli, "fen 7k/6Q1/6K1/8/8/8/8/8 b - - 0 1\nKh7\n");
+        assert!(output.contains("Checkmate, White wins"), "{}", output);
+        assert!(output.contains("The game is over (Checkmate, White wins), type new or undo."), "{}", output);
+    }
+
+    #[test]
+    fn flip_and_new() {
+        let mut cli = game(PieceColor::White);
+        play(&mut cli, "flip\n");
+        assert!(cli.flipped);
+        play(&mut cli, "e4\nflip\nnew\n");
+        assert!(!cli.flipped);
+        assert_eq!(cli.board.to_fen(), START);
+    }
+
+    #[test]
+    fn quit_stops_reading() {
+        let mut cli = game(PieceColor::White);
+        play(&mut cli, "quit\ne4\n");
+        assert_eq!(cli.board.plies_played(), 0);
+        play(&mut cli, "exit\ne4\n");
+        assert_eq!(cli.board.plies_played(), 0);
+    }
+}
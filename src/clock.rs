@@ -0,0 +1,287 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::chess::{PieceColor, TimeControl, TimeSource, WallClock};
+
+/**
+ * Time control of a game: the time each player starts with, plus either an increment added after each move
+ * or a delay during which the clock does not run at the start of each move.
+ */
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ClockSettings {
+    base: Duration,
+    increment: Duration,
+    delay: Duration,
+}
+
+impl ClockSettings {
+    /**
+     * Parse "MINUTES", "MINUTES+INCREMENT" or "MINUTESdDELAY", the increment and the delay being in seconds, e.g. "5+3" or "90d30".
+     */
+    pub(crate) fn parse(spec: &str) -> Result<Self, String> {
+        let seconds = |value: &str| value.parse::<f64>().ok()
+            .filter(|value| value.is_finite() && *value >= 0.0)
+            .ok_or_else(|| format!("invalid time control '{}', expected MINUTES, MINUTES+INCREMENT or MINUTESdDELAY", spec));
+        let (base, increment, delay) = if let Some((base, increment)) = spec.split_once('+') {
+            (base, seconds(increment)?, 0.0)
+        } else if let Some((base, delay)) = spec.split_once('d') {
+            (base, 0.0, seconds(delay)?)
+        } else {
+            (spec, 0.0, 0.0)
+        };
+        let base = seconds(base)? * 60.0;
+        if base == 0.0 {
+            return Err(format!("invalid time control '{}', the base time cannot be zero", spec));
+        }
+        Ok(Self {
+            base: Duration::from_secs_f64(base),
+            increment: Duration::from_secs_f64(increment),
+            delay: Duration::from_secs_f64(delay),
+        })
+    }
+
+    /**
+     * Value of the PGN TimeControl tag: seconds per player, followed by the increment in seconds if any.
+     * A delay has no standard notation and is left out.
+     */
+    pub(crate) fn pgn_tag(&self) -> String {
+        if self.increment.is_zero() {
+            format!("{}", self.base.as_secs())
+        } else {
+            format!("{}+{}", self.base.as_secs(), self.increment.as_secs_f64())
+        }
+    }
+}
+
+/**
+ * The clocks of both players. Only the clock of the side to move runs; pressing it after a move stops it, adds the increment and starts the other one.
+ */
+pub(crate) struct ChessClock {
+    settings: ClockSettings,
+    // indexed by PieceColor::index, not counting the time of the move being played
+    remaining: [Duration; 2],
+    // the side whose clock is running, and when its move started
+    running: Option<(PieceColor, Duration)>,
+    source: Arc<dyn TimeSource>,
+}
+
+impl ChessClock {
+    pub(crate) fn new(settings: ClockSettings) -> Self {
+        Self::with_source(settings, Arc::new(WallClock::new()))
+    }
+
+    /**
+     * Clocks reading the time from source, e.g. a mock clock in tests.
+     */
+    fn with_source(settings: ClockSettings, source: Arc<dyn TimeSource>) -> Self {
+        Self {
+            settings,
+            remaining: [settings.base; 2],
+            running: None,
+            source,
+        }
+    }
+
+    pub(crate) fn settings(&self) -> &ClockSettings {
+        &self.settings
+    }
+
+    pub(crate) fn start(&mut self, color: PieceColor) {
+        self.running = Some((color, self.source.now()));
+    }
+
+    pub(crate) fn running(&self) -> Option<PieceColor> {
+        self.running.map(|(color, _)| color)
+    }
+
+    /**
+     * Time charged to the side to move so far: the time since its move started, minus the delay.
+     */
+    fn used(&self, started: Duration) -> Duration {
+        self.source.now().saturating_sub(started).saturating_sub(self.settings.delay)
+    }
+
+    pub(crate) fn remaining(&self, color: PieceColor) -> Duration {
+        let remaining = self.remaining[color.index()];
+        match self.running {
+            Some((running, started)) if running == color => remaining.saturating_sub(self.used(started)),
+            _ => remaining,
+        }
+    }
+
+    pub(crate) fn flagged(&self, color: PieceColor) -> bool {
+        self.remaining(color).is_zero()
+    }
+
+    /**
+     * Stop the running clock, charging the time of the move being played, e.g. when the game ends.
+     */
+    pub(crate) fn stop(&mut self) {
+        if let Some((color, _)) = self.running {
+            self.remaining[color.index()] = self.remaining(color);
+            self.running = None;
+        }
+    }
+
+    /**
+     * The side to move has played: stop its clock, add the increment and start the clock of the opponent.
+     * Returns false, leaving the clocks stopped, if the move came after the time ran out.
+     */
+    pub(crate) fn press(&mut self) -> bool {
+        let Some((color, _)) = self.running else {
+            return true;
+        };
+        self.stop();
+        if self.flagged(color) {
+            return false;
+        }
+        self.remaining[color.index()] += self.settings.increment;
+        self.start(color.opposite());
+        true
+    }
+
+    /**
+     * The clock of color as seen by the engine. The delay is given as an increment, since it saves the same time on every move.
+     */
+    pub(crate) fn time_control(&self, color: PieceColor) -> TimeControl {
+        TimeControl {
+            remaining: self.remaining(color),
+            increment: self.settings.increment + self.settings.delay,
+            moves_to_go: None,
+        }
+    }
+}
+
+/**
+ * Remaining time as shown on the clock: "h:mm:ss", "m:ss", or seconds and tenths under ten seconds.
+ */
+pub(crate) fn format_time(time: Duration) -> String {
+    let seconds = time.as_secs();
+    if seconds >= 3600 {
+        format!("{}:{:02}:{:02}", seconds / 3600, seconds / 60 % 60, seconds % 60)
+    } else if seconds >= 10 {
+        format!("{}:{:02}", seconds / 60, seconds % 60)
+    } else {
+        format!("0:{:02}.{}", seconds, time.subsec_millis() / 100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::chess::{PieceColor, TimeSource};
+    use super::{format_time, ChessClock, ClockSettings};
+
+    /**
+     * Clock which only moves when told to.
+     */
+    #[derive(Default)]
+    struct MockClock {
+        millis: AtomicU64,
+    }
+
+    impl MockClock {
+        fn advance(&self, seconds: f64) {
+            self.millis.fetch_add((seconds * 1000.0) as u64, Ordering::Relaxed);
+        }
+    }
+
+    impl TimeSource for MockClock {
+        fn now(&self) -> Duration {
+            Duration::from_millis(self.millis.load(Ordering::Relaxed))
+        }
+    }
+
+    fn clocks(spec: &str) -> (ChessClock, Arc<MockClock>) {
+        let source = Arc::new(MockClock::default());
+        (ChessClock::with_source(ClockSettings::parse(spec).unwrap(), source.clone()), source)
+    }
+
+    fn seconds(clock: &ChessClock, color: PieceColor) -> f64 {
+        clock.remaining(color).as_secs_f64()
+    }
+
+    #[test]
+    fn parse_settings() {
+        for (spec, base, increment, delay, tag) in [
+            ("5", 300.0, 0.0, 0.0, "300"),
+            ("5+3", 300.0, 3.0, 0.0, "300+3"),
+            ("0.5+0.5", 30.0, 0.5, 0.0, "30+0.5"),
+            ("90d30", 5400.0, 0.0, 30.0, "5400"),
+        ] {
+            let settings = ClockSettings::parse(spec).unwrap();
+            assert_eq!((settings.base.as_secs_f64(), settings.increment.as_secs_f64(), settings.delay.as_secs_f64()), (base, increment, delay), "{}", spec);
+            assert_eq!(settings.pgn_tag(), tag);
+        }
+        for spec in ["0", "0+5", "x", "5+x", "5d", "-1", ""] {
+            assert!(ClockSettings::parse(spec).is_err(), "{}", spec);
+        }
+    }
+
+    #[test]
+    fn press_adds_the_increment() {
+        let (mut clock, source) = clocks("1+2");
+        clock.start(PieceColor::White);
+        source.advance(5.0);
+        assert_eq!(seconds(&clock, PieceColor::White), 55.0);
+        assert!(clock.press());
+        assert_eq!(clock.running(), Some(PieceColor::Black));
+        source.advance(1.0);
+        assert_eq!((seconds(&clock, PieceColor::White), seconds(&clock, PieceColor::Black)), (57.0, 59.0));
+        assert_eq!(clock.time_control(PieceColor::Black).increment, Duration::from_secs(2));
+    }
+
+    #[test]
+    fn press_after_the_flag_fell() {
+        let (mut clock, source) = clocks("1+2");
+        clock.start(PieceColor::White);
+        source.advance(61.0);
+        assert!(clock.flagged(PieceColor::White));
+        assert!(!clock.press());
+        assert_eq!(clock.running(), None);
+        assert_eq!(seconds(&clock, PieceColor::White), 0.0);
+    }
+
+    #[test]
+    fn delay_is_not_charged() {
+        let (mut clock, source) = clocks("1d5");
+        clock.start(PieceColor::White);
+        source.advance(3.0);
+        assert_eq!(seconds(&clock, PieceColor::White), 60.0);
+        source.advance(4.0);
+        assert_eq!(seconds(&clock, PieceColor::White), 58.0);
+        assert!(clock.press());
+        assert_eq!(seconds(&clock, PieceColor::White), 58.0);
+        // the engine sees the delay as an increment
+        assert_eq!(clock.time_control(PieceColor::White).increment, Duration::from_secs(5));
+    }
+
+    #[test]
+    fn stop_freezes_the_clock() {
+        let (mut clock, source) = clocks("1");
+        clock.start(PieceColor::Black);
+        source.advance(10.0);
+        clock.stop();
+        source.advance(10.0);
+        assert_eq!(clock.running(), None);
+        assert_eq!((seconds(&clock, PieceColor::White), seconds(&clock, PieceColor::Black)), (60.0, 50.0));
+    }
+
+    #[test]
+    fn time_format() {
+        for (millis, expected) in [
+            (3_661_000, "1:01:01"),
+            (3_600_000, "1:00:00"),
+            (600_000, "10:00"),
+            (59_999, "0:59"),
+            (10_000, "0:10"),
+            (9_870, "0:09.8"),
+            (0, "0:00.0"),
+        ] {
+            assert_eq!(format_time(Duration::from_millis(millis)), expected);
+        }
+    }
+}
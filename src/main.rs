@@ -1,15 +1,339 @@
 mod chess;
+mod cli;
+mod clock;
+mod selfplay;
+mod uci;
 
 use gtk::{gdk_pixbuf, glib, prelude::*, Picture};
 use gtk::{Application, ApplicationWindow, Button, Grid};
 use gtk::gdk;
-use chess::Board;
+use chess::{pgn_date, Board, GameOutcome, OpeningBook, PgnGame, Piece, PieceColor, PieceType, SearchLimits, Tablebase, TranspositionTable, DEFAULT_BOOK_DEPTH, ENGINE_NAME};
+use clock::{format_time, ChessClock, ClockSettings};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 
 const BOARD_SIZE: usize = 8;
 const SQUARE_PIXELS: usize = 60;
+const THINKING_TIME: Duration = Duration::from_millis(500);
+// delay before the computer moves, so that the window gets the chance to draw the previous move
+const MOVE_DELAY: Duration = Duration::from_millis(100);
+// how often the clocks are redrawn and checked for a fallen flag
+const CLOCK_TICK: Duration = Duration::from_millis(100);
+// how often the GUI checks whether the engine, searching on its own thread, has found its move
+const SEARCH_POLL: Duration = Duration::from_millis(20);
+const TITLE: &str = "Chessboard";
+const PROMOTION_CHOICES: [PieceType; 4] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight];
 
-fn show_board() {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Player {
+    Human,
+    Engine,
+    Random,
+}
+
+impl Player {
+    fn name(&self) -> &'static str {
+        match self {
+            Player::Human => "Human",
+            Player::Engine => ENGINE_NAME,
+            Player::Random => "Random mover",
+        }
+    }
+}
+
+struct Game {
+    board: Board,
+    // indexed by PieceColor::index
+    players: [Player; 2],
+    limits: SearchLimits,
+    // shared with the thread of the search in progress
+    tt: Arc<TranspositionTable>,
+    // set to stop the search in progress, if any
+    search_stop: Option<Arc<AtomicBool>>,
+    // tags of the game loaded with --pgn, kept when the game is exported
+    tags: Vec<(String, String)>,
+    selected: Option<(u8, u8)>,
+    over: bool,
+    // None when the game is played without time control
+    clock: Option<ChessClock>,
+}
+
+#[derive(Clone)]
+struct Gui {
+    game: Rc<RefCell<Game>>,
+    grid: Grid,
+    window: ApplicationWindow,
+    // indexed by PieceColor::index, present when the game has a clock
+    clock_labels: Option<[gtk::Label; 2]>,
+}
+
+impl Gui {
+    /**
+     * Draw the pieces, the selected square and the legal targets of the selected piece.
+     */
+    fn refresh(&self) {
+        let game = self.game.borrow();
+        game.board.apply_to_grid(&self.grid);
+        let targets = game.selected.map_or(Vec::new(), |from| game.board.legal_targets(from));
+        for row in 0..BOARD_SIZE {
+            for col in 0..BOARD_SIZE {
+                if let Some(widget) = self.grid.child_at(col as i32, row as i32) {
+                    let square = (row as u8, col as u8);
+                    set_css_class(&widget, "selected", game.selected == Some(square));
+                    set_css_class(&widget, "target", targets.contains(&square));
+                }
+            }
+        }
+    }
+
+    fn refresh_clocks(&self) {
+        let Some(labels) = &self.clock_labels else {
+            return;
+        };
+        let game = self.game.borrow();
+        let Some(clock) = &game.clock else {
+            return;
+        };
+        for color in [PieceColor::White, PieceColor::Black] {
+            let label = &labels[color.index()];
+            label.set_text(&format_time(clock.remaining(color)));
+            set_css_class(label.upcast_ref(), "clock-running", clock.running() == Some(color));
+            set_css_class(label.upcast_ref(), "clock-flagged", clock.flagged(color));
+        }
+    }
+
+    /**
+     * Redraw the clocks and end the game if the side to move ran out of time. Runs every CLOCK_TICK until the game is over.
+     */
+    fn tick(&self) -> glib::ControlFlow {
+        self.refresh_clocks();
+        let flagged = {
+            let game = self.game.borrow();
+            if game.over {
+                return glib::ControlFlow::Break;
+            }
+            let turn = game.board.side_to_move();
+            game.clock.as_ref().is_some_and(|clock| clock.flagged(turn)).then_some(turn)
+        };
+        match flagged {
+            Some(color) => {
+                let outcome = self.game.borrow().board.outcome_on_time(color);
+                self.game_over(outcome);
+                glib::ControlFlow::Break
+            },
+            None => glib::ControlFlow::Continue,
+        }
+    }
+
+    /**
+     * Press the clock of the side which just moved. A move played after its time ran out is taken back and the game is lost on time instead.
+     */
+    fn press_clock(&self) -> Option<GameOutcome> {
+        let mut game = self.game.borrow_mut();
+        if game.clock.as_mut().is_none_or(|clock| clock.press()) {
+            return None;
+        }
+        game.board.rollback_move();
+        let color = game.board.side_to_move();
+        Some(game.board.outcome_on_time(color))
+    }
+
+    /**
+     * Clicking one of our pieces selects it, clicking one of its targets plays the move and anything else clears the selection.
+     */
+    fn on_square_clicked(&self, square: (u8, u8)) {
+        let mut game = self.game.borrow_mut();
+        let turn = game.board.side_to_move();
+        if game.over || game.players[turn.index()] != Player::Human {
+            return;
+        }
+        match game.selected.take() {
+            Some(from) if game.board.legal_targets(from).contains(&square) => {
+                let promotion = game.board.is_promotion(from, square);
+                drop(game);
+                if promotion {
+                    self.refresh();
+                    self.choose_promotion(from, square);
+                } else {
+                    self.play(from, square, None);
+                }
+            },
+            selected => {
+                if selected != Some(square) && game.board.color_at(square) == Some(turn) {
+                    game.selected = Some(square);
+                }
+                drop(game);
+                self.refresh();
+            }
+        }
+    }
+
+    /**
+     * Ask which piece to promote to; closing the dialog cancels the move.
+     */
+    fn choose_promotion(&self, from: (u8, u8), to: (u8, u8)) {
+        let color = self.game.borrow().board.side_to_move();
+        let dialog = gtk::Window::builder()
+            .title("Promote to")
+            .transient_for(&self.window)
+            .modal(true)
+            .resizable(false)
+            .build();
+        let choices = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+        for piece_type in PROMOTION_CHOICES {
+            let button = Button::new();
+            button.add_css_class("square");
+            button.add_css_class("light-square");
+            button.set_size_request(SQUARE_PIXELS as i32, SQUARE_PIXELS as i32);
+            let image_path = format!("images/{}", Piece::new(piece_type, color, to.0, to.1).get_png());
+            button.set_child(Some(&Picture::for_filename(image_path)));
+            let gui = self.clone();
+            let dialog_handle = dialog.clone();
+            button.connect_clicked(move |_| {
+                dialog_handle.close();
+                gui.play(from, to, Some(piece_type));
+            });
+            choices.append(&button);
+        }
+        dialog.set_child(Some(&choices));
+        dialog.present();
+    }
+
+    fn play(&self, from: (u8, u8), to: (u8, u8), promotion: Option<PieceType>) {
+        let outcome = {
+            let mut game = self.game.borrow_mut();
+            let mv = match game.board.find_move(from, to, promotion) {
+                Some(mv) => mv,
+                None => return,
+            };
+            println!("{}", game.board.numbered_san(from, &mv));
+            game.board.play_chosen_move(from, &mv)
+        };
+        self.after_move(outcome);
+    }
+
+    fn after_move(&self, outcome: Option<GameOutcome>) {
+        let outcome = self.press_clock().or(outcome);
+        self.refresh();
+        self.refresh_clocks();
+        match outcome {
+            Some(outcome) => self.game_over(outcome),
+            None => self.schedule_computer_move(),
+        }
+    }
+
+    /**
+     * If the side to move is not played by a human, let the engine (or the random player) reply.
+     */
+    fn schedule_computer_move(&self) {
+        let player = {
+            let game = self.game.borrow();
+            game.players[game.board.side_to_move().index()]
+        };
+        if player == Player::Human {
+            return;
+        }
+        let gui = self.clone();
+        glib::timeout_add_local_once(MOVE_DELAY, move || match player {
+            Player::Random => {
+                let outcome = {
+                    let mut game = gui.game.borrow_mut();
+                    match game.board.random_move() {
+                        Some((row, col, mv)) => {
+                            println!("{}", game.board.numbered_san((row, col), &mv));
+                            game.board.play_chosen_move((row, col), &mv)
+                        },
+                        None => game.board.outcome(),
+                    }
+                };
+                gui.after_move(outcome);
+            },
+            _ => gui.start_search(),
+        });
+    }
+
+    /**
+     * Search the position on a worker thread, so that the window and the clocks stay live while the engine thinks,
+     * then play the move found, unless the game ended in the meantime.
+     */
+    fn start_search(&self) {
+        let (mut board, limits, tt) = {
+            let mut game = self.game.borrow_mut();
+            let stop = Arc::new(AtomicBool::new(false));
+            game.search_stop = Some(stop.clone());
+            let mut limits = SearchLimits { stop: Some(stop), ..game.limits.clone() };
+            if let Some(clock) = &game.clock {
+                limits.time_control = Some(clock.time_control(game.board.side_to_move()));
+            }
+            (game.board.clone(), limits, game.tt.clone())
+        };
+        let mut search = Some(thread::spawn(move || board.search(&limits, &tt, |_| {}).best_move));
+        let gui = self.clone();
+        glib::timeout_add_local(SEARCH_POLL, move || {
+            if search.as_ref().is_some_and(|search| !search.is_finished()) {
+                return glib::ControlFlow::Continue;
+            }
+            let best_move = search.take().and_then(|search| search.join().expect("search thread panicked"));
+            let outcome = {
+                let mut game = gui.game.borrow_mut();
+                game.search_stop = None;
+                if game.over {
+                    return glib::ControlFlow::Break;
+                }
+                match best_move {
+                    Some((row, col, mv)) => {
+                        println!("{}", game.board.numbered_san((row, col), &mv));
+                        game.board.play_chosen_move((row, col), &mv)
+                    },
+                    None => game.board.outcome_after_move(),
+                }
+            };
+            gui.after_move(outcome);
+            glib::ControlFlow::Break
+        });
+    }
+
+    fn game_over(&self, outcome: GameOutcome) {
+        let mut game = self.game.borrow_mut();
+        game.over = true;
+        if let Some(stop) = game.search_stop.take() {
+            stop.store(true, Ordering::Relaxed);
+        }
+        println!("{:?}", outcome);
+        let Game { board, players, tags: loaded_tags, clock, .. } = &mut *game;
+        let date = pgn_date();
+        let mut tags = vec![("Event", "Casual game"), ("Site", TITLE), ("Date", date.as_str()), ("White", players[0].name()), ("Black", players[1].name())];
+        let time_control = clock.as_ref().map(|clock| clock.settings().pgn_tag());
+        if let Some(time_control) = &time_control {
+            tags.push(("TimeControl", time_control.as_str()));
+        }
+        if let GameOutcome::Timeout(_) = outcome {
+            tags.push(("Termination", "time forfeit"));
+        }
+        tags.extend(loaded_tags.iter().map(|(name, value)| (name.as_str(), value.as_str())));
+        println!("{}", board.export_pgn(&tags, Some(&outcome)));
+        if let Some(clock) = clock {
+            clock.stop();
+        }
+        drop(game);
+        self.refresh_clocks();
+        self.window.set_title(Some(&format!("{} - {:?}", TITLE, outcome)));
+    }
+}
+
+fn set_css_class(widget: &gtk::Widget, class: &str, enabled: bool) {
+    if enabled {
+        widget.add_css_class(class);
+    } else {
+        widget.remove_css_class(class);
+    }
+}
+
+fn show_board(start: PgnGame, players: [Player; 2], clock: Option<ClockSettings>, limits: SearchLimits) {
     gtk::init().expect("Failed to initialize GDK");
     // Initialize GTK
     let app = Application::builder()
@@ -27,16 +351,31 @@ fn show_board() {
     provider.load_from_data(include_str!("../styles/board.css"));
     let priority = gtk::STYLE_PROVIDER_PRIORITY_APPLICATION;
     gtk::style_context_add_provider_for_display(&display, &provider, priority);
+    let game = Rc::new(RefCell::new(Game {
+        board: start.board,
+        players,
+        // with a clock, the engine manages its own time
+        limits: SearchLimits {
+            movetime: if clock.is_some() {None} else {Some(THINKING_TIME)},
+            ..limits
+        },
+        tt: Arc::new(TranspositionTable::default()),
+        search_stop: None,
+        tags: start.tags,
+        selected: None,
+        over: false,
+        clock: clock.map(ChessClock::new),
+    }));
     // Connect to activate event
-    app.connect_activate(|app| {
+    app.connect_activate(move |app| {
         // Create a window
         let window = ApplicationWindow::builder()
             .application(app)
-            .title("Chessboard")
+            .title(TITLE)
             .default_width((BOARD_SIZE * SQUARE_PIXELS) as i32)
             .default_height((BOARD_SIZE * SQUARE_PIXELS) as i32)
             .build();
-        
+
         // Create a grid to hold the chessboard squares
         let grid = Grid::new();
         grid.set_row_homogeneous(true);
@@ -44,6 +383,15 @@ fn show_board() {
 
         //gtk::StyleContext::add_provider(&grid.style_context(), &provider, priority);
 
+        // black's clock above the board, white's below, as the board is seen from white's side
+        let clock_labels = clock.map(|_| [gtk::Label::new(None), gtk::Label::new(None)]);
+        let gui = Gui {
+            game: game.clone(),
+            grid: grid.clone(),
+            window: window.clone(),
+            clock_labels: clock_labels.clone(),
+        };
+
         // Create the chessboard squares
         for row in 0..BOARD_SIZE {
             for col in 0..BOARD_SIZE {
@@ -59,34 +407,180 @@ fn show_board() {
                     picture.set_pixbuf(Some(&pixbuf));
                     button.set_child(Some(&picture));
                 }
+                let gui = gui.clone();
+                button.connect_clicked(move |_| gui.on_square_clicked((row as u8, col as u8)));
                 grid.attach(&button, col as i32, row as i32, 1, 1);
             }
         }
-        let mut board = Board::new();
-        board.apply_to_grid(&grid);
+        gui.refresh();
 
-        // Add the grid to the window
-        window.set_child(Some(&grid));
+        // Add the grid to the window, with the clocks next to it
+        match &clock_labels {
+            Some([white, black]) => {
+                let clocks = gtk::Box::new(gtk::Orientation::Vertical, 0);
+                white.add_css_class("clock");
+                black.add_css_class("clock");
+                black.set_vexpand(true);
+                black.set_valign(gtk::Align::Start);
+                white.set_vexpand(true);
+                white.set_valign(gtk::Align::End);
+                clocks.append(black);
+                clocks.append(white);
+                let layout = gtk::Box::new(gtk::Orientation::Horizontal, 0);
+                grid.set_hexpand(true);
+                layout.append(&grid);
+                layout.append(&clocks);
+                window.set_child(Some(&layout));
+            },
+            None => window.set_child(Some(&grid)),
+        }
 
         // Show the window
         window.show();
 
-        // Schedule updates to the UI at regular intervals
-        glib::timeout_add_local(Duration::from_millis(5), move || {
-            if let Some(outcome) = board.play_random_move() {
-                board.apply_to_grid(&grid);
-                println!("{:?}", outcome);
-                return glib::ControlFlow::Break;
-            }
-            board.apply_to_grid(&grid);
-            glib::ControlFlow::Continue
-        });
+        let turn = game.borrow().board.side_to_move();
+        if let Some(clock) = game.borrow_mut().clock.as_mut() {
+            clock.start(turn);
+            let gui = gui.clone();
+            glib::timeout_add_local(CLOCK_TICK, move || gui.tick());
+        }
+        gui.refresh_clocks();
+        gui.schedule_computer_move();
     });
 
-    // Run the application
-    app.run();
+    // Run the application (our own flags are not meant for GTK)
+    app.run_with_args::<&str>(&[]);
+}
+
+/**
+ * The game the GUI starts from: the one stored in the file given with --pgn, or a new game.
+ */
+fn initial_game() -> Result<PgnGame, String> {
+    let args: Vec<String> = std::env::args().collect();
+    match args.iter().position(|arg| arg == "--pgn") {
+        None => Ok(PgnGame { tags: Vec::new(), board: Board::new() }),
+        Some(i) => {
+            let path = args.get(i + 1).ok_or("--pgn expects a file name")?;
+            let pgn = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+            Board::from_pgn(&pgn).map_err(|e| format!("{}: {}", path, e))
+        }
+    }
+}
+
+/**
+ * The time control given with --time, if any.
+ */
+fn clock_settings() -> Result<Option<ClockSettings>, String> {
+    let args: Vec<String> = std::env::args().collect();
+    match args.iter().position(|arg| arg == "--time") {
+        None => Ok(None),
+        Some(i) => {
+            let spec = args.get(i + 1).ok_or("--time expects a time control")?;
+            ClockSettings::parse(spec).map(Some)
+        }
+    }
+}
+
+/**
+ * The opening book given with --book, used up to the move given with --book-depth, if any.
+ */
+fn opening_book() -> Result<Option<Arc<OpeningBook>>, String> {
+    let args: Vec<String> = std::env::args().collect();
+    let depth = match args.iter().position(|arg| arg == "--book-depth") {
+        None => DEFAULT_BOOK_DEPTH,
+        Some(i) => args.get(i + 1).and_then(|depth| depth.parse().ok()).filter(|depth| *depth > 0).ok_or("--book-depth expects a number of moves")?,
+    };
+    match args.iter().position(|arg| arg == "--book") {
+        None => Ok(None),
+        Some(i) => {
+            let path = args.get(i + 1).ok_or("--book expects a file name")?;
+            let book = OpeningBook::open(path, depth).map_err(|e| format!("{}: {}", path, e))?;
+            Ok(Some(Arc::new(book)))
+        }
+    }
+}
+
+/**
+ * The Syzygy tablebases found in the directories given with --syzygy (separated as in PATH), if any.
+ */
+fn tablebase() -> Result<Option<Arc<Tablebase>>, String> {
+    let args: Vec<String> = std::env::args().collect();
+    match args.iter().position(|arg| arg == "--syzygy") {
+        None => Ok(None),
+        Some(i) => {
+            let paths = args.get(i + 1).ok_or("--syzygy expects a directory")?;
+            let tablebase = Tablebase::open(paths);
+            if tablebase.is_empty() {
+                return Err(format!("{}: no tablebases found", paths));
+            }
+            Ok(Some(Arc::new(tablebase)))
+        }
+    }
 }
 
 fn main() {
-    show_board();
-}
\ No newline at end of file
+    let flag = |name: &str| std::env::args().any(|arg| arg == name);
+    let args: Vec<String> = std::env::args().collect();
+    if args.get(1).is_some_and(|command| command == "match") {
+        if let Err(e) = selfplay::run(&args[2..]) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if flag("--uci") {
+        uci::Uci::new().run();
+        return;
+    }
+    let start = match initial_game() {
+        Ok(start) => start,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let book = match opening_book() {
+        Ok(book) => book,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let tablebase = match tablebase() {
+        Ok(tablebase) => tablebase,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    if flag("--cli") {
+        let depth = args.iter().position(|arg| arg == "--depth").and_then(|i| args.get(i + 1)).and_then(|depth| depth.parse().ok());
+        let limits = SearchLimits {
+            depth,
+            movetime: if depth.is_some() {None} else {Some(THINKING_TIME)},
+            book,
+            tablebase,
+            ..Default::default()
+        };
+        let human = if flag("--black") {PieceColor::Black} else {PieceColor::White};
+        cli::Cli::new(start.board, human, limits, !flag("--ascii")).run();
+        return;
+    }
+    let clock = match clock_settings() {
+        Ok(clock) => clock,
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+    };
+    let limits = SearchLimits { book, tablebase, ..Default::default() };
+    if flag("--random") {
+        show_board(start, [Player::Random, Player::Random], clock, limits);
+    } else if flag("--watch") {
+        show_board(start, [Player::Engine, Player::Engine], clock, limits);
+    } else if flag("--black") {
+        show_board(start, [Player::Engine, Player::Human], clock, limits);
+    } else {
+        show_board(start, [Player::Human, Player::Engine], clock, limits);
+    }
+}
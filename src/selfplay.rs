@@ -0,0 +1,276 @@
+use std::time::Duration;
+
+use crate::chess::{result_token, Board, GameOutcome, PieceColor, SearchLimits, TranspositionTable, DEFAULT_HASH_MB};
+
+// openings the games start from when no file is given, as moves from the initial position
+const DEFAULT_OPENINGS: [&str; 14] = [
+    "e4 e5 Nf3 Nc6 Bb5 a6",
+    "e4 e5 Nf3 Nc6 Bc4 Bc5",
+    "e4 e5 Nf3 Nf6",
+    "e4 c5 Nf3 d6 d4 cxd4 Nxd4 Nf6 Nc3",
+    "e4 e6 d4 d5 Nc3 Nf6",
+    "e4 c6 d4 d5 Nc3 dxe4 Nxe4",
+    "e4 d5 exd5 Qxd5 Nc3 Qa5",
+    "d4 d5 c4 e6 Nc3 Nf6",
+    "d4 d5 c4 c6 Nf3 Nf6",
+    "d4 Nf6 c4 g6 Nc3 Bg7 e4 d6",
+    "d4 Nf6 c4 e6 Nc3 Bb4",
+    "d4 f5 g3 Nf6 Bg2 e6",
+    "c4 e5 Nc3 Nf6 g3",
+    "Nf3 d5 g3 Nf6 Bg2 c6",
+];
+const DEFAULT_GAMES: usize = 100;
+// bounds of the SPRT on the Elo difference, and its false positive and false negative rates
+const DEFAULT_ELO0: f64 = 0.0;
+const DEFAULT_ELO1: f64 = 10.0;
+const SPRT_ALPHA: f64 = 0.05;
+const SPRT_BETA: f64 = 0.05;
+// quantile of the normal distribution for 95% error bars
+const Z_95: f64 = 1.96;
+const USAGE: &str = "usage: match [--games N] [--openings FILE] [--sprt ELO0 ELO1] ENGINE_A ENGINE_B
+An engine is a comma-separated list of search limits and options, e.g. depth=4 or movetime=200 or depth=6,movetime=500,hash=64.
+The evaluation terms can be weighted in percent with material, psqt, pawns, mobility, kingsafety and bishoppair, e.g. depth=4,mobility=150.";
+
+/**
+ * One of the two players of a match: the engine with a given set of search limits and its own transposition table.
+ */
+struct Engine {
+    name: String,
+    limits: SearchLimits,
+    tt: TranspositionTable,
+}
+
+impl Engine {
+    fn parse(spec: &str) -> Result<Self, String> {
+        let mut limits = SearchLimits::default();
+        let mut hash = DEFAULT_HASH_MB;
+        for option in spec.split(',') {
+            let (name, value) = option.split_once('=').ok_or_else(|| format!("engine option '{}' is not NAME=VALUE", option))?;
+            let value: u64 = value.parse().map_err(|_| format!("engine option '{}' expects a number", name))?;
+            match name {
+                "depth" => limits.depth = Some(value as u32),
+                "movetime" => limits.movetime = Some(Duration::from_millis(value)),
+                "hash" => hash = value as usize,
+                "material" => limits.eval_weights.material = value as i32,
+                "psqt" => limits.eval_weights.piece_squares = value as i32,
+                "pawns" => limits.eval_weights.pawns = value as i32,
+                "mobility" => limits.eval_weights.mobility = value as i32,
+                "kingsafety" => limits.eval_weights.king_safety = value as i32,
+                "bishoppair" => limits.eval_weights.bishop_pair = value as i32,
+                _ => return Err(format!("unknown engine option '{}'", name)),
+            }
+        }
+        if limits.depth.is_none() && limits.movetime.is_none() {
+            return Err(format!("engine '{}' needs a depth or a movetime", spec));
+        }
+        Ok(Self { name: spec.to_string(), limits, tt: TranspositionTable::new(hash) })
+    }
+}
+
+/**
+ * Results of the first engine against the second.
+ */
+#[derive(Default)]
+struct Tally {
+    wins: u32,
+    draws: u32,
+    losses: u32,
+}
+
+impl Tally {
+    fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games() as f64
+    }
+
+    /**
+     * Variance of the score of a single game.
+     */
+    fn variance(&self) -> f64 {
+        let score = self.score();
+        let games = self.games() as f64;
+        (self.wins as f64 * (1.0 - score).powi(2) + self.draws as f64 * (0.5 - score).powi(2) + self.losses as f64 * score.powi(2)) / games
+    }
+
+    /**
+     * Elo difference and the half-width of its 95% confidence interval.
+     * None with a perfect (or perfectly bad) score, which does not bound the difference.
+     */
+    fn elo(&self) -> Option<(f64, f64)> {
+        let score = self.score();
+        if score <= 0.0 || score >= 1.0 {
+            return None;
+        }
+        let games = self.games() as f64;
+        let margin = Z_95 * (self.variance() / games).sqrt();
+        // the interval stops half a point short of a perfect score, the closest a match of this length can get to one
+        let (lowest, highest) = (0.5 / games, 1.0 - 0.5 / games);
+        let high = elo_difference((score + margin).min(highest));
+        let low = elo_difference((score - margin).max(lowest));
+        Some((elo_difference(score), (high - low) / 2.0))
+    }
+
+    /**
+     * Log-likelihood ratio of "the difference is elo1" over "the difference is elo0", with the normal approximation of the generalized SPRT.
+     * It stays at 0 until the results vary, since a single kind of result says nothing about the variance.
+     */
+    fn llr(&self, elo0: f64, elo1: f64) -> f64 {
+        let variance = self.variance();
+        if variance == 0.0 {
+            return 0.0;
+        }
+        let (score0, score1) = (expected_score(elo0), expected_score(elo1));
+        (score1 - score0) * (2.0 * self.score() - score0 - score1) * self.games() as f64 / (2.0 * variance)
+    }
+}
+
+fn expected_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+fn elo_difference(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+/**
+ * FEN of an opening given either as a FEN (or EPD) record or as moves from the initial position.
+ */
+fn opening_fen(opening: &str) -> Result<String, String> {
+    if let Ok(board) = Board::from_fen_lenient(opening) {
+        return Ok(board.to_fen());
+    }
+    let mut board = Board::new();
+    for notation in opening.split_whitespace() {
+        let (from, mv) = board.parse_move(notation).map_err(|e| format!("opening '{}': {}", opening, e))?;
+        board.play_move(from, &mv);
+    }
+    Ok(board.to_fen())
+}
+
+/**
+ * Play a game between the two engines, white being the first one. Neither remembers anything from the previous games.
+ */
+fn play_game(mut board: Board, white: &mut Engine, black: &mut Engine) -> GameOutcome {
+    white.tt.clear();
+    black.tt.clear();
+    loop {
+        if let Some(outcome) = board.outcome() {
+            return outcome;
+        }
+        let engine = if board.side_to_move() == PieceColor::White {&mut *white} else {&mut *black};
+        let (row, col, mv) = board.search(&engine.limits, &engine.tt, |_| {}).best_move.expect("no move found in a position which is not over");
+        board.play_move((row, col), &mv);
+    }
+}
+
+/**
+ * The "match" subcommand: play games between two engine configurations, alternating colors and going through the openings,
+ * then report the results of the first engine with its Elo difference and the verdict of a sequential probability ratio test.
+ * The match stops early when the test reaches a verdict.
+ */
+pub fn run(args: &[String]) -> Result<(), String> {
+    let mut games = DEFAULT_GAMES;
+    let mut openings: Vec<String> = DEFAULT_OPENINGS.iter().map(|opening| opening.to_string()).collect();
+    let (mut elo0, mut elo1) = (DEFAULT_ELO0, DEFAULT_ELO1);
+    let mut engines = Vec::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = |name: &str| args.next().ok_or_else(|| format!("{} expects a value\n{}", name, USAGE));
+        match arg.as_str() {
+            "--games" => games = value("--games")?.parse().ok().filter(|games| *games > 0).ok_or_else(|| format!("--games expects a positive number\n{}", USAGE))?,
+            "--openings" => {
+                let path = value("--openings")?;
+                let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+                openings = text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')).map(String::from).collect();
+            },
+            "--sprt" => {
+                elo0 = value("--sprt")?.parse().map_err(|_| format!("--sprt expects two numbers\n{}", USAGE))?;
+                elo1 = value("--sprt")?.parse().map_err(|_| format!("--sprt expects two numbers\n{}", USAGE))?;
+            },
+            spec => engines.push(Engine::parse(spec)?),
+        }
+    }
+    let (a, b) = match engines.as_mut_slice() {
+        [a, b] => (a, b),
+        _ => return Err(USAGE.to_string()),
+    };
+    if openings.is_empty() {
+        return Err("no openings to play".to_string());
+    }
+    let fens = openings.iter().map(|opening| opening_fen(opening)).collect::<Result<Vec<String>, String>>()?;
+
+    let lower = (SPRT_BETA / (1.0 - SPRT_ALPHA)).ln();
+    let upper = ((1.0 - SPRT_BETA) / SPRT_ALPHA).ln();
+    let mut tally = Tally::default();
+    for game in 0..games {
+        // every opening is played twice in a row, once with each engine as white
+        let board = Board::from_fen(&fens[game / 2 % fens.len()]).expect("invalid opening position");
+        let a_is_white = game % 2 == 0;
+        let (white, black) = if a_is_white {(&mut *a, &mut *b)} else {(&mut *b, &mut *a)};
+        let outcome = play_game(board, white, black);
+        match (&outcome, a_is_white) {
+            (GameOutcome::Draw(_), _) => tally.draws += 1,
+            (GameOutcome::Checkmate(PieceColor::Black) | GameOutcome::Timeout(PieceColor::Black), true)
+            | (GameOutcome::Checkmate(PieceColor::White) | GameOutcome::Timeout(PieceColor::White), false) => tally.wins += 1,
+            (GameOutcome::Checkmate(_) | GameOutcome::Timeout(_), _) => tally.losses += 1,
+        }
+        println!("Game {} of {}: {} - {}: {} ({})", game + 1, games, white.name, black.name, result_token(Some(&outcome)), outcome);
+        println!("Score of {} vs {}: {} - {} - {} [{:.3}] {}", a.name, b.name, tally.wins, tally.losses, tally.draws, tally.score(), tally.games());
+        let llr = tally.llr(elo0, elo1);
+        if llr <= lower || llr >= upper {
+            break;
+        }
+    }
+
+    let llr = tally.llr(elo0, elo1);
+    println!();
+    println!("{} vs {}: {} wins, {} draws, {} losses", a.name, b.name, tally.wins, tally.draws, tally.losses);
+    match tally.elo() {
+        Some((elo, margin)) => println!("Elo difference: {:+.1} +/- {:.1}", elo, margin),
+        None => println!("Elo difference: unknown, {} scored {:.0}% of the points", a.name, tally.score() * 100.0),
+    }
+    println!("SPRT: elo0 {} elo1 {} alpha {} beta {}, LLR {:.2} ({:.2}, {:.2})", elo0, elo1, SPRT_ALPHA, SPRT_BETA, llr, lower, upper);
+    if llr >= upper {
+        println!("H1 accepted: {} is stronger", a.name);
+    } else if llr <= lower {
+        println!("H0 accepted: {} is not stronger", a.name);
+    } else {
+        println!("Inconclusive: more games are needed");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{run, Engine, Tally};
+
+    #[test]
+    fn engine_options() {
+        let engine = Engine::parse("depth=3,mobility=150,kingsafety=0").unwrap();
+        assert_eq!(engine.limits.depth, Some(3));
+        assert_eq!((engine.limits.eval_weights.mobility, engine.limits.eval_weights.king_safety, engine.limits.eval_weights.material), (150, 0, 100));
+        assert!(Engine::parse("mobility=150").is_err());
+        assert!(Engine::parse("depth=3,speed=2").is_err());
+    }
+
+    #[test]
+    fn zero_games_are_rejected() {
+        let args: Vec<String> = ["--games", "0", "depth=1", "depth=1"].iter().map(|arg| arg.to_string()).collect();
+        assert!(run(&args).is_err());
+    }
+
+    #[test]
+    fn elo_of_perfect_and_even_scores() {
+        assert!(Tally { wins: 10, draws: 0, losses: 0 }.elo().is_none());
+        assert!(Tally { wins: 0, draws: 0, losses: 7 }.elo().is_none());
+        let (elo, margin) = Tally { wins: 5, draws: 10, losses: 5 }.elo().unwrap();
+        assert_eq!(elo, 0.0);
+        assert!(margin > 0.0 && margin.is_finite());
+        // the interval reaches a perfect score, but its width stays finite
+        let (elo, margin) = Tally { wins: 2, draws: 0, losses: 1 }.elo().unwrap();
+        assert!(elo > 0.0 && margin.is_finite());
+    }
+}
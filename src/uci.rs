@@ -0,0 +1,530 @@
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::chess::{Board, MoveSource, OpeningBook, PieceColor, SearchLimits, SearchResult, Tablebase, TimeControl, TranspositionTable};
+use crate::chess::{DEFAULT_BOOK_DEPTH, DEFAULT_HASH_MB, ENGINE_NAME, MAX_BOOK_DEPTH, MAX_HASH_MB, MAX_THREADS};
+
+const ENGINE_AUTHOR: &str = "Andrea Leone";
+// perft positions and depths timed by "bench": the Chess Programming Wiki positions and a few en passant edge cases
+const BENCH_POSITIONS: [(&str, u32); 10] = [
+    ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 5),
+    ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 4),
+    ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 5),
+    ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 4),
+    ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 4),
+    ("r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10", 4),
+    ("3k4/3p4/8/K1P4r/8/8/8/8 b - - 0 1", 6),
+    ("8/8/4k3/8/2p5/8/B2P2K1/8 w - - 0 1", 6),
+    ("8/8/1k6/2b5/2pP4/8/5K2/8 b - d3 0 1", 6),
+    ("8/5bk1/8/2Pp4/8/1K6/8/8 w - d6 0 1", 6),
+];
+// depth of the searches timed by "bench"
+const BENCH_SEARCH_DEPTH: u32 = 6;
+
+// positions searched to SMP_DEPTH by "bench" with one thread and with several, to compare nodes per second and time to depth
+const SMP_POSITIONS: [&str; 3] = [
+    "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+    "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+    "r4rk1/1pp1qppp/p1np1n2/2b1p1B1/2B1P1b1/P1NP1N2/1PP1QPPP/R4RK1 w - - 0 10",
+];
+const SMP_DEPTH: u32 = 7;
+
+#[derive(Debug, Default)]
+struct GoOptions {
+    depth: Option<u32>,
+    movetime: Option<u64>,
+    wtime: Option<u64>,
+    btime: Option<u64>,
+    winc: Option<u64>,
+    binc: Option<u64>,
+    movestogo: Option<u32>,
+    infinite: bool,
+    perft: Option<u32>,
+}
+
+impl GoOptions {
+    fn parse(tokens: &[&str]) -> Self {
+        let mut options = GoOptions::default();
+        let mut i = 0;
+        while i < tokens.len() {
+            let value = tokens.get(i + 1).and_then(|v| v.parse::<u64>().ok());
+            match tokens[i] {
+                "depth" => options.depth = value.map(|v| v as u32),
+                "movetime" => options.movetime = value,
+                "wtime" => options.wtime = value,
+                "btime" => options.btime = value,
+                "winc" => options.winc = value,
+                "binc" => options.binc = value,
+                "movestogo" => options.movestogo = value.map(|v| v as u32),
+                "perft" => options.perft = value.map(|v| v as u32),
+                "infinite" => {
+                    options.infinite = true;
+                    i += 1;
+                    continue;
+                },
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            }
+            // a missing value must not swallow the next option
+            i += if value.is_some() {2} else {1};
+        }
+        options
+    }
+
+    fn limits(&self, turn: PieceColor, stop: Arc<AtomicBool>) -> SearchLimits {
+        let (time, increment) = match turn {
+            PieceColor::White => (self.wtime, self.winc.unwrap_or(0)),
+            PieceColor::Black => (self.btime, self.binc.unwrap_or(0)),
+        };
+        let time_control = time.filter(|_| !self.infinite).map(|time| TimeControl {
+            remaining: Duration::from_millis(time),
+            increment: Duration::from_millis(increment),
+            moves_to_go: self.movestogo,
+        });
+        SearchLimits {
+            depth: self.depth,
+            movetime: self.movetime.filter(|_| !self.infinite).map(Duration::from_millis),
+            time_control,
+            stop: Some(stop),
+            ..Default::default()
+        }
+    }
+}
+
+struct Search {
+    stop: Arc<AtomicBool>,
+    handle: JoinHandle<(Board, TranspositionTable)>,
+}
+
+pub struct Uci {
+    board: Option<Board>,
+    // moved to the search thread together with the board while searching
+    tt: Option<TranspositionTable>,
+    search: Option<Search>,
+    // the book is only probed with OwnBook set, and reloaded when BookFile or BookDepth change
+    own_book: bool,
+    book_file: String,
+    book_depth: u16,
+    book: Option<Arc<OpeningBook>>,
+    // from SyzygyPath
+    tablebase: Option<Arc<Tablebase>>,
+    threads: usize,
+}
+
+impl Uci {
+    pub fn new() -> Self {
+        Self {
+            board: Some(Board::new()),
+            tt: Some(TranspositionTable::default()),
+            search: None,
+            own_book: false,
+            book_file: String::new(),
+            book_depth: DEFAULT_BOOK_DEPTH,
+            book: None,
+            tablebase: None,
+            threads: 1,
+        }
+    }
+
+    pub fn run(&mut self) {
+        let stdin = io::stdin();
+        for line in stdin.lock().lines() {
+            let line = match line {
+                Ok(line) => line,
+                Err(_) => break,
+            };
+            if !self.handle_command(&line) {
+                break;
+            }
+        }
+        self.stop_search();
+    }
+
+    /**
+     * Handle a single line coming from the GUI, returning false when the engine should quit.
+     */
+    fn handle_command(&mut self, line: &str) -> bool {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        match tokens.first() {
+            Some(&"uci") => {
+                send(&format!("id name {}", ENGINE_NAME));
+                send(&format!("id author {}", ENGINE_AUTHOR));
+                send(&format!("option name Hash type spin default {} min 1 max {}", DEFAULT_HASH_MB, MAX_HASH_MB));
+                send(&format!("option name Threads type spin default 1 min 1 max {}", MAX_THREADS));
+                send("option name OwnBook type check default false");
+                send("option name BookFile type string default <empty>");
+                send(&format!("option name BookDepth type spin default {} min 1 max {}", DEFAULT_BOOK_DEPTH, MAX_BOOK_DEPTH));
+                send("option name SyzygyPath type string default <empty>");
+                send("uciok");
+            },
+            Some(&"isready") => send("readyok"),
+            Some(&"ucinewgame") => {
+                self.stop_search();
+                self.board = Some(Board::new());
+                if let Some(tt) = self.tt.as_mut() {
+                    tt.clear();
+                }
+            },
+            Some(&"setoption") => {
+                self.stop_search();
+                if let Err(e) = self.set_option(&tokens[1..]) {
+                    send(&format!("info string {}", e));
+                }
+            },
+            Some(&"position") => {
+                self.stop_search();
+                match Uci::parse_position(&tokens[1..]) {
+                    Ok(board) => self.board = Some(board),
+                    Err(e) => send(&format!("info string {}", e)),
+                }
+            },
+            Some(&"go") => {
+                self.stop_search();
+                let options = GoOptions::parse(&tokens[1..]);
+                match options.perft {
+                    Some(depth) => self.perft(depth),
+                    None => self.start_search(options),
+                }
+            },
+            Some(&"bench") => {
+                self.stop_search();
+                bench(self.threads);
+            },
+            Some(&"stop") => self.stop_search(),
+            Some(&"quit") => return false,
+            Some(other) => send(&format!("info string unknown command '{}'", other)),
+            None => {},
+        }
+        true
+    }
+
+    /**
+     * Handle "setoption name NAME value VALUE".
+     */
+    fn set_option(&mut self, tokens: &[&str]) -> Result<(), String> {
+        let value_idx = tokens.iter().position(|t| *t == "value").unwrap_or(tokens.len());
+        let name = tokens.get(1..value_idx).unwrap_or_default().join(" ");
+        let value = tokens.get(value_idx + 1..).unwrap_or_default().join(" ");
+        match name.to_lowercase().as_str() {
+            "hash" => {
+                let megabytes = value.parse().map_err(|_| format!("setoption: invalid Hash value '{}'", value))?;
+                if let Some(tt) = self.tt.as_mut() {
+                    tt.resize(megabytes);
+                }
+                Ok(())
+            },
+            "threads" => {
+                self.threads = value.parse().ok()
+                    .filter(|threads| (1..=MAX_THREADS).contains(threads))
+                    .ok_or_else(|| format!("setoption: invalid Threads value '{}'", value))?;
+                Ok(())
+            },
+            "ownbook" => {
+                self.own_book = value.parse().map_err(|_| format!("setoption: invalid OwnBook value '{}'", value))?;
+                Ok(())
+            },
+            "bookfile" => {
+                self.book_file = if value == "<empty>" {String::new()} else {value};
+                self.load_book()
+            },
+            "bookdepth" => {
+                self.book_depth = value.parse().ok()
+                    .filter(|depth| (1..=MAX_BOOK_DEPTH).contains(depth))
+                    .ok_or_else(|| format!("setoption: invalid BookDepth value '{}'", value))?;
+                self.load_book()
+            },
+            "syzygypath" => {
+                self.tablebase = None;
+                if value.is_empty() || value == "<empty>" {
+                    return Ok(());
+                }
+                let tablebase = Tablebase::open(&value);
+                if tablebase.is_empty() {
+                    return Err(format!("setoption: no tablebases found in '{}'", value));
+                }
+                send(&format!("info string found {} tablebases with up to {} pieces", tablebase.len(), tablebase.max_pieces()));
+                self.tablebase = Some(Arc::new(tablebase));
+                Ok(())
+            },
+            _ => Err(format!("setoption: unknown option '{}'", name)),
+        }
+    }
+
+    /**
+     * Read the book of BookFile, or drop the current one if none is set.
+     */
+    fn load_book(&mut self) -> Result<(), String> {
+        self.book = None;
+        if self.book_file.is_empty() {
+            return Ok(());
+        }
+        let book = OpeningBook::open(&self.book_file, self.book_depth).map_err(|e| format!("setoption: {}: {}", self.book_file, e))?;
+        self.book = Some(Arc::new(book));
+        Ok(())
+    }
+
+    fn parse_position(tokens: &[&str]) -> Result<Board, String> {
+        let moves_idx = tokens.iter().position(|t| *t == "moves").unwrap_or(tokens.len());
+        let mut board = match tokens.first() {
+            Some(&"startpos") => Board::new(),
+            Some(&"fen") => Board::from_fen_lenient(&tokens[1..moves_idx].join(" ")).map_err(|e| e.to_string())?,
+            _ => return Err("position: expected 'startpos' or 'fen'".to_string()),
+        };
+        for notation in tokens.iter().skip(moves_idx + 1) {
+            let (from, mv) = board.parse_move(notation).map_err(|e| format!("position: {}", e))?;
+            board.play_move(from, &mv);
+        }
+        Ok(board)
+    }
+
+    fn perft(&mut self, depth: u32) {
+        if let Some(board) = self.board.as_mut() {
+            let start = Instant::now();
+            let divide = board.divide(depth);
+            for (notation, nodes) in divide.iter() {
+                send(&format!("{}: {}", notation, nodes));
+            }
+            let nodes: u64 = divide.iter().map(|(_, nodes)| nodes).sum();
+            send("");
+            send(&format!("Nodes searched: {} ({} ms)", nodes, start.elapsed().as_millis()));
+        }
+    }
+
+    fn start_search(&mut self, options: GoOptions) {
+        let (board, tt) = match (self.board.take(), self.tt.take()) {
+            (Some(board), Some(tt)) => (board, tt),
+            (board, tt) => {
+                self.board = board;
+                self.tt = tt;
+                return;
+            }
+        };
+        let stop = Arc::new(AtomicBool::new(false));
+        let mut limits = options.limits(board.side_to_move(), stop.clone());
+        if self.own_book {
+            limits.book = self.book.clone();
+        }
+        limits.tablebase = self.tablebase.clone();
+        limits.threads = self.threads;
+        let infinite = options.infinite;
+        let handle = thread::spawn(move || think(board, tt, limits, infinite));
+        self.search = Some(Search { stop, handle });
+    }
+
+    fn stop_search(&mut self) {
+        if let Some(search) = self.search.take() {
+            search.stop.store(true, Ordering::Relaxed);
+            let (board, tt) = search.handle.join().expect("search thread panicked");
+            self.board = Some(board);
+            self.tt = Some(tt);
+        }
+    }
+}
+
+/**
+ * Time perft over BENCH_POSITIONS, to measure the speed of move generation and make/unmake.
+ * Then time a search of the same positions to BENCH_SEARCH_DEPTH.
+ * Finally search SMP_POSITIONS with one thread and with threads (or as many as the machine has, if only one is set) to measure the speedup.
+ */
+fn bench(threads: usize) {
+    let start = Instant::now();
+    let mut total = 0;
+    for (fen, depth) in BENCH_POSITIONS {
+        let mut board = Board::from_fen(fen).expect("invalid bench position");
+        let nodes = board.perft(depth);
+        send(&format!("{} depth {}: {}", fen, depth, nodes));
+        total += nodes;
+    }
+    let millis = start.elapsed().as_millis() as u64;
+    send("");
+    send(&format!("Nodes searched: {} ({} ms, {} nps)", total, millis, total * 1000 / millis.max(1)));
+
+    send("");
+    let start = Instant::now();
+    let mut total = 0;
+    for (fen, _) in BENCH_POSITIONS {
+        let mut board = Board::from_fen(fen).expect("invalid bench position");
+        let result = board.search(&SearchLimits { depth: Some(BENCH_SEARCH_DEPTH), ..Default::default() }, &TranspositionTable::default(), |_| {});
+        let best = result.best_move.map_or("0000".to_string(), |(row, col, mv)| mv.to_uci((row, col)));
+        send(&format!("{} depth {}: {} nodes {}", fen, BENCH_SEARCH_DEPTH, best, result.nodes));
+        total += result.nodes;
+    }
+    let millis = start.elapsed().as_millis() as u64;
+    send("");
+    send(&format!("Nodes searched: {} ({} ms, {} nps)", total, millis, total * 1000 / millis.max(1)));
+
+    send("");
+    let threads = if threads > 1 {threads} else {thread::available_parallelism().map_or(1, |cores| cores.get()).clamp(2, MAX_THREADS)};
+    // total nodes and milliseconds to reach the depth, with one thread and with threads
+    let mut totals = [(0, 0); 2];
+    for fen in SMP_POSITIONS {
+        let mut line = format!("{} depth {}:", fen, SMP_DEPTH);
+        for (i, threads) in [1, threads].into_iter().enumerate() {
+            let mut board = Board::from_fen(fen).expect("invalid bench position");
+            let limits = SearchLimits { depth: Some(SMP_DEPTH), threads, ..Default::default() };
+            let start = Instant::now();
+            let result = board.search(&limits, &TranspositionTable::default(), |_| {});
+            let millis = start.elapsed().as_millis() as u64;
+            totals[i].0 += result.nodes;
+            totals[i].1 += millis;
+            let label = if threads == 1 {"1 thread".to_string()} else {format!("{} threads", threads)};
+            line += &format!(" {} {} ms {} nodes {} nps,", label, millis, result.nodes, result.nodes * 1000 / millis.max(1));
+        }
+        send(line.trim_end_matches(','));
+    }
+    let nps = |(nodes, millis): (u64, u64)| nodes as f64 * 1000.0 / millis.max(1) as f64;
+    send(&format!("{} threads: {:.2}x nodes per second, {:.2}x time to depth", threads,
+        nps(totals[1]) / nps(totals[0]), totals[0].1 as f64 / totals[1].1.max(1) as f64));
+}
+
+fn send(message: &str) {
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{}", message);
+    let _ = stdout.flush();
+}
+
+fn info(result: &SearchResult) -> String {
+    let score = match result.mate_in() {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", result.score),
+    };
+    let millis = result.elapsed.as_millis() as u64;
+    let nps = result.nodes * 1000 / millis.max(1);
+    let pv: Vec<String> = result.pv.iter().map(|(row, col, mv)| mv.to_uci((*row, *col))).collect();
+    format!("info depth {} score {} nodes {} nps {} time {} hashfull {} pv {}", result.depth, score, result.nodes, nps, millis, result.hashfull, pv.join(" "))
+}
+
+/**
+ * Search the position and report the outcome as "bestmove".
+ * With "go infinite" the answer is held back until the GUI sends "stop", as required by the protocol.
+ */
+fn think(mut board: Board, tt: TranspositionTable, limits: SearchLimits, infinite: bool) -> (Board, TranspositionTable) {
+    let result = board.search(&limits, &tt, |result| send(&info(result)));
+    match result.source {
+        MoveSource::Book => send("info string book move"),
+        MoveSource::Tablebase => {
+            send(&info(&result));
+            send("info string tablebase move");
+        },
+        MoveSource::Search => {},
+    }
+    if let Some(stop) = &limits.stop {
+        while infinite && !stop.load(Ordering::Relaxed) {
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+    match result.best_move {
+        Some((row, col, mv)) => send(&format!("bestmove {}", mv.to_uci((row, col)))),
+        None => send("bestmove 0000"),
+    }
+    (board, tt)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::AtomicBool;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    use crate::chess::{PieceColor, SearchLimits};
+    use super::{GoOptions, Uci};
+
+    fn limits(command: &str, turn: PieceColor) -> SearchLimits {
+        let tokens: Vec<&str> = command.split_whitespace().collect();
+        GoOptions::parse(&tokens).limits(turn, Arc::new(AtomicBool::new(false)))
+    }
+
+    #[test]
+    fn go_options() {
+        let white = limits("wtime 60000 btime 30000 winc 1000 binc 500 movestogo 20", PieceColor::White);
+        let time_control = white.time_control.unwrap();
+        assert_eq!((time_control.remaining, time_control.increment, time_control.moves_to_go), (Duration::from_secs(60), Duration::from_secs(1), Some(20)));
+        let black = limits("wtime 60000 btime 30000 winc 1000 binc 500", PieceColor::Black);
+        let time_control = black.time_control.unwrap();
+        assert_eq!((time_control.remaining, time_control.increment, time_control.moves_to_go), (Duration::from_secs(30), Duration::from_millis(500), None));
+
+        let depth = limits("depth 7 movetime 2500", PieceColor::White);
+        assert_eq!((depth.depth, depth.movetime, depth.time_control.is_none()), (Some(7), Some(Duration::from_millis(2500)), true));
+
+        // infinite ignores the clock, until "stop"
+        let infinite = limits("infinite wtime 60000 btime 60000 movetime 1000", PieceColor::White);
+        assert!(infinite.time_control.is_none() && infinite.movetime.is_none() && infinite.stop.is_some());
+        assert!(GoOptions::parse(&["wtime", "1000", "infinite"]).infinite);
+    }
+
+    #[test]
+    fn go_options_with_missing_values() {
+        let options = GoOptions::parse(&["depth", "wtime", "1000", "movestogo"]);
+        assert_eq!((options.depth, options.wtime, options.movestogo), (None, Some(1000), None));
+        let options = GoOptions::parse(&["movetime", "x", "perft", "3", "foo"]);
+        assert_eq!((options.movetime, options.perft), (None, Some(3)));
+    }
+
+    #[test]
+    fn positions() {
+        for (command, fen) in [
+            ("startpos", "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1"),
+            ("startpos moves e2e4 e7e5 g1f3", "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 1 2"),
+            ("fen 4k3/P7/8/8/8/8/8/4K3 w - - 0 1 moves a7a8q e8d7", "Q7/3k4/8/8/8/8/8/4K3 w - - 1 2"),
+            // EPD-style, without the clocks
+            ("fen 4k3/8/8/8/8/8/8/4K2R w K - moves e1g1", "4k3/8/8/8/8/8/8/5RK1 b - - 1 1"),
+        ] {
+            let tokens: Vec<&str> = command.split_whitespace().collect();
+            assert_eq!(Uci::parse_position(&tokens).map(|board| board.to_fen()), Ok(fen.to_string()), "{}", command);
+        }
+    }
+
+    #[test]
+    fn invalid_positions() {
+        for (command, error) in [
+            ("startpos moves e2e4 e2e4", "position: illegal move 'e2e4'"),
+            ("startpos moves e2e4 xyz", "position: 'xyz' is not a move in UCI or SAN notation"),
+            ("", "position: expected 'startpos' or 'fen'"),
+            ("moves e2e4", "position: expected 'startpos' or 'fen'"),
+        ] {
+            let tokens: Vec<&str> = command.split_whitespace().collect();
+            assert_eq!(Uci::parse_position(&tokens).err().as_deref(), Some(error), "{}", command);
+        }
+        assert!(Uci::parse_position(&["fen", "8/8/8/8/8/8/8/8", "w", "-", "-", "0", "1"]).is_err());
+    }
+
+    #[test]
+    fn options() {
+        let mut uci = Uci::new();
+        let mut set = |command: &str| {
+            let tokens: Vec<&str> = command.split_whitespace().collect();
+            uci.set_option(&tokens)
+        };
+        assert_eq!(set("name Threads value 4"), Ok(()));
+        assert_eq!(set("name ownbook value true"), Ok(()));
+        assert_eq!(set("name Hash value 1"), Ok(()));
+        assert_eq!(set("name BookFile value <empty>"), Ok(()));
+        assert_eq!(set("name SyzygyPath value"), Ok(()));
+        assert_eq!((uci.threads, uci.own_book, uci.book_file.as_str()), (4, true, ""));
+
+        let mut set = |command: &str| {
+            let tokens: Vec<&str> = command.split_whitespace().collect();
+            uci.set_option(&tokens).err()
+        };
+        for (command, error) in [
+            ("name Hash value x", "setoption: invalid Hash value 'x'"),
+            ("name Threads value 0", "setoption: invalid Threads value '0'"),
+            ("name Threads value 257", "setoption: invalid Threads value '257'"),
+            ("name OwnBook value yes", "setoption: invalid OwnBook value 'yes'"),
+            ("name BookDepth value 0", "setoption: invalid BookDepth value '0'"),
+            // names and values of several words
+            ("name Own Book value true", "setoption: unknown option 'Own Book'"),
+            ("name SyzygyPath value /no such/directory", "setoption: no tablebases found in '/no such/directory'"),
+            ("name Contempt", "setoption: unknown option 'Contempt'"),
+        ] {
+            assert_eq!(set(command).as_deref(), Some(error), "{}", command);
+        }
+        assert!(set("name BookFile value /no such/book.bin").is_some_and(|error| error.starts_with("setoption: /no such/book.bin: ")));
+        assert_eq!((uci.threads, uci.own_book), (4, true));
+    }
+}